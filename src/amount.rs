@@ -0,0 +1,281 @@
+/// Largest exponent accepted in scientific notation. Anything beyond this
+/// can't fit in a `u64` of base units anyway.
+const MAX_EXPONENT: usize = 64;
+
+/// Converts a decimal token amount such as `1.5` into base units. Unlike
+/// `spl_token_2022::ui_amount_to_amount` this never goes through `f64`, so
+/// large amounts are either exact or rejected rather than silently rounded.
+///
+/// Spreadsheet exports are tolerated: underscores, thousands separators
+/// (`1,000,000`) and scientific notation (`1.5E+3`) are accepted, as long as
+/// the result is a whole number of base units.
+pub fn ui_amount_to_base_units(ui_amount: &str, decimals: u8) -> Result<u64, String> {
+    let invalid = || format!("Invalid amount {}", ui_amount);
+
+    let normalized = normalize_ui_amount(ui_amount.trim()).ok_or_else(invalid)?;
+    let (whole, fraction) = normalized.split_once('.').unwrap_or((&normalized, ""));
+    if whole.is_empty() && fraction.is_empty()
+        || !whole.chars().all(|c| c.is_ascii_digit())
+        || !fraction.chars().all(|c| c.is_ascii_digit())
+    {
+        return Err(invalid());
+    }
+
+    let fraction = fraction.trim_end_matches('0');
+    if fraction.len() > decimals as usize {
+        return Err(format!(
+            "Amount {} has more than {} decimal places",
+            ui_amount, decimals
+        ));
+    }
+
+    let overflow = || {
+        format!(
+            "Amount {} is too large: it exceeds {} base units",
+            ui_amount,
+            u64::MAX
+        )
+    };
+    let scale = 10u64.checked_pow(decimals as u32).ok_or_else(overflow)?;
+    let whole: u64 = match whole {
+        "" => 0,
+        whole => whole.parse().map_err(|_| overflow())?,
+    };
+    let fraction: u64 = match fraction {
+        "" => 0,
+        fraction => {
+            fraction.parse::<u64>().map_err(|_| invalid())?
+                * 10u64.pow((decimals as usize - fraction.len()) as u32)
+        }
+    };
+
+    whole
+        .checked_mul(scale)
+        .and_then(|base_units| base_units.checked_add(fraction))
+        .ok_or_else(overflow)
+}
+
+/// Rewrites `ui_amount` as plain `digits[.digits]`, removing underscores and
+/// thousands separators and expanding scientific notation. Returns `None` if
+/// the separators are misplaced, e.g. `1,5`, which is more likely a decimal
+/// comma than a thousands separator.
+fn normalize_ui_amount(ui_amount: &str) -> Option<String> {
+    let ui_amount = ui_amount.replace('_', "");
+    let (mantissa, exponent) = match ui_amount.split_once(['e', 'E']) {
+        Some((mantissa, exponent)) => (mantissa, Some(exponent)),
+        None => (ui_amount.as_str(), None),
+    };
+
+    let (whole, fraction) = mantissa.split_once('.').unwrap_or((mantissa, ""));
+    let whole = if whole.contains(',') {
+        let mut groups = whole.split(',');
+        let first = groups.next()?;
+        if first.is_empty() || first.len() > 3 || groups.any(|group| group.len() != 3) {
+            return None;
+        }
+        whole.replace(',', "")
+    } else {
+        whole.to_string()
+    };
+
+    let Some(exponent) = exponent else {
+        return Some(match fraction {
+            "" if !mantissa.contains('.') => whole,
+            fraction => format!("{}.{}", whole, fraction),
+        });
+    };
+
+    if whole.is_empty() && fraction.is_empty()
+        || !whole.chars().all(|c| c.is_ascii_digit())
+        || !fraction.chars().all(|c| c.is_ascii_digit())
+    {
+        return None;
+    }
+    let (negative, magnitude) = match exponent.strip_prefix('-') {
+        Some(magnitude) => (true, magnitude),
+        None => (false, exponent.strip_prefix('+').unwrap_or(exponent)),
+    };
+    if magnitude.is_empty() || !magnitude.chars().all(|c| c.is_ascii_digit()) {
+        return None;
+    }
+    let magnitude: usize = magnitude.parse().ok().filter(|m| *m <= MAX_EXPONENT)?;
+
+    // Shift the decimal point within the mantissa's digits, padding with zeros
+    // where it moves past either end.
+    let digits = format!("{}{}", whole, fraction);
+    let point = if negative {
+        let point = whole.len() as isize - magnitude as isize;
+        if point < 0 {
+            return Some(format!("0.{}{}", "0".repeat(-point as usize), digits));
+        }
+        point as usize
+    } else {
+        whole.len() + magnitude
+    };
+    Some(if point >= digits.len() {
+        format!("{}{}", digits, "0".repeat(point - digits.len()))
+    } else {
+        format!("{}.{}", &digits[..point], &digits[point..])
+    })
+}
+
+/// Formats base units as a decimal token amount, the inverse of
+/// [`ui_amount_to_base_units`].
+pub fn base_units_to_ui_amount(amount: u64, decimals: u8) -> String {
+    let decimals = decimals as usize;
+    if decimals == 0 {
+        return amount.to_string();
+    }
+
+    let digits = format!("{:0>width$}", amount, width = decimals + 1);
+    let (whole, fraction) = digits.split_at(digits.len() - decimals);
+    match fraction.trim_end_matches('0') {
+        "" => whole.to_string(),
+        fraction => format!("{}.{}", whole, fraction),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_whole_and_fractional_amounts() {
+        assert_eq!(ui_amount_to_base_units("1", 9), Ok(1_000_000_000));
+        assert_eq!(ui_amount_to_base_units("1.5", 9), Ok(1_500_000_000));
+        assert_eq!(ui_amount_to_base_units("0.000000001", 9), Ok(1));
+        assert_eq!(ui_amount_to_base_units(".5", 2), Ok(50));
+        assert_eq!(ui_amount_to_base_units("2.", 2), Ok(200));
+        assert_eq!(ui_amount_to_base_units(" 1.50 ", 2), Ok(150));
+        assert_eq!(ui_amount_to_base_units("1.500000000000", 2), Ok(150));
+    }
+
+    #[test]
+    fn rejects_more_decimal_places_than_the_mint_has() {
+        assert!(ui_amount_to_base_units("0.0000000001", 9).is_err());
+        assert!(ui_amount_to_base_units("1.5", 0).is_err());
+    }
+
+    #[test]
+    fn rejects_malformed_amounts() {
+        for amount in ["", ".", "-1", "1.2.3", "abc", "1 000", "0x10", "1e", "e5"] {
+            assert!(
+                ui_amount_to_base_units(amount, 9).is_err(),
+                "{:?} was accepted",
+                amount
+            );
+        }
+    }
+
+    #[test]
+    fn parses_amounts_up_to_u64_max() {
+        assert_eq!(
+            ui_amount_to_base_units("18446744073.709551615", 9),
+            Ok(u64::MAX)
+        );
+        assert_eq!(
+            ui_amount_to_base_units("18446744073709551615", 0),
+            Ok(u64::MAX)
+        );
+        assert_eq!(
+            ui_amount_to_base_units("18446744073.709551614", 9),
+            Ok(u64::MAX - 1)
+        );
+    }
+
+    #[test]
+    fn rejects_amounts_past_u64_max() {
+        let err = ui_amount_to_base_units("18446744073.709551616", 9).unwrap_err();
+        assert!(err.contains("too large"), "{}", err);
+        assert!(ui_amount_to_base_units("18446744074", 9).is_err());
+        assert!(ui_amount_to_base_units("18446744073709551616", 0).is_err());
+        assert!(ui_amount_to_base_units("1", 20).is_err());
+    }
+
+    #[test]
+    fn keeps_large_csv_amounts_exact() {
+        // Past 2^53, where going through an f64 would round them.
+        assert_eq!(
+            ui_amount_to_base_units("18446744073000000", 0),
+            Ok(18_446_744_073_000_000)
+        );
+        assert_eq!(
+            ui_amount_to_base_units("9007199254740993", 0),
+            Ok(9_007_199_254_740_993)
+        );
+        assert_eq!(
+            ui_amount_to_base_units("18446744073.000000001", 9),
+            Ok(18_446_744_073_000_000_001)
+        );
+    }
+
+    #[test]
+    fn accepts_grouped_thousands_separators() {
+        assert_eq!(ui_amount_to_base_units("1,000,000", 0), Ok(1_000_000));
+        assert_eq!(ui_amount_to_base_units("1,000.25", 2), Ok(100_025));
+        assert_eq!(ui_amount_to_base_units("1_000_000", 0), Ok(1_000_000));
+    }
+
+    #[test]
+    fn rejects_misplaced_thousands_separators() {
+        // Most likely a decimal comma, which would be read a thousand times too
+        // big as a separator.
+        for amount in ["1,5", "1,50", "1,0000", ",100", "1000,000", "1,000,00"] {
+            assert!(
+                ui_amount_to_base_units(amount, 9).is_err(),
+                "{:?} was accepted",
+                amount
+            );
+        }
+    }
+
+    #[test]
+    fn expands_scientific_notation() {
+        assert_eq!(ui_amount_to_base_units("1.5E+3", 0), Ok(1500));
+        assert_eq!(ui_amount_to_base_units("1.5e3", 2), Ok(150_000));
+        assert_eq!(ui_amount_to_base_units("1e-3", 9), Ok(1_000_000));
+        assert_eq!(ui_amount_to_base_units("25E-1", 1), Ok(25));
+        assert_eq!(ui_amount_to_base_units("1e0", 0), Ok(1));
+        // Not a whole number of base units.
+        assert!(ui_amount_to_base_units("1e-10", 9).is_err());
+        assert!(ui_amount_to_base_units("1e65", 0).is_err());
+    }
+
+    #[test]
+    fn normalizes_to_plain_decimals() {
+        assert_eq!(normalize_ui_amount("1,234.5").as_deref(), Some("1234.5"));
+        assert_eq!(normalize_ui_amount("1.5E+3").as_deref(), Some("1500"));
+        assert_eq!(normalize_ui_amount("1e-3").as_deref(), Some("0.001"));
+        assert_eq!(normalize_ui_amount("12.34e1").as_deref(), Some("123.4"));
+        assert_eq!(normalize_ui_amount("1,5"), None);
+        assert_eq!(normalize_ui_amount("1e+"), None);
+    }
+
+    #[test]
+    fn formats_base_units() {
+        assert_eq!(base_units_to_ui_amount(1_500_000_000, 9), "1.5");
+        assert_eq!(base_units_to_ui_amount(1, 9), "0.000000001");
+        assert_eq!(base_units_to_ui_amount(0, 9), "0");
+        assert_eq!(base_units_to_ui_amount(42, 0), "42");
+        assert_eq!(
+            base_units_to_ui_amount(u64::MAX, 9),
+            "18446744073.709551615"
+        );
+    }
+
+    #[test]
+    fn round_trips_through_base_units() {
+        for decimals in [0, 2, 6, 9] {
+            for amount in [0, 1, 10, 999, 1_000_000_001, u64::MAX / 3, u64::MAX] {
+                let ui_amount = base_units_to_ui_amount(amount, decimals);
+                assert_eq!(
+                    ui_amount_to_base_units(&ui_amount, decimals),
+                    Ok(amount),
+                    "{} at {} decimals",
+                    ui_amount,
+                    decimals
+                );
+            }
+        }
+    }
+}
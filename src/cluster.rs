@@ -0,0 +1,67 @@
+use {clap::ValueEnum, solana_sdk::signature::Signature};
+
+/// A well-known cluster that `--cluster` expands to the standard endpoint of.
+#[derive(Clone, Copy, Debug, PartialEq, ValueEnum)]
+pub enum Cluster {
+    #[value(name = "mainnet", alias = "mainnet-beta")]
+    Mainnet,
+    Devnet,
+    Testnet,
+    Localhost,
+}
+
+impl Cluster {
+    pub fn rpc_url(&self) -> &'static str {
+        match self {
+            Cluster::Mainnet => "https://api.mainnet-beta.solana.com",
+            Cluster::Devnet => "https://api.devnet.solana.com",
+            Cluster::Testnet => "https://api.testnet.solana.com",
+            Cluster::Localhost => "http://localhost:8899",
+        }
+    }
+}
+
+/// A block explorer that `--explorer` links transactions to.
+#[derive(Clone, Copy, Debug, PartialEq, ValueEnum)]
+pub enum Explorer {
+    /// explorer.solana.com
+    Solana,
+    /// solscan.io
+    Solscan,
+}
+
+impl Explorer {
+    /// A link to `signature` on this explorer, on the cluster that `rpc_url`
+    /// serves. Endpoints that don't name devnet, testnet or a local validator
+    /// are taken for mainnet, as most private RPC URLs are.
+    pub fn transaction_url(&self, signature: &Signature, rpc_url: &str) -> String {
+        let base = match self {
+            Explorer::Solana => "https://explorer.solana.com/tx",
+            Explorer::Solscan => "https://solscan.io/tx",
+        };
+        let url = rpc_url.to_ascii_lowercase();
+        let cluster = if url.contains("devnet") {
+            "?cluster=devnet".to_string()
+        } else if url.contains("testnet") {
+            "?cluster=testnet".to_string()
+        } else if url.contains("localhost") || url.contains("127.0.0.1") {
+            format!("?cluster=custom&customUrl={}", percent_encode(rpc_url))
+        } else {
+            String::new()
+        };
+        format!("{}/{}{}", base, signature, cluster)
+    }
+}
+
+/// Escapes everything but unreserved characters, for a URL query value.
+fn percent_encode(value: &str) -> String {
+    value
+        .bytes()
+        .map(|byte| match byte {
+            b'A'..=b'Z' | b'a'..=b'z' | b'0'..=b'9' | b'-' | b'.' | b'_' | b'~' => {
+                (byte as char).to_string()
+            }
+            byte => format!("%{:02X}", byte),
+        })
+        .collect()
+}
@@ -0,0 +1,167 @@
+use {
+    crate::{amount::base_units_to_ui_amount, recipients::read_recipients_csvs, CompareArgs},
+    csv::Writer,
+    solana_sdk::pubkey::Pubkey,
+    std::{collections::HashMap, error::Error},
+};
+
+/// Header of the diff CSV.
+const DIFF_HEADER: [&str; 5] = [
+    "address",
+    "change",
+    "old_amount",
+    "new_amount",
+    "difference",
+];
+
+/// How a recipient differs between the two lists.
+enum Change {
+    Added(u64),
+    Removed(u64),
+    Changed { old: u64, new: u64 },
+}
+
+impl Change {
+    fn name(&self) -> &'static str {
+        match self {
+            Change::Added(_) => "added",
+            Change::Removed(_) => "removed",
+            Change::Changed { .. } => "changed",
+        }
+    }
+
+    fn amounts(&self) -> (Option<u64>, Option<u64>) {
+        match *self {
+            Change::Added(new) => (None, Some(new)),
+            Change::Removed(old) => (Some(old), None),
+            Change::Changed { old, new } => (Some(old), Some(new)),
+        }
+    }
+
+    /// The new amount less the old, where a missing side counts as 0.
+    fn difference(&self) -> i128 {
+        let (old, new) = self.amounts();
+        new.unwrap_or_default() as i128 - old.unwrap_or_default() as i128
+    }
+}
+
+/// Formats a signed difference in base units, with an explicit `+` for
+/// increases so the direction is never ambiguous.
+fn signed_amount(difference: i128, decimals: u8) -> String {
+    let sign = if difference < 0 { "-" } else { "+" };
+    // Two u64 amounts can't differ by more than a u64.
+    let magnitude = difference.unsigned_abs() as u64;
+    format!("{}{}", sign, base_units_to_ui_amount(magnitude, decimals))
+}
+
+/// Diffs two recipient CSVs, printing a summary and writing one row per
+/// recipient that was added, removed or given a different amount. Recipients
+/// in the old list keep its order, and those only in the new list follow in
+/// theirs.
+pub fn compare_recipients(args: &CompareArgs) -> Result<(), Box<dyn Error>> {
+    let old = read_recipients_csvs(
+        std::slice::from_ref(&args.old_csv_path),
+        args.decimals,
+        None,
+        None,
+        None,
+    )?;
+    let new = read_recipients_csvs(
+        std::slice::from_ref(&args.new_csv_path),
+        args.decimals,
+        None,
+        None,
+        None,
+    )?;
+
+    let new_amounts: HashMap<Pubkey, u64> = new
+        .iter()
+        .map(|recipient| (recipient.pubkey, recipient.amount))
+        .collect();
+    let old_amounts: HashMap<Pubkey, u64> = old
+        .iter()
+        .map(|recipient| (recipient.pubkey, recipient.amount))
+        .collect();
+
+    let mut changes: Vec<(Pubkey, Change)> = Vec::new();
+    for recipient in old.iter() {
+        match new_amounts.get(&recipient.pubkey) {
+            None => changes.push((recipient.pubkey, Change::Removed(recipient.amount))),
+            Some(&amount) if amount != recipient.amount => changes.push((
+                recipient.pubkey,
+                Change::Changed {
+                    old: recipient.amount,
+                    new: amount,
+                },
+            )),
+            Some(_) => {}
+        }
+    }
+    for recipient in new.iter() {
+        if !old_amounts.contains_key(&recipient.pubkey) {
+            changes.push((recipient.pubkey, Change::Added(recipient.amount)));
+        }
+    }
+
+    let mut writer = Writer::from_path(&args.out)
+        .map_err(|err| format!("Could not write {}: {}", args.out, err))?;
+    writer.write_record(DIFF_HEADER)?;
+    let amount_field = |amount: Option<u64>| {
+        amount
+            .map(|amount| base_units_to_ui_amount(amount, args.decimals))
+            .unwrap_or_default()
+    };
+    for (address, change) in changes.iter() {
+        let (old_amount, new_amount) = change.amounts();
+        writer.write_record([
+            address.to_string(),
+            change.name().to_string(),
+            amount_field(old_amount),
+            amount_field(new_amount),
+            signed_amount(change.difference(), args.decimals),
+        ])?;
+    }
+    writer.flush()?;
+
+    let count = |name: &str| {
+        changes
+            .iter()
+            .filter(|(_, change)| change.name() == name)
+            .count()
+    };
+    let old_total: u128 = old.iter().map(|recipient| recipient.amount as u128).sum();
+    let new_total: u128 = new.iter().map(|recipient| recipient.amount as u128).sum();
+    let total = |total: u128| match u64::try_from(total) {
+        Ok(total) => base_units_to_ui_amount(total, args.decimals),
+        Err(_) => format!("{} base units", total),
+    };
+    println!(
+        "{}: {} recipient(s), {} tokens",
+        args.old_csv_path,
+        old.len(),
+        total(old_total)
+    );
+    println!(
+        "{}: {} recipient(s), {} tokens",
+        args.new_csv_path,
+        new.len(),
+        total(new_total)
+    );
+    println!(
+        "Added {}, removed {}, amount changed {}, unchanged {}",
+        count("added"),
+        count("removed"),
+        count("changed"),
+        old.len() - count("removed") - count("changed")
+    );
+    let net = new_total as i128 - old_total as i128;
+    println!(
+        "Net change: {} tokens",
+        match u64::try_from(net.unsigned_abs()) {
+            Ok(_) => signed_amount(net, args.decimals),
+            Err(_) => format!("{} base units", net),
+        }
+    );
+    println!("Wrote {} change(s) to {}", changes.len(), args.out);
+    Ok(())
+}
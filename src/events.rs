@@ -0,0 +1,101 @@
+use {
+    clap::ValueEnum,
+    serde::Serialize,
+    std::{
+        error::Error,
+        io::{stdout, Write},
+    },
+};
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, ValueEnum)]
+pub enum OutputFormat {
+    /// Human-readable progress and summaries.
+    #[default]
+    Text,
+    /// One JSON event per line on stdout, written as each transaction settles.
+    Ndjson,
+}
+
+/// A line of `--output-format ndjson` output.
+#[derive(Serialize)]
+#[serde(tag = "event", rename_all = "snake_case")]
+pub enum Event {
+    Confirmed {
+        index: usize,
+        batch_id: String,
+        signature: String,
+        recipients: Vec<String>,
+    },
+    Failed {
+        index: usize,
+        batch_id: String,
+        signature: String,
+        error: String,
+        recipients: Vec<String>,
+    },
+    /// A transaction broadcast with `--no-confirm`, whose outcome is never
+    /// checked.
+    Sent {
+        index: usize,
+        batch_id: String,
+        signature: String,
+        recipients: Vec<String>,
+    },
+    /// A transaction reported failed turned out to be in this state when
+    /// its status was checked at the end of the run.
+    Reconciled {
+        index: usize,
+        batch_id: String,
+        signature: String,
+        outcome: Outcome,
+        recipients: Vec<String>,
+    },
+    /// `--stop-on-insufficient-balance` stopped before the recipient at
+    /// `index` in the recipients list, which the source can't cover. They and
+    /// everyone after them, `cut_off` in all, go to the remaining CSV.
+    CutOff {
+        index: usize,
+        recipient: String,
+        amount: String,
+        cut_off: usize,
+    },
+    /// `--preflight-only` found every transaction ready to send; nothing was
+    /// sent.
+    PreflightPassed {
+        transfers: usize,
+        transactions: usize,
+    },
+    /// `--only-missing-atas` is done, having created `created` ATAs in
+    /// `transactions` confirmed transactions. Each ATA transaction that
+    /// failed, `failed` in all, was reported by a `Failed` event before it.
+    AtasCreated {
+        created: usize,
+        transactions: usize,
+        failed: usize,
+    },
+    Complete {
+        confirmed: usize,
+        failed: usize,
+        /// Sent transactions whose outcome was still unknown at the end.
+        unknown: usize,
+    },
+}
+
+/// What reconciling a transaction found on chain.
+#[derive(Clone, Copy, Debug, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Outcome {
+    Confirmed,
+    Failed,
+    Unknown,
+}
+
+/// Writes `event` as a single line and flushes it straight away, so consumers
+/// see each transaction as soon as it settles rather than when a buffer fills.
+pub fn emit(event: &Event) -> Result<(), Box<dyn Error>> {
+    let mut stdout = stdout().lock();
+    serde_json::to_writer(&mut stdout, event)?;
+    writeln!(stdout)?;
+    stdout.flush()?;
+    Ok(())
+}
@@ -0,0 +1,146 @@
+use {
+    async_trait::async_trait,
+    solana_client::{
+        client_error::{ClientError, ClientErrorKind, Result as ClientResult},
+        nonblocking::rpc_client::RpcClient,
+        rpc_client::RpcClientConfig,
+        rpc_custom_error::JSON_RPC_SERVER_ERROR_NODE_UNHEALTHY,
+        rpc_request::{RpcError, RpcRequest},
+        rpc_sender::{RpcSender, RpcTransportStats},
+    },
+    solana_sdk::commitment_config::CommitmentConfig,
+    std::{
+        sync::atomic::{AtomicU32, Ordering},
+        time::Duration,
+    },
+};
+
+/// Connects to the RPC endpoints in `urls`. A single endpoint gets a plain
+/// client; with more, every request goes through a [`FailoverSender`].
+pub fn rpc_client(urls: &[String], timeout: Duration, commitment: CommitmentConfig) -> RpcClient {
+    match urls {
+        [url] => RpcClient::new_with_timeout_and_commitment(url.clone(), timeout, commitment),
+        _ => RpcClient::new_sender(
+            FailoverSender::new(urls, timeout),
+            RpcClientConfig::with_commitment(commitment),
+        ),
+    }
+}
+
+struct Endpoint {
+    url: String,
+    client: RpcClient,
+    /// Failures since the endpoint last answered, which is how unhealthy it
+    /// is considered.
+    failures: AtomicU32,
+}
+
+/// An RPC transport over several endpoints, so a long run survives one of
+/// them going down. Each request tries the healthiest endpoint first, the
+/// one with the fewest failures since it last answered, and ties go to the
+/// endpoint given first. A request moves on to the next endpoint when one
+/// can't be reached, times out, rate limits or reports itself unhealthy;
+/// any other error is the request's own and is returned as it is.
+pub struct FailoverSender {
+    endpoints: Vec<Endpoint>,
+}
+
+impl FailoverSender {
+    fn new(urls: &[String], timeout: Duration) -> Self {
+        Self {
+            endpoints: urls
+                .iter()
+                .map(|url| Endpoint {
+                    url: url.clone(),
+                    // The commitment is filled in by the outer client.
+                    client: RpcClient::new_with_timeout(url.clone(), timeout),
+                    failures: AtomicU32::new(0),
+                })
+                .collect(),
+        }
+    }
+
+    fn by_health(&self) -> Vec<&Endpoint> {
+        let mut endpoints: Vec<&Endpoint> = self.endpoints.iter().collect();
+        endpoints.sort_by_key(|endpoint| endpoint.failures.load(Ordering::Relaxed));
+        endpoints
+    }
+}
+
+/// Whether `err` says something about the endpoint rather than the request,
+/// so another endpoint may well get a different answer.
+fn is_endpoint_failure(err: &ClientError) -> bool {
+    match err.kind() {
+        ClientErrorKind::Io(_) | ClientErrorKind::Reqwest(_) => true,
+        ClientErrorKind::RpcError(RpcError::RpcResponseError { code, .. }) => {
+            *code == JSON_RPC_SERVER_ERROR_NODE_UNHEALTHY
+        }
+        _ => false,
+    }
+}
+
+#[async_trait]
+impl RpcSender for FailoverSender {
+    async fn send(
+        &self,
+        request: RpcRequest,
+        params: serde_json::Value,
+    ) -> ClientResult<serde_json::Value> {
+        let endpoints = self.by_health();
+        let mut last_err = None;
+        for (index, endpoint) in endpoints.iter().enumerate() {
+            match endpoint
+                .client
+                .send::<serde_json::Value>(request, params.clone())
+                .await
+            {
+                Ok(result) => {
+                    endpoint.failures.store(0, Ordering::Relaxed);
+                    if request == RpcRequest::SendTransaction {
+                        progress!(
+                            "{} sent via {}",
+                            result.as_str().unwrap_or_default(),
+                            endpoint.url
+                        );
+                    }
+                    return Ok(result);
+                }
+                Err(err) if is_endpoint_failure(&err) => {
+                    endpoint.failures.fetch_add(1, Ordering::Relaxed);
+                    if let Some(next) = endpoints.get(index + 1) {
+                        progress!(
+                            "RPC {} failed on {}, trying {} ⚠️: {}",
+                            endpoint.url,
+                            request,
+                            next.url,
+                            err
+                        );
+                    }
+                    last_err = Some(err);
+                }
+                Err(err) => {
+                    // The endpoint answered, so it is healthy.
+                    endpoint.failures.store(0, Ordering::Relaxed);
+                    return Err(err);
+                }
+            }
+        }
+        Err(last_err.expect("there is at least one endpoint"))
+    }
+
+    fn get_transport_stats(&self) -> RpcTransportStats {
+        let mut stats = RpcTransportStats::default();
+        for endpoint in self.endpoints.iter() {
+            let endpoint_stats = endpoint.client.get_transport_stats();
+            stats.request_count += endpoint_stats.request_count;
+            stats.elapsed_time += endpoint_stats.elapsed_time;
+            stats.rate_limited_time += endpoint_stats.rate_limited_time;
+        }
+        stats
+    }
+
+    /// The endpoint the next request will try first.
+    fn url(&self) -> String {
+        self.by_health()[0].url.clone()
+    }
+}
@@ -0,0 +1,322 @@
+use {
+    futures_util::StreamExt,
+    serde::{Deserialize, Serialize},
+    solana_client::{
+        client_error::{ClientError, ClientErrorKind},
+        nonblocking::rpc_client::RpcClient,
+        rpc_request::RpcError,
+    },
+    solana_sdk::{compute_budget, instruction::Instruction, message::Message, pubkey::Pubkey},
+    std::{error::Error, fmt, fs, str::FromStr, time::Duration},
+};
+
+/// How long a fee lookup may take before falling back, so a hanging endpoint
+/// can't stall the airdrop.
+const FEE_LOOKUP_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// JSON-RPC error code for an unknown method.
+const METHOD_NOT_FOUND: i64 = -32601;
+
+/// Fee quotes requested from the RPC at once.
+const FEE_QUOTE_CONCURRENCY: usize = 16;
+
+/// Lamports charged per signature, for estimating fees the RPC can't quote.
+const LAMPORTS_PER_SIGNATURE: u64 = 5000;
+
+/// Compute units the runtime allows each instruction when no limit is set.
+const DEFAULT_INSTRUCTION_COMPUTE_UNITS: u64 = 200_000;
+
+/// The most compute units a transaction may use.
+const MAX_COMPUTE_UNITS: u64 = 1_400_000;
+
+/// Where to look up the compute unit price to pay.
+#[derive(Clone, Debug)]
+pub enum FeeSource {
+    /// Median of the node's `getRecentPrioritizationFees`.
+    Local,
+    /// A JSON endpoint returning a recommended price in microlamports.
+    Url(String),
+}
+
+impl FromStr for FeeSource {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        if s == "local" {
+            Ok(FeeSource::Local)
+        } else if s.starts_with("http://") || s.starts_with("https://") {
+            Ok(FeeSource::Url(s.to_string()))
+        } else {
+            Err(format!("expected `local` or an http(s) URL, found {}", s))
+        }
+    }
+}
+
+impl fmt::Display for FeeSource {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            FeeSource::Local => write!(f, "getRecentPrioritizationFees"),
+            FeeSource::Url(url) => write!(f, "{}", url),
+        }
+    }
+}
+
+/// Compute unit prices for ranges of transactions, read from a
+/// `--fee-schedule` JSON file such as
+/// `[{"first": 1, "last": 100, "microlamports": 1000}, {"first": 101,
+/// "microlamports": 20000}]`. Transactions are numbered from 1 in the order
+/// they are built, and a range without `last` runs to the end.
+#[derive(Clone, Debug, Deserialize, Serialize)]
+#[serde(transparent)]
+pub struct FeeSchedule {
+    ranges: Vec<FeeScheduleRange>,
+}
+
+#[derive(Clone, Debug, Deserialize, Serialize)]
+#[serde(deny_unknown_fields)]
+struct FeeScheduleRange {
+    first: usize,
+    last: Option<usize>,
+    microlamports: u64,
+}
+
+impl FeeScheduleRange {
+    fn contains(&self, number: usize) -> bool {
+        number >= self.first && self.last.is_none_or(|last| number <= last)
+    }
+}
+
+impl FeeSchedule {
+    /// Reads and checks the schedule at `path`. Ranges may not overlap, so
+    /// every transaction has at most one price.
+    pub fn load(path: &str) -> Result<Self, String> {
+        let json = fs::read_to_string(path).map_err(|err| format!("{}: {}", path, err))?;
+        let schedule: Self =
+            serde_json::from_str(&json).map_err(|err| format!("{}: {}", path, err))?;
+
+        let mut ranges: Vec<&FeeScheduleRange> = schedule.ranges.iter().collect();
+        ranges.sort_by_key(|range| range.first);
+        for (index, range) in ranges.iter().enumerate() {
+            if range.first == 0 {
+                return Err(format!("{}: transactions are numbered from 1", path));
+            }
+            if range.last.is_some_and(|last| last < range.first) {
+                return Err(format!(
+                    "{}: the range starting at {} ends before it starts",
+                    path, range.first
+                ));
+            }
+            if let Some(next) = ranges.get(index + 1) {
+                if range.contains(next.first) {
+                    return Err(format!(
+                        "{}: the ranges starting at {} and {} overlap",
+                        path, range.first, next.first
+                    ));
+                }
+            }
+        }
+        Ok(schedule)
+    }
+
+    /// The price for the `number`th transaction, counting from 1, if the
+    /// schedule covers it.
+    pub fn price_for(&self, number: usize) -> Option<u64> {
+        self.ranges
+            .iter()
+            .find(|range| range.contains(number))
+            .map(|range| range.microlamports)
+    }
+
+    /// The highest price in the schedule.
+    pub fn max_price(&self) -> u64 {
+        self.ranges
+            .iter()
+            .map(|range| range.microlamports)
+            .max()
+            .unwrap_or_default()
+    }
+}
+
+/// Whether `instruction` sets the compute unit price.
+pub fn is_compute_unit_price(instruction: &Instruction) -> bool {
+    // SetComputeUnitPrice(u64) is tag 3.
+    instruction.program_id == compute_budget::id() && instruction.data.first() == Some(&3)
+}
+
+/// Looks up the compute unit price from `source`, falling back to the static
+/// `--priority-fee` if the lookup fails for any reason, including endpoints
+/// that don't implement the RPC method or never respond.
+pub async fn resolve_priority_fee(
+    source: &FeeSource,
+    fee_field: Option<&str>,
+    rpc_client: &RpcClient,
+    writable_accounts: &[Pubkey],
+    fallback: u64,
+) -> u64 {
+    let lookup = async {
+        match source {
+            FeeSource::Local => fetch_local_fee(rpc_client, writable_accounts).await,
+            FeeSource::Url(url) => fetch_url_fee(url, fee_field).await,
+        }
+    };
+    let result = match tokio::time::timeout(FEE_LOOKUP_TIMEOUT, lookup).await {
+        Ok(result) => result,
+        Err(_) => Err(format!("no response within {:?}", FEE_LOOKUP_TIMEOUT).into()),
+    };
+
+    match result {
+        Ok(fee) => {
+            progress!(
+                "Using priority fee of {} microlamports from {}",
+                fee,
+                source
+            );
+            fee
+        }
+        Err(err) => {
+            progress!(
+                "Could not fetch priority fee from {} ({}), using {} microlamports ⚠️",
+                source,
+                err,
+                fallback
+            );
+            fallback
+        }
+    }
+}
+
+async fn fetch_local_fee(
+    rpc_client: &RpcClient,
+    writable_accounts: &[Pubkey],
+) -> Result<u64, Box<dyn Error>> {
+    let mut fees: Vec<u64> = rpc_client
+        .get_recent_prioritization_fees(writable_accounts)
+        .await
+        .map_err(|err| -> Box<dyn Error> {
+            if is_method_not_found(&err) {
+                "the RPC endpoint does not support getRecentPrioritizationFees".into()
+            } else {
+                err.into()
+            }
+        })?
+        .iter()
+        .map(|fee| fee.prioritization_fee)
+        .collect();
+    if fees.is_empty() {
+        return Err("no recent prioritization fees reported".into());
+    }
+    fees.sort_unstable();
+    Ok(fees[fees.len() / 2])
+}
+
+/// Whether the RPC node rejected the call because it doesn't implement the
+/// method, as limited and archive endpoints often don't.
+fn is_method_not_found(err: &ClientError) -> bool {
+    match err.kind() {
+        ClientErrorKind::RpcError(RpcError::RpcResponseError { code, message, .. }) => {
+            *code == METHOD_NOT_FOUND || message.to_lowercase().contains("method not found")
+        }
+        _ => false,
+    }
+}
+
+/// GETs `url` and reads the fee from the response: the whole body if it's a
+/// number, otherwise the value at the dot-separated `fee_field` path.
+async fn fetch_url_fee(url: &str, fee_field: Option<&str>) -> Result<u64, Box<dyn Error>> {
+    let body: serde_json::Value = reqwest::get(url).await?.error_for_status()?.json().await?;
+
+    let value = match fee_field {
+        Some(path) => path
+            .split('.')
+            .try_fold(&body, |value, key| value.get(key))
+            .ok_or_else(|| format!("response has no field {}", path))?,
+        None => &body,
+    };
+
+    value
+        .as_f64()
+        .filter(|fee| *fee >= 0.0)
+        .map(|fee| fee.ceil() as u64)
+        .ok_or_else(|| format!("expected a non-negative number, found {}", value).into())
+}
+
+/// The total fee the messages will pay, quoting each one with
+/// `getFeeForMessage` so priority fees are counted exactly. A message the RPC
+/// can't quote, e.g. because it doesn't implement the method, is estimated
+/// from its signatures and compute budget instead, with a warning.
+pub async fn quote_fees(rpc_client: &RpcClient, messages: &[&Message]) -> u64 {
+    let quotes: Vec<Result<u64, ClientError>> = futures_util::stream::iter(messages)
+        .map(|message| rpc_client.get_fee_for_message(*message))
+        .buffered(FEE_QUOTE_CONCURRENCY)
+        .collect()
+        .await;
+
+    let mut total: u64 = 0;
+    let mut estimated = 0;
+    let mut last_error = None;
+    for (message, quote) in messages.iter().zip(quotes) {
+        let fee = match quote {
+            Ok(fee) => fee,
+            Err(err) => {
+                estimated += 1;
+                last_error = Some(err);
+                estimate_fee(message)
+            }
+        };
+        total = total.saturating_add(fee);
+    }
+
+    if let Some(err) = last_error {
+        let reason = if is_method_not_found(&err) {
+            "the RPC endpoint does not support getFeeForMessage".to_string()
+        } else {
+            err.to_string()
+        };
+        progress!(
+            "Could not quote the fee of {} transaction(s) ({}), estimating it from signatures and compute budget ⚠️",
+            estimated,
+            reason
+        );
+    }
+    total
+}
+
+/// Estimates a message's fee from its signature count and the compute unit
+/// limit and price it sets, the way the runtime charges them.
+fn estimate_fee(message: &Message) -> u64 {
+    let mut unit_limit = None;
+    let mut unit_price: u64 = 0;
+    let mut other_instructions: u64 = 0;
+    for instruction in message.instructions.iter() {
+        if message.account_keys[instruction.program_id_index as usize] != compute_budget::id() {
+            other_instructions += 1;
+            continue;
+        }
+        match instruction.data.split_first() {
+            // SetComputeUnitLimit(u32)
+            Some((2, data)) => {
+                unit_limit = data
+                    .get(..4)
+                    .and_then(|bytes| bytes.try_into().ok())
+                    .map(|bytes| u32::from_le_bytes(bytes) as u64)
+            }
+            // SetComputeUnitPrice(u64), in microlamports
+            Some((3, data)) => {
+                unit_price = data
+                    .get(..8)
+                    .and_then(|bytes| bytes.try_into().ok())
+                    .map(u64::from_le_bytes)
+                    .unwrap_or_default()
+            }
+            _ => {}
+        }
+    }
+    let unit_limit = unit_limit
+        .unwrap_or(other_instructions.saturating_mul(DEFAULT_INSTRUCTION_COMPUTE_UNITS))
+        .min(MAX_COMPUTE_UNITS);
+    let priority_fee = (unit_price as u128 * unit_limit as u128).div_ceil(1_000_000) as u64;
+
+    (message.header.num_required_signatures as u64)
+        .saturating_mul(LAMPORTS_PER_SIGNATURE)
+        .saturating_add(priority_fee)
+}
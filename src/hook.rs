@@ -0,0 +1,142 @@
+use {
+    solana_client::nonblocking::rpc_client::RpcClient,
+    solana_sdk::{instruction::AccountMeta, pubkey::Pubkey},
+    spl_tlv_account_resolution::{seeds::Seed, state::ExtraAccountMetaList},
+    spl_token_2022::{
+        extension::{transfer_hook, StateWithExtensions},
+        state::Mint,
+    },
+    spl_transfer_hook_interface::{
+        get_extra_account_metas_address, instruction::ExecuteInstruction,
+    },
+    spl_type_length_value::state::TlvStateBorrowed,
+    std::{error::Error, fmt, str::FromStr},
+};
+
+/// The hook interface whose extra account metas this tool knows how to
+/// resolve.
+const SUPPORTED_INTERFACE: &str = "spl-transfer-hook-interface 0.5 (an ExtraAccountMetaList for \
+                                   the Execute instruction, with fixed, PDA and external PDA \
+                                   accounts seeded by literals, instruction data, account keys or \
+                                   account data)";
+
+/// An account given with `--extra-account`, appended to every transfer for
+/// hooks that need an account their ExtraAccountMetaList can't derive.
+/// Written as `PUBKEY`, or `PUBKEY:w` for a writable account.
+#[derive(Clone, Debug, PartialEq)]
+pub struct ExtraAccount {
+    pub pubkey: Pubkey,
+    pub is_writable: bool,
+}
+
+impl ExtraAccount {
+    pub fn to_account_meta(&self) -> AccountMeta {
+        if self.is_writable {
+            AccountMeta::new(self.pubkey, false)
+        } else {
+            AccountMeta::new_readonly(self.pubkey, false)
+        }
+    }
+}
+
+impl FromStr for ExtraAccount {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (address, is_writable) = match s.split_once(':') {
+            Some((address, "w")) => (address, true),
+            Some((_address, suffix)) => {
+                return Err(format!(
+                    "unknown suffix :{} on {}, expected :w for a writable account",
+                    suffix, s
+                ))
+            }
+            None => (s, false),
+        };
+        let pubkey = Pubkey::from_str(address)
+            .map_err(|_| format!("invalid account address {}", address))?;
+        Ok(ExtraAccount {
+            pubkey,
+            is_writable,
+        })
+    }
+}
+
+impl fmt::Display for ExtraAccount {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        if self.is_writable {
+            write!(f, "{}:w", self.pubkey)
+        } else {
+            write!(f, "{}", self.pubkey)
+        }
+    }
+}
+
+/// Checks that the mint's transfer hook, if it has one, declares its extra
+/// accounts in a layout we can resolve, so an unsupported hook fails up front
+/// instead of as a run of reverted transactions. Returns the hook program id.
+pub async fn check_transfer_hook(
+    rpc_client: &RpcClient,
+    mint: &Pubkey,
+) -> Result<Option<Pubkey>, Box<dyn Error>> {
+    let mint_account = rpc_client.get_account(mint).await?;
+    let mint_state = StateWithExtensions::<Mint>::unpack(&mint_account.data)
+        .map_err(|_| format!("{} is not a mint account", mint))?;
+    let Some(program_id) = transfer_hook::get_program_id(&mint_state) else {
+        return Ok(None);
+    };
+
+    let validation_address = get_extra_account_metas_address(mint, &program_id);
+    let validation_account = rpc_client
+        .get_account_with_commitment(&validation_address, rpc_client.commitment())
+        .await?
+        .value
+        .ok_or_else(|| {
+            format!(
+                "Transfer hook program {} has no ExtraAccountMetaList at {} for this mint",
+                program_id, validation_address
+            )
+        })?;
+    if validation_account.owner != program_id {
+        return Err(format!(
+            "ExtraAccountMetaList {} is owned by {}, not the transfer hook program {}",
+            validation_address, validation_account.owner, program_id
+        )
+        .into());
+    }
+
+    let unsupported = |reason: &str| {
+        format!(
+            "Transfer hook program {} uses an unsupported interface: {}. Supported: {}",
+            program_id, reason, SUPPORTED_INTERFACE
+        )
+    };
+    let tlv_state = TlvStateBorrowed::unpack(&validation_account.data)
+        .map_err(|_| unsupported("the ExtraAccountMetaList is not TLV-encoded"))?;
+    let extra_metas = ExtraAccountMetaList::unpack_with_tlv_state::<ExecuteInstruction>(&tlv_state)
+        .map_err(|_| unsupported("there is no entry for the Execute instruction"))?;
+
+    for (index, meta) in extra_metas.data().iter().enumerate() {
+        let is_supported = match meta.discriminator {
+            0 => true,
+            // 1 is a PDA of the hook program, 128+ a PDA of another account.
+            1 | 128.. => Seed::unpack_address_config(&meta.address_config).is_ok(),
+            _ => false,
+        };
+        if !is_supported {
+            return Err(unsupported(&format!(
+                "extra account {} has an unknown type or seed configuration",
+                index
+            ))
+            .into());
+        }
+    }
+
+    progress!(
+        "Transfer hook: {} ({} extra account(s))",
+        program_id,
+        extra_metas.data().len()
+    );
+
+    Ok(Some(program_id))
+}
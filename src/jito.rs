@@ -0,0 +1,157 @@
+use {
+    crate::sender::TransactionSender,
+    base64::{engine::general_purpose::STANDARD, Engine},
+    rand::seq::SliceRandom,
+    serde_json::{json, Value},
+    solana_client::{
+        client_error::{ClientError, ClientErrorKind},
+        nonblocking::rpc_client::RpcClient,
+        rpc_config::RpcSendTransactionConfig,
+    },
+    solana_sdk::{hash::Hash, pubkey::Pubkey, signature::Signature, transaction::Transaction},
+    std::{error::Error, str::FromStr, time::Duration},
+    tokio::time::Instant,
+};
+
+/// The smallest tip the block engine accepts for a bundle.
+pub const MIN_TIP_LAMPORTS: u64 = 1000;
+
+/// How often a submitted bundle's status is polled.
+const BUNDLE_POLL_INTERVAL: Duration = Duration::from_secs(1);
+
+/// How long to poll a bundle before leaving it to the usual signature
+/// confirmation. Bundles are only tracked by the block engine for a few
+/// minutes, and a blockhash expires in about one.
+const BUNDLE_STATUS_TIMEOUT: Duration = Duration::from_secs(30);
+
+/// A Jito block engine that transactions are submitted to as bundles.
+#[derive(Debug)]
+pub struct JitoClient {
+    bundles_url: String,
+    client: reqwest::Client,
+}
+
+impl JitoClient {
+    /// `url` is the block engine's base URL, e.g.
+    /// `https://mainnet.block-engine.jito.wtf`.
+    pub fn new(url: &str) -> Self {
+        Self {
+            bundles_url: format!("{}/api/v1/bundles", url.trim_end_matches('/')),
+            client: reqwest::Client::new(),
+        }
+    }
+
+    /// Picks one of the block engine's tip accounts at random, as Jito asks,
+    /// so tips don't all contend for the same account.
+    pub async fn tip_account(&self) -> Result<Pubkey, Box<dyn Error>> {
+        let accounts = self.call("getTipAccounts", json!([])).await?;
+        let accounts: Vec<&str> = accounts
+            .as_array()
+            .map(|accounts| accounts.iter().filter_map(Value::as_str).collect())
+            .unwrap_or_default();
+        let account = accounts
+            .choose(&mut rand::thread_rng())
+            .ok_or_else(|| format!("{} returned no tip accounts", self.bundles_url))?;
+        Ok(Pubkey::from_str(account).map_err(|_| {
+            format!(
+                "{} returned an invalid tip account {}",
+                self.bundles_url, account
+            )
+        })?)
+    }
+
+    /// Submits `transaction` as a bundle of its own and returns the bundle id.
+    async fn send_bundle(&self, transaction: &Transaction) -> Result<String, ClientError> {
+        let serialized = bincode::serialize(transaction)
+            .map_err(|err| ClientError::from(ClientErrorKind::Custom(err.to_string())))?;
+        let bundle_id = self
+            .call(
+                "sendBundle",
+                json!([[STANDARD.encode(serialized)], { "encoding": "base64" }]),
+            )
+            .await?;
+        bundle_id.as_str().map(str::to_string).ok_or_else(|| {
+            ClientErrorKind::Custom(format!(
+                "sendBundle returned {} instead of an id",
+                bundle_id
+            ))
+            .into()
+        })
+    }
+
+    /// Polls the bundle until it lands or is dropped. Gives up quietly after
+    /// `BUNDLE_STATUS_TIMEOUT`, since the transaction may still land and the
+    /// caller confirms it by signature either way.
+    async fn wait_for_bundle(&self, bundle_id: &str) -> Result<(), ClientError> {
+        let deadline = Instant::now() + BUNDLE_STATUS_TIMEOUT;
+        while Instant::now() < deadline {
+            tokio::time::sleep(BUNDLE_POLL_INTERVAL).await;
+            let statuses = self
+                .call("getInflightBundleStatuses", json!([[bundle_id]]))
+                .await?;
+            // An unknown bundle has no entry yet.
+            let status = statuses
+                .pointer("/value/0/status")
+                .and_then(Value::as_str)
+                .unwrap_or("Pending");
+            match status {
+                "Landed" => return Ok(()),
+                "Failed" | "Invalid" => {
+                    return Err(ClientErrorKind::Custom(format!(
+                        "Jito bundle {} {}",
+                        bundle_id,
+                        status.to_lowercase()
+                    ))
+                    .into())
+                }
+                _ => {}
+            }
+        }
+        Ok(())
+    }
+
+    /// Makes a JSON-RPC call to the block engine and returns its result.
+    async fn call(&self, method: &str, params: Value) -> Result<Value, ClientError> {
+        let response: Value = self
+            .client
+            .post(&self.bundles_url)
+            .json(&json!({ "jsonrpc": "2.0", "id": 1, "method": method, "params": params }))
+            .send()
+            .await?
+            .error_for_status()?
+            .json()
+            .await?;
+        if let Some(error) = response.get("error") {
+            return Err(ClientErrorKind::Custom(format!(
+                "{} {} failed: {}",
+                self.bundles_url, method, error
+            ))
+            .into());
+        }
+        Ok(response.get("result").cloned().unwrap_or(Value::Null))
+    }
+}
+
+/// Sends each transaction as a single-transaction Jito bundle and waits for
+/// the block engine to report it landed. Blockhashes still come from the
+/// RPC.
+pub struct JitoSender<'a> {
+    pub rpc_client: &'a RpcClient,
+    pub jito: &'a JitoClient,
+}
+
+impl TransactionSender for JitoSender<'_> {
+    async fn send(
+        &self,
+        transaction: &Transaction,
+        _config: RpcSendTransactionConfig,
+    ) -> Result<Signature, ClientError> {
+        let bundle_id = self.jito.send_bundle(transaction).await?;
+        self.jito.wait_for_bundle(&bundle_id).await?;
+        Ok(transaction.signatures[0])
+    }
+
+    async fn get_latest_blockhash(&self) -> Result<Hash, ClientError> {
+        RpcClient::get_latest_blockhash(self.rpc_client).await
+    }
+}
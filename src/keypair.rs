@@ -0,0 +1,45 @@
+use {
+    solana_sdk::{bs58, signature::Keypair},
+    std::{env, error::Error, fs},
+};
+
+/// Prefix for reading the keypair from an environment variable instead of a
+/// file, e.g. `--keypair env:AIRDROP_KEYPAIR`.
+const ENV_PREFIX: &str = "env:";
+
+/// Loads a keypair from a file path or `env:VAR_NAME`. The contents may be
+/// either the JSON byte array written by `solana-keygen` or a base58 secret
+/// key string.
+pub fn load_keypair(source: &str) -> Result<Keypair, Box<dyn Error>> {
+    let (contents, origin) = match source.strip_prefix(ENV_PREFIX) {
+        Some(var_name) => {
+            let contents = env::var(var_name)
+                .map_err(|_| format!("Environment variable {} is not set", var_name))?;
+            (contents, format!("environment variable {}", var_name))
+        }
+        None => {
+            let contents = fs::read_to_string(source)
+                .map_err(|err| format!("Could not read keypair file {}: {}", source, err))?;
+            (contents, format!("keypair file {}", source))
+        }
+    };
+
+    parse_keypair(contents.trim())
+        .map_err(|err| format!("Invalid keypair in {}: {}", origin, err).into())
+}
+
+fn parse_keypair(contents: &str) -> Result<Keypair, String> {
+    let bytes: Vec<u8> = if contents.starts_with('[') {
+        serde_json::from_str(contents).map_err(|err| format!("malformed JSON array: {}", err))?
+    } else {
+        bs58::decode(contents)
+            .into_vec()
+            .map_err(|_| "expected a JSON byte array or a base58 secret key".to_string())?
+    };
+
+    if bytes.len() != 64 {
+        return Err(format!("expected 64 bytes, found {}", bytes.len()));
+    }
+    Keypair::from_bytes(&bytes)
+        .map_err(|_| "the public key half does not match the secret key".to_string())
+}
@@ -0,0 +1,387 @@
+use {
+    crate::{
+        amount::ui_amount_to_base_units,
+        recipients::{is_gzip, open_input},
+        LintArgs, TOKEN_DECIMALS,
+    },
+    csv::{ReaderBuilder, StringRecord, Writer},
+    flate2::{write::GzEncoder, Compression},
+    solana_sdk::{bs58, pubkey::Pubkey},
+    std::{
+        collections::{hash_map::Entry, HashMap},
+        error::Error,
+        fs::File,
+        path::Path,
+        str::FromStr,
+    },
+};
+
+/// Symbols that spreadsheets put in front of or behind amounts.
+const CURRENCY_SYMBOLS: &[char] = &['$', '€', '£', '¥', '₿', '◎'];
+
+/// Characters that look like base58 but aren't in its alphabet, usually the
+/// sign of a mistyped address or one whose case was changed.
+const NON_BASE58: &[char] = &['0', 'O', 'I', 'l'];
+
+/// Header written when the file has none, for the columns `airdrop` reads.
+const DEFAULT_HEADER: [&str; 3] = ["address", "amount", "ata_owner"];
+
+/// A problem found on a row. Fixable issues are corrected by `--fix`; the rest
+/// need the operator to look at them.
+struct Issue {
+    row: usize,
+    message: String,
+    fixable: bool,
+}
+
+/// A row as `--fix` writes it back, or `None` if the fix drops it.
+type FixedRow = Option<Vec<String>>;
+
+/// Reports common problems in a recipients CSV and, with `--fix`, writes a
+/// cleaned copy. Fails if any issue is left unfixed, so it can gate a CI job.
+pub fn lint_recipients(args: &LintArgs) -> Result<(), Box<dyn Error>> {
+    let path = &args.recipients_csv_path;
+    // Rows of the wrong length are reported rather than failing the read.
+    let mut reader = ReaderBuilder::new()
+        .flexible(true)
+        .from_reader(open_input(path)?);
+    let header = reader.headers()?.clone();
+
+    let mut issues: Vec<Issue> = Vec::new();
+    let mut rows: Vec<(usize, StringRecord)> = Vec::new();
+
+    // Without a header the first recipient is silently taken for one.
+    let header = if header
+        .get(0)
+        .is_some_and(|field| parse_address(clean(field)).is_ok())
+    {
+        issues.push(Issue {
+            row: 1,
+            message: "looks like a recipient rather than a header, so it would be skipped"
+                .to_string(),
+            fixable: true,
+        });
+        rows.push((1, header.clone()));
+        let mut names: Vec<String> = DEFAULT_HEADER.iter().map(|name| name.to_string()).collect();
+        names.extend((DEFAULT_HEADER.len()..header.len()).map(|index| format!("column_{}", index)));
+        names.truncate(header.len().max(1));
+        StringRecord::from(names)
+    } else {
+        header
+    };
+    for (index, result) in reader.records().enumerate() {
+        // Row 1 is the header.
+        rows.push((index + 2, result?));
+    }
+
+    let mut seen: HashMap<Pubkey, (usize, Option<u64>)> = HashMap::new();
+    let mut fixed: Vec<FixedRow> = Vec::with_capacity(rows.len());
+    for (row, record) in rows.iter() {
+        fixed.push(lint_row(*row, record, header.len(), &mut seen, &mut issues));
+    }
+
+    let fixable = issues.iter().filter(|issue| issue.fixable).count();
+    let unfixable = issues.len() - fixable;
+    for issue in issues.iter() {
+        let note = match (issue.fixable, args.fix.is_some()) {
+            (true, true) => " (fixed)",
+            (true, false) => " (fixable)",
+            (false, _) => "",
+        };
+        println!("Row {}: {}{}", issue.row, issue.message, note);
+    }
+
+    if let Some(fix_path) = &args.fix {
+        let fix_path = fix_path.clone().unwrap_or_else(|| default_fix_path(path));
+        let written = write_fixed(&fix_path, &header, &fixed)?;
+        println!("Wrote {} row(s) to {}", written, fix_path);
+    }
+
+    if issues.is_empty() {
+        println!("No issues found in {} ({} row(s))", path, rows.len());
+        return Ok(());
+    }
+    if args.fix.is_some() {
+        println!("Fixed {} issue(s)", fixable);
+        if unfixable == 0 {
+            return Ok(());
+        }
+        return Err(format!("{} issue(s) in {} need fixing by hand", unfixable, path).into());
+    }
+    Err(format!(
+        "Found {} issue(s) in {}, {} of them fixable with --fix",
+        issues.len(),
+        path,
+        fixable
+    )
+    .into())
+}
+
+/// Checks one data row, recording its issues, and returns it as `--fix`
+/// writes it.
+fn lint_row(
+    row: usize,
+    record: &StringRecord,
+    columns: usize,
+    seen: &mut HashMap<Pubkey, (usize, Option<u64>)>,
+    issues: &mut Vec<Issue>,
+) -> FixedRow {
+    let mut issue = |message: String, fixable: bool| {
+        issues.push(Issue {
+            row,
+            message,
+            fixable,
+        })
+    };
+
+    if record.iter().all(|field| clean(field).is_empty()) {
+        issue("is empty".to_string(), true);
+        return None;
+    }
+
+    let mut fields: Vec<String> = record.iter().map(str::to_string).collect();
+    if fields.len() < columns {
+        issue(
+            format!(
+                "has {} field(s) but the header has {}",
+                fields.len(),
+                columns
+            ),
+            true,
+        );
+        fields.resize(columns, String::new());
+    } else if fields.len() > columns {
+        if fields[columns..]
+            .iter()
+            .all(|field| clean(field).is_empty())
+        {
+            issue(
+                format!(
+                    "has {} field(s) but the header has {}; the extra ones are empty",
+                    fields.len(),
+                    columns
+                ),
+                true,
+            );
+            fields.truncate(columns);
+        } else {
+            issue(
+                format!(
+                    "has {} field(s) but the header has {}",
+                    fields.len(),
+                    columns
+                ),
+                false,
+            );
+        }
+    }
+
+    let raw_address = fields[0].clone();
+    let address = clean(&raw_address);
+    let pubkey = match parse_address(address) {
+        Ok(pubkey) => pubkey,
+        Err(message) => {
+            issue(message, false);
+            return Some(fields);
+        }
+    };
+    if address != raw_address {
+        issue(
+            format!(
+                "address {:?} has whitespace or quotes around it",
+                raw_address
+            ),
+            true,
+        );
+    }
+    if is_one_case(address) {
+        issue(
+            format!(
+                "address {} is all one case, which a real address almost never is; check that a spreadsheet didn't change it",
+                address
+            ),
+            false,
+        );
+    }
+    fields[0] = pubkey.to_string();
+
+    let mut amount = None;
+    if let Some(raw_amount) = fields.get(1).filter(|field| !clean(field).is_empty()) {
+        match lint_amount(raw_amount) {
+            Ok((value, cleaned, message)) => {
+                amount = Some(value);
+                if let Some(message) = message {
+                    issue(message, true);
+                }
+                fields[1] = cleaned;
+            }
+            Err(message) => issue(message, false),
+        }
+    }
+
+    if let Some(raw_owner) = fields.get(2).filter(|field| !clean(field).is_empty()) {
+        let owner = clean(raw_owner);
+        match parse_address(owner) {
+            Ok(owner_pubkey) => {
+                if owner != raw_owner {
+                    issue(
+                        format!(
+                            "ATA owner {:?} has whitespace or quotes around it",
+                            raw_owner
+                        ),
+                        true,
+                    );
+                }
+                fields[2] = owner_pubkey.to_string();
+            }
+            Err(message) => issue(format!("ATA owner: {}", message), false),
+        }
+    }
+
+    match seen.entry(pubkey) {
+        Entry::Occupied(entry) => {
+            let (first_row, first_amount) = *entry.get();
+            if first_amount == amount {
+                issue(format!("duplicates row {}", first_row), true);
+                return None;
+            }
+            issue(
+                format!(
+                    "lists {} again, with a different amount than row {}; airdrop would keep the first",
+                    pubkey, first_row
+                ),
+                false,
+            );
+        }
+        Entry::Vacant(entry) => {
+            entry.insert((row, amount));
+        }
+    }
+
+    Some(fields)
+}
+
+/// Parses an amount, stripping currency symbols and units if that's what
+/// stops it parsing. Returns its base units, the value to write back, and the
+/// issue found, if any.
+fn lint_amount(raw: &str) -> Result<(u64, String, Option<String>), String> {
+    let trimmed = clean(raw);
+    if let Ok(value) = ui_amount_to_base_units(trimmed, TOKEN_DECIMALS) {
+        let message = (trimmed != raw)
+            .then(|| format!("amount {:?} has whitespace or quotes around it", raw));
+        return Ok((value, trimmed.to_string(), message));
+    }
+
+    // A unit such as `SOL` or `USDC` is letters, but so is the exponent of
+    // `1.5E+3`, so only strip them if the plain parse failed.
+    let stripped = trimmed
+        .trim_matches(|c: char| CURRENCY_SYMBOLS.contains(&c) || c.is_whitespace())
+        .trim_start_matches(|c: char| c.is_alphabetic() || c.is_whitespace())
+        .trim_end_matches(|c: char| c.is_alphabetic() || c.is_whitespace())
+        .trim_matches(|c: char| CURRENCY_SYMBOLS.contains(&c) || c.is_whitespace());
+    match ui_amount_to_base_units(stripped, TOKEN_DECIMALS) {
+        Ok(value) if !stripped.is_empty() => Ok((
+            value,
+            stripped.to_string(),
+            Some(format!(
+                "amount {:?} has a currency symbol or unit; use {}",
+                raw, stripped
+            )),
+        )),
+        _ => Err(match ui_amount_to_base_units(trimmed, TOKEN_DECIMALS) {
+            Err(message) => message,
+            Ok(_) => format!("Invalid amount {}", raw),
+        }),
+    }
+}
+
+/// Parses an address, explaining why it isn't one if it doesn't parse.
+fn parse_address(address: &str) -> Result<Pubkey, String> {
+    if address.is_empty() {
+        return Err("address is missing".to_string());
+    }
+    if let Ok(pubkey) = Pubkey::from_str(address) {
+        return Ok(pubkey);
+    }
+    if address.contains(NON_BASE58) {
+        let hint = if is_one_case(address) {
+            ", probably because its case was changed"
+        } else {
+            ""
+        };
+        return Err(format!(
+            "address {} contains 0, O, I or l, which base58 never uses{}",
+            address, hint
+        ));
+    }
+    match bs58::decode(address).into_vec() {
+        Ok(bytes) => Err(format!(
+            "address {} decodes to {} bytes, not 32",
+            address,
+            bytes.len()
+        )),
+        Err(_) => Err(format!("address {} is not valid base58", address)),
+    }
+}
+
+/// Whether every letter in `address` has the same case, which is what a
+/// spreadsheet's UPPER() or LOWER() leaves behind.
+fn is_one_case(address: &str) -> bool {
+    let mut letters = address.chars().filter(|c| c.is_ascii_alphabetic());
+    letters.clone().all(|c| c.is_ascii_lowercase()) || letters.all(|c| c.is_ascii_uppercase())
+}
+
+/// Trims whitespace, including the non-breaking and zero-width spaces that
+/// spreadsheet exports leave behind, and surrounding quotes.
+fn clean(field: &str) -> &str {
+    field.trim_matches(|c: char| {
+        c.is_whitespace() || matches!(c, '\u{200b}' | '\u{feff}' | '"' | '\'')
+    })
+}
+
+/// `recipients.csv` becomes `recipients.fixed.csv`, next to the original.
+fn default_fix_path(path: &str) -> String {
+    let path = path.strip_suffix(".gz").unwrap_or(path);
+    Path::new(path)
+        .with_extension("fixed.csv")
+        .display()
+        .to_string()
+}
+
+/// Writes the header and every row `--fix` keeps, gzipped if `path` ends in
+/// `.gz`. Returns the number of rows written.
+fn write_fixed(
+    path: &str,
+    header: &StringRecord,
+    rows: &[FixedRow],
+) -> Result<usize, Box<dyn Error>> {
+    let file = File::create(path)?;
+    if is_gzip(path) {
+        let mut writer = Writer::from_writer(GzEncoder::new(file, Compression::default()));
+        let written = write_rows(&mut writer, header, rows)?;
+        writer
+            .into_inner()
+            .map_err(|err| err.into_error())?
+            .finish()?;
+        Ok(written)
+    } else {
+        let mut writer = Writer::from_writer(file);
+        let written = write_rows(&mut writer, header, rows)?;
+        writer.flush()?;
+        Ok(written)
+    }
+}
+
+fn write_rows<W: std::io::Write>(
+    writer: &mut Writer<W>,
+    header: &StringRecord,
+    rows: &[FixedRow],
+) -> Result<usize, Box<dyn Error>> {
+    writer.write_record(header)?;
+    let mut written = 0;
+    for fields in rows.iter().flatten() {
+        writer.write_record(fields)?;
+        written += 1;
+    }
+    Ok(written)
+}
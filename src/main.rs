@@ -1,29 +1,151 @@
+#![recursion_limit = "256"]
+
 use {
-    clap::{Parser, Subcommand},
-    csv::Reader,
-    futures_util::TryFutureExt,
-    solana_client::{nonblocking::rpc_client::RpcClient, rpc_config::RpcSendTransactionConfig},
+    amount::{base_units_to_ui_amount, ui_amount_to_base_units},
+    clap::{parser::ValueSource, CommandFactory, FromArgMatches, Parser, Subcommand},
+    cluster::{Cluster, Explorer},
+    events::{Event, Outcome, OutputFormat},
+    fees::{is_compute_unit_price, resolve_priority_fee, FeeSchedule, FeeSource},
+    futures_util::{FutureExt, StreamExt, TryFutureExt},
+    hook::ExtraAccount,
+    jito::{JitoClient, JitoSender, MIN_TIP_LAMPORTS},
+    output::OutputTemplate,
+    price::{UsdPrice, USD_DECIMALS},
+    rand::{rngs::StdRng, seq::SliceRandom, Rng, SeedableRng},
+    recipients::{
+        expand_recipient_paths, has_csv_amounts, read_recipients_csvs, LabelColumn, Recipient,
+        LABEL_HEADER,
+    },
+    remaining::{
+        append_permanently_failed, merge_remaining_csv, write_failures_csv, write_remaining_csv,
+        RecipientAttempts, FAILURES_PATH, PERMANENTLY_FAILED_PATH, RECIPIENT_ATTEMPTS_PATH,
+        REMAINING_RECIPIENTS_PATH,
+    },
+    run_state::RunState,
+    sender::{Broadcaster, TransactionSender},
+    signatures::{
+        append_send_failure_log, append_signature_log, confirmed_payments, skip_prior_payments,
+        SIGNATURE_LOG_PATH,
+    },
+    solana_client::{
+        client_error::{ClientError, ClientErrorKind},
+        nonblocking::{pubsub_client::PubsubClient, rpc_client::RpcClient},
+        rpc_config::{RpcBlockConfig, RpcSendTransactionConfig, RpcSignatureSubscribeConfig},
+        rpc_custom_error::{
+            JSON_RPC_SERVER_ERROR_BLOCK_NOT_AVAILABLE,
+            JSON_RPC_SERVER_ERROR_LONG_TERM_STORAGE_SLOT_SKIPPED,
+            JSON_RPC_SERVER_ERROR_SLOT_SKIPPED,
+        },
+        rpc_request::{RpcError, RpcResponseErrorData},
+        rpc_response::{ProcessedSignatureResult, RpcSignatureResult},
+    },
     solana_sdk::{
-        address_lookup_table::program,
+        account::Account,
+        clock::Slot,
         commitment_config::{CommitmentConfig, CommitmentLevel},
         compute_budget::ComputeBudgetInstruction,
-        instruction::Instruction,
+        hash::{hashv, Hash},
+        instruction::{AccountMeta, Instruction},
         message::Message,
         pubkey::Pubkey,
-        signature::read_keypair_file,
+        signature::Signature,
         signer::Signer,
-        transaction::Transaction,
+        system_instruction, system_program,
+        transaction::{Transaction, TransactionError},
     },
-    spl_associated_token_account::{
-        get_associated_token_address_with_program_id,
-        instruction::create_associated_token_account_idempotent,
+    solana_transaction_status::{EncodedTransaction, TransactionDetails, UiTransactionEncoding},
+    source::SourceTokenAccount,
+    spl_associated_token_account::instruction::create_associated_token_account_idempotent,
+    spl_token_2022::{
+        extension::{
+            memo_transfer::MemoTransfer, BaseStateWithExtensions, ExtensionType,
+            StateWithExtensions,
+        },
+        offchain,
+        state::{Account as TokenAccount, Mint},
     },
-    spl_token_2022::offchain,
     spl_token_client::client::{ProgramClient, ProgramRpcClient, ProgramRpcClientSendTransaction},
-    std::{error::Error, str::FromStr, sync::Arc},
+    std::{
+        any::Any,
+        collections::{BTreeMap, BTreeSet, HashMap, HashSet},
+        error::Error,
+        fs,
+        io::{stdin, stdout, IsTerminal, Write},
+        panic::AssertUnwindSafe,
+        path::Path,
+        str::FromStr,
+        sync::{
+            atomic::{AtomicBool, Ordering},
+            Arc, Mutex, PoisonError,
+        },
+        time::{Duration, Instant},
+    },
+    throttle::{SendJitter, SendLimiter},
+    timings::{print_timing_summary, BatchTimings},
+    webhook::Webhook,
 };
 
 pub const CU_LIMIT: u32 = 1000000;
+pub const TOKEN_DECIMALS: u8 = 9;
+pub const MAX_MULTIPLE_ACCOUNTS: usize = 100;
+pub const MAX_CU_LIMIT: u32 = 1400000;
+pub const WS_CONFIRM_TIMEOUT: Duration = Duration::from_secs(60);
+pub const CONFIRM_POLL_INTERVAL: Duration = Duration::from_millis(500);
+/// The longest wait between two status polls once they have backed off.
+pub const MAX_CONFIRM_POLL_INTERVAL: Duration = Duration::from_secs(5);
+pub const MAX_SIGNATURE_STATUSES_PER_REQUEST: usize = 256;
+pub const SEND_RETRY_BACKOFF: Duration = Duration::from_millis(500);
+pub const CONFIRM_RETRY_BACKOFF: Duration = Duration::from_secs(2);
+/// How long `--confirm-commitment-escalation` waits for confirmed
+/// transactions to finalize. Finalizing takes about 32 slots, roughly 13s.
+pub const FINALITY_TIMEOUT: Duration = Duration::from_secs(60);
+pub const ACCOUNT_FETCH_RETRIES: u32 = 2;
+pub const BLOCKHASH_FETCH_RETRIES: u32 = 2;
+pub const RPC_WARMUP_RETRIES: u32 = 4;
+pub const RPC_WARMUP_BACKOFF: Duration = Duration::from_secs(1);
+pub const RPC_TIMEOUT_SECS: u64 = 30;
+/// Priority fee cost per transaction, in SOL, above which a run must be
+/// confirmed.
+pub const MAX_PRIORITY_COST: &str = "0.01";
+
+static QUIET: AtomicBool = AtomicBool::new(false);
+
+/// Prints progress output unless `--quiet` was passed.
+macro_rules! progress {
+    ($($arg:tt)*) => {
+        if !$crate::QUIET.load(::std::sync::atomic::Ordering::Relaxed) {
+            println!($($arg)*);
+        }
+    };
+}
+
+mod amount;
+mod cluster;
+mod compare;
+mod events;
+mod failover;
+mod fees;
+mod hook;
+mod jito;
+mod keypair;
+mod lint;
+mod mint_info;
+#[cfg(test)]
+mod mock;
+mod output;
+mod plan;
+mod price;
+mod recipients;
+mod remaining;
+mod requeue;
+mod run_state;
+mod sender;
+mod signatures;
+mod snapshot;
+mod source;
+mod throttle;
+mod timings;
+mod webhook;
 
 #[derive(Parser)]
 #[command(version, about, long_about = None)]
@@ -31,10 +153,39 @@ struct Args {
     #[arg(
         long,
         value_name = "NETWORK_URL",
-        help = "Network address of your RPC provider",
+        help = "Network address of your RPC provider; repeat it or separate with commas to fail over to further endpoints",
+        env = "SOLANA_RPC_URL",
+        value_delimiter = ',',
+        global = true
+    )]
+    rpc: Vec<String>,
+
+    #[arg(
+        long,
+        value_name = "CLUSTER",
+        help = "Use the standard RPC endpoint of mainnet, devnet, testnet or localhost",
+        value_enum,
+        global = true
+    )]
+    cluster: Option<Cluster>,
+
+    #[arg(
+        long,
+        value_name = "WEBSOCKET_URL",
+        help = "WebSocket address of your RPC provider, used for faster confirmation",
+        global = true
+    )]
+    ws: Option<String>,
+
+    #[arg(
+        long,
+        value_name = "SECONDS",
+        help = "How long to wait for each RPC request before giving up on it",
+        default_value_t = RPC_TIMEOUT_SECS,
+        value_parser = clap::value_parser!(u64).range(1..),
         global = true
     )]
-    rpc: Option<String>,
+    rpc_timeout_secs: u64,
 
     #[clap(
         global = true,
@@ -48,11 +199,27 @@ struct Args {
     #[arg(
         long,
         value_name = "KEYPAIR_FILEPATH",
-        help = "Filepath to keypair to use",
+        help = "Filepath to keypair to use, or env:VAR_NAME to read it from the environment",
+        env = "SOLANA_KEYPAIR",
         global = true
     )]
     keypair: Option<String>,
 
+    #[arg(
+        long,
+        short = 'q',
+        help = "Only print errors and the final summary",
+        global = true
+    )]
+    quiet: bool,
+
+    #[arg(
+        long,
+        help = "Print the effective configuration as JSON and exit without doing anything",
+        global = true
+    )]
+    dump_config: bool,
+
     #[command(subcommand)]
     command: Commands,
 }
@@ -60,7 +227,27 @@ struct Args {
 #[derive(Subcommand, Debug)]
 enum Commands {
     #[command(about = "Airdrop tokens to the provided list of addresses.")]
-    Airdrop(AirdropArgs),
+    Airdrop(Box<AirdropArgs>),
+    #[command(about = "Airdrop native SOL to the provided list of addresses.")]
+    AirdropSol(Box<AirdropSolArgs>),
+    #[command(
+        about = "Retry the recipients in the remaining CSV, optionally with a higher priority fee."
+    )]
+    RetryFailed(Box<RetryFailedArgs>),
+    #[command(about = "Write the holders of a token to a recipients CSV.")]
+    Snapshot(SnapshotArgs),
+    #[command(about = "Show a token's program, decimals, supply and extensions.")]
+    MintInfo(MintInfoArgs),
+    #[command(about = "Check a recipients CSV for common problems, and optionally fix them.")]
+    Lint(LintArgs),
+    #[command(about = "Remove a recipient from a remaining CSV so they are not retried.")]
+    Remove(RemoveArgs),
+    #[command(
+        about = "Move a recipient from the permanently failed CSV back into the remaining CSV."
+    )]
+    Requeue(RequeueArgs),
+    #[command(about = "Compare two recipient CSVs and write the differences to a CSV.")]
+    Compare(CompareArgs),
 }
 
 #[derive(Parser, Debug)]
@@ -71,226 +258,6534 @@ struct AirdropArgs {
     )]
     pub token_address: String,
 
+    #[command(flatten)]
+    pub recipients: RecipientsArgs,
+
+    #[command(flatten)]
+    pub options: AirdropOptions,
+
+    #[command(flatten)]
+    pub token: TokenOptions,
+}
+
+#[derive(Parser, Debug)]
+struct AirdropSolArgs {
+    #[command(flatten)]
+    pub recipients: RecipientsArgs,
+
+    #[command(flatten)]
+    pub options: AirdropOptions,
+}
+
+#[derive(Parser, Debug)]
+struct RetryFailedArgs {
+    #[arg(
+        value_name = "TOKEN_ADDRESS",
+        help = "The address of the token to airdrop"
+    )]
+    pub token_address: String,
+
+    #[arg(
+        long,
+        value_name = "PATH",
+        help = "The remaining CSV to retry, which is updated in place",
+        default_value = REMAINING_RECIPIENTS_PATH
+    )]
+    pub from: String,
+
+    #[arg(
+        long,
+        value_name = "MICROLAMPORTS",
+        help = "Microlamports to add to the priority fee for this round",
+        default_value_t = 0
+    )]
+    pub escalate_fee: u64,
+
+    #[command(flatten)]
+    pub options: AirdropOptions,
+
+    #[command(flatten)]
+    pub token: TokenOptions,
+}
+
+/// Where the recipients of an airdrop come from and how much each is sent.
+#[derive(clap::Args, Debug)]
+struct RecipientsArgs {
     #[arg(
         value_name = "RECIPIENTS_CSV_PATH",
-        help = "The address CSV of the airdrop recipients"
+        help = "The address CSV of the airdrop recipients, or a glob pattern matching several"
     )]
     pub recipients_csv_path: String,
 
     #[arg(
         value_name = "AMOUNT",
-        help = "The total amount of the token to airdrop"
+        help = "The amount to airdrop to each recipient in whole tokens or SOL, e.g. 100 or 0.5. Overrides the CSV amount column"
     )]
-    pub amount: u64,
+    pub amount: Option<String>,
 
     #[arg(
         long,
-        value_name = "MICROLAMPORTS",
-        help = "Number of microlamports to pay as priority fee per transaction",
-        default_value = "0",
-        global = true
+        help = "Send AMOUNT to everyone even though the CSV has its own amounts",
+        requires = "amount"
     )]
-    priority_fee: Option<u64>,
-}
-fn extract_column_from_csv(
-    file_path: &str,
-    column_index: usize,
-) -> Result<Vec<Pubkey>, Box<dyn std::error::Error>> {
-    let mut rdr = Reader::from_path(file_path)?;
-    let mut column_values: Vec<Pubkey> = Vec::new();
+    pub override_csv_amounts: bool,
 
-    for result in rdr.records() {
-        let record = result?;
-        if let Some(value) = record.get(column_index) {
-            column_values.push(Pubkey::from_str(&value.to_string()).unwrap());
-        }
-    }
+    #[arg(
+        long,
+        value_name = "USD",
+        help = "Treat AMOUNT and the CSV amounts as USD and send each recipient that much worth at this USD price per whole token or SOL",
+        value_parser = UsdPrice::parse,
+        conflicts_with_all = ["price_url", "decimals_column"]
+    )]
+    pub price: Option<UsdPrice>,
 
-    Ok(column_values)
-}
+    #[arg(
+        long,
+        value_name = "URL",
+        help = "Like --price, but GET the price from this JSON URL before building transactions",
+        conflicts_with = "decimals_column"
+    )]
+    pub price_url: Option<String>,
 
-async fn load_config(args: &Args) -> Result<solana_cli_config::Config, Box<dyn Error>> {
-    if let Some(config_file) = &args.config_file {
-        Ok(solana_cli_config::Config::load(config_file)?)
-    } else if let Some(config_file) = &*solana_cli_config::CONFIG_FILE {
-        Ok(solana_cli_config::Config::load(config_file).unwrap_or_default())
-    } else {
-        Ok(solana_cli_config::Config::default())
-    }
+    #[arg(
+        long,
+        value_name = "FIELD",
+        help = "Dot-separated path to the price in the --price-url response, if it isn't a bare number",
+        requires = "price_url"
+    )]
+    pub price_field: Option<String>,
 }
 
-const MAX_INSTRUCTIONS_PER_TX: usize = 4;
+/// Options that only apply to token airdrops, which pay into ATAs.
+#[derive(clap::Args, Debug)]
+struct TokenOptions {
+    #[arg(
+        long,
+        help = "Treat amounts as each recipient's desired final balance and only send the difference"
+    )]
+    pub target_balance: bool,
 
-async fn create_airdrop_tx(
-    args: AirdropArgs,
-    rpc_client: Arc<RpcClient>,
-    source_keypair: Arc<dyn Signer>,
-) -> Result<Vec<Transaction>, Box<dyn Error>> {
-    let recipients_pubkeys = extract_column_from_csv(&args.recipients_csv_path, 0).unwrap();
-    let source_pubkey = &source_keypair.pubkey();
-    let token_pubkey = Pubkey::from_str(&args.token_address).unwrap();
-    let token_amount = args.amount;
+    #[arg(
+        long,
+        value_name = "OWNER_ADDRESS",
+        help = "Send every recipient's tokens to the ATA of this owner instead of the recipient's own"
+    )]
+    pub ata_owner: Option<Pubkey>,
 
-    let amount = spl_token_2022::ui_amount_to_amount(token_amount as f64, 9);
+    #[arg(
+        long,
+        help = "Skip recipients whose token account already holds their amount, e.g. when resuming from the remaining CSV",
+        conflicts_with = "target_balance"
+    )]
+    pub verify_before_resume: bool,
 
-    let mut transactions: Vec<Transaction> = Vec::new();
-    let mut instructions: Vec<Instruction> = Vec::new();
+    #[arg(
+        long,
+        value_name = "TEXT",
+        help = "Memo to attach to transfers into accounts that require incoming memos"
+    )]
+    pub memo: Option<String>,
 
-    println!("Source: {:?}", source_keypair.pubkey());
-    println!("Token: {:?}", token_pubkey);
-    println!("Recipients: {:?}", recipients_pubkeys);
-    println!("Amount: {}", token_amount);
+    #[arg(
+        long,
+        value_name = "COMMITMENT",
+        help = "Commitment used to check whether recipient token accounts exist and to read their balances: processed, confirmed or finalized",
+        default_value = "confirmed"
+    )]
+    pub account_commitment: CommitmentLevel,
 
-    let program_client: Arc<dyn ProgramClient<ProgramRpcClientSendTransaction>> = Arc::new(
-        ProgramRpcClient::new(rpc_client, ProgramRpcClientSendTransaction),
-    );
+    #[arg(
+        long,
+        value_name = "PUBKEY[:w]",
+        help = "Account to append to every transfer for the transfer hook, read-only unless suffixed with :w; may be repeated"
+    )]
+    pub extra_account: Vec<ExtraAccount>,
 
-    let sender = get_associated_token_address_with_program_id(
-        &source_pubkey,
-        &token_pubkey,
-        &spl_token_2022::id(),
-    );
-    println!("Sender ATA: {}", sender);
+    #[arg(
+        long,
+        help = "Skip recipients whose transfer hook accounts, such as an allowlist entry, don't exist, and list them before sending"
+    )]
+    pub check_hook_accounts: bool,
 
-    let cu_limit_ix = ComputeBudgetInstruction::set_compute_unit_limit(CU_LIMIT);
-    let cu_price_ix =
-        ComputeBudgetInstruction::set_compute_unit_price(args.priority_fee.unwrap_or_default());
+    #[arg(
+        long,
+        value_name = "ACCOUNT[:KEYPAIR]",
+        help = "Token account to send from instead of the --keypair wallet's ATA, with its owner's keypair if that's not --keypair; may be repeated to drain each in turn"
+    )]
+    pub source_token_account: Vec<SourceTokenAccount>,
 
-    for recipient in recipients_pubkeys.iter() {
-        let mut recipient_instructions: Vec<Instruction> = Vec::new();
+    #[arg(
+        long,
+        value_name = "PUBKEY",
+        help = "Owner or delegate of the source token account, if it isn't --keypair, which then only pays fees and rent; its keypair must be given with --signer"
+    )]
+    pub transfer_authority: Option<Pubkey>,
 
-        let destination = get_associated_token_address_with_program_id(
-            recipient,
-            &token_pubkey,
-            &spl_token_2022::id(),
-        );
-        println!("Destination ATA: {}", destination);
+    #[arg(
+        long,
+        help = "Send as the approved delegate of each --source-token-account rather than its owner, and check the delegated amounts cover the airdrop",
+        requires = "source_token_account"
+    )]
+    pub as_delegate: bool,
 
-        if let Ok(Some(_ata)) = program_client.get_account(destination).await {
-        } else {
-            recipient_instructions.push(create_associated_token_account_idempotent(
-                &source_pubkey,
-                recipient,
-                &token_pubkey,
-                &spl_token_2022::id(),
-            ));
-        }
+    #[arg(
+        long,
+        help = "Instead of refusing to start when the source can't cover every transfer, send as far as it can and write everyone after that to the remaining CSV"
+    )]
+    pub stop_on_insufficient_balance: bool,
 
-        let fetch_account_data_fn = |address| {
-            program_client
-                .get_account(address)
-                .map_ok(|opt| opt.map(|acc| acc.data))
-        };
+    #[arg(
+        long,
+        help = "Create missing ATAs in transactions of their own and confirm them before sending any transfer"
+    )]
+    pub separate_ata_creation: bool,
 
-        let instruction = offchain::create_transfer_checked_instruction_with_extra_metas(
-            &spl_token_2022::id(),
-            &sender,
-            &token_pubkey,
-            &destination,
-            &source_keypair.pubkey(),
-            &[],
-            amount,
-            9,
-            fetch_account_data_fn,
-        )
-        .await
-        .unwrap();
+    #[arg(
+        long,
+        help = "Only create the missing ATAs, without sending any tokens, to provision accounts ahead of the airdrop",
+        conflicts_with_all = ["stop_on_insufficient_balance", "min_recipient_balance_sol"]
+    )]
+    pub only_missing_atas: bool,
 
-        recipient_instructions.push(instruction);
+    #[arg(
+        long,
+        value_name = "SOL",
+        help = "Top up each recipient wallet holding less than this much SOL to this amount, alongside its token transfer"
+    )]
+    pub min_recipient_balance_sol: Option<String>,
 
-        if instructions.len() + recipient_instructions.len() + 1 > MAX_INSTRUCTIONS_PER_TX {
-            let mut tx_instructions = vec![cu_price_ix.clone(), cu_limit_ix.clone()];
-            tx_instructions.append(&mut instructions);
+    #[arg(
+        long,
+        value_name = "AMOUNT",
+        help = "Split any recipient's amount above this many whole tokens into several transfers of at most this much",
+        conflicts_with_all = ["target_balance", "run_id"]
+    )]
+    pub amount_per_transfer_cap: Option<String>,
 
-            let blockhash = program_client.get_latest_blockhash().await.unwrap();
-            let message = Message::new_with_blockhash(
-                &tx_instructions,
-                Some(&source_keypair.pubkey()),
-                &blockhash,
-            );
-            let mut transaction = Transaction::new_unsigned(message);
+    #[arg(
+        long,
+        value_name = "KEYPAIR",
+        help = "Additional keypair file, or env:VAR_NAME, to sign with, e.g. for --transfer-authority; may be repeated"
+    )]
+    pub signer: Vec<String>,
 
-            let signers: Vec<&dyn Signer> = vec![source_keypair.as_ref()];
-            transaction.sign(&signers, blockhash);
+    #[arg(
+        long,
+        value_name = "BYTES",
+        help = "Size of the ATAs created, used to estimate their rent, if they get more extensions than the mint requires [default: the standard Token-2022 ATA size for the mint]"
+    )]
+    pub token_account_size: Option<usize>,
 
-            transactions.push(transaction);
-            instructions.clear();
-        }
+    #[arg(
+        long,
+        value_name = "PROGRAM_ID",
+        help = "Associated token account program to derive and create ATAs with, for deployments without the standard one",
+        default_value_t = spl_associated_token_account::id()
+    )]
+    pub ata_program: Pubkey,
+}
 
-        instructions.extend(recipient_instructions);
-    }
+/// Options shared by every kind of airdrop: how recipients are read and
+/// ordered, and how transactions are priced, sent, confirmed and reported.
+#[derive(clap::Args, Debug)]
+struct AirdropOptions {
+    #[arg(
+        long,
+        value_name = "PATTERN",
+        help = "More recipient CSVs to include, as a path or glob pattern; may be repeated"
+    )]
+    pub recipients_glob: Vec<String>,
 
-    if !instructions.is_empty() {
-        let mut tx_instructions = vec![cu_price_ix.clone(), cu_limit_ix.clone()];
-        tx_instructions.append(&mut instructions);
+    #[arg(
+        long,
+        value_name = "MICROLAMPORTS",
+        help = "Number of microlamports to pay as priority fee per transaction",
+        default_value = "0",
+        global = true
+    )]
+    priority_fee: Option<u64>,
 
-        let blockhash = program_client.get_latest_blockhash().await.unwrap();
-        let message = Message::new_with_blockhash(
-            &tx_instructions,
-            Some(&source_keypair.pubkey()),
-            &blockhash,
-        );
-        let mut transaction = Transaction::new_unsigned(message);
+    #[arg(
+        long,
+        value_name = "SOURCE",
+        help = "Look up the priority fee from `local` (getRecentPrioritizationFees) or a JSON URL, falling back to --priority-fee"
+    )]
+    pub fee_source: Option<FeeSource>,
 
-        let signers: Vec<&dyn Signer> = vec![source_keypair.as_ref()];
-        transaction.sign(&signers, blockhash);
+    #[arg(
+        long,
+        value_name = "FIELD",
+        help = "Dot-separated path to the fee in the --fee-source URL response, if it isn't a bare number",
+        requires = "fee_source"
+    )]
+    pub fee_field: Option<String>,
 
-        transactions.push(transaction);
-    }
+    #[arg(
+        long,
+        value_name = "PATH",
+        help = "JSON file of compute unit prices for ranges of transactions, numbered from 1, such as [{\"first\": 1, \"last\": 100, \"microlamports\": 1000}]; transactions outside every range pay the usual priority fee",
+        value_parser = FeeSchedule::load
+    )]
+    pub fee_schedule: Option<FeeSchedule>,
 
-    Ok(transactions)
-}
+    #[arg(
+        long,
+        value_name = "COMPUTE_UNITS",
+        help = "Compute unit limit requested per transaction, or 0 to leave the runtime default",
+        default_value_t = CU_LIMIT,
+        value_parser = clap::value_parser!(u32).range(0..=MAX_CU_LIMIT as i64)
+    )]
+    pub cu_limit: u32,
 
-async fn execute_airdrop(
-    transactions: Vec<Transaction>,
-    rpc_client: Arc<RpcClient>,
-) -> Result<(), Box<dyn Error>> {
-    let config = RpcSendTransactionConfig {
-        skip_preflight: false,
-        preflight_commitment: Some(CommitmentLevel::Processed),
-        ..Default::default()
-    };
+    #[arg(
+        long,
+        value_name = "AMOUNT",
+        help = "Abort unless the recipient amounts add up to this many whole tokens or SOL, to catch a truncated CSV"
+    )]
+    pub expected_total: Option<String>,
 
-    // rpc_client.send_transaction(&transaction).await.unwrap();
+    #[arg(
+        long,
+        value_name = "N",
+        help = "Refuse to start if the recipients CSV lists more than this many recipients, to catch pointing the tool at the wrong file",
+        default_value_t = DEFAULT_MAX_RECIPIENTS
+    )]
+    pub max_recipients: usize,
 
-    for transaction in transactions.iter() {
-        println!("Sending tx 📦");
-        let signature = rpc_client
-            .send_and_confirm_transaction_with_spinner_and_config(
-                transaction,
-                CommitmentConfig::confirmed(),
-                config,
-            )
-            .await
-            .unwrap();
-        println!("Done ✅");
-        println!("Signature: {}", signature);
-    }
+    #[arg(
+        long,
+        value_name = "AMOUNT",
+        help = "How far the recipient amounts may differ from --expected-total",
+        requires = "expected_total"
+    )]
+    pub expected_total_tolerance: Option<String>,
 
-    Ok(())
-}
+    #[arg(
+        long,
+        value_name = "HASH",
+        help = "Abort unless the recipients hash printed by an earlier run, such as a --plan-out dry run, matches this one, to catch a CSV edited in between"
+    )]
+    pub expect_hash: Option<String>,
 
-#[tokio::main]
-async fn main() -> Result<(), Box<dyn std::error::Error>> {
-    let args = Args::parse();
+    #[arg(
+        long,
+        value_name = "SOL",
+        help = "Abort before sending if fees, rent and transfers would leave the fee payer with less than this much SOL"
+    )]
+    pub min_sol_reserve: Option<String>,
 
-    let cli_config = load_config(&args).await?;
-    let source_keypair =
-        Arc::new(read_keypair_file(args.keypair.unwrap_or(cli_config.keypair_path)).unwrap());
-    let cluster = args.rpc.unwrap_or(cli_config.json_rpc_url);
-    let rpc_client = Arc::new(RpcClient::new_with_commitment(
-        cluster,
-        CommitmentConfig::confirmed(),
-    ));
+    #[arg(
+        long,
+        value_name = "SOL",
+        help = "Ask for confirmation if the priority fee would cost more than this much SOL per transaction",
+        default_value = MAX_PRIORITY_COST
+    )]
+    pub max_priority_cost: String,
 
-    match args.command {
-        Commands::Airdrop(args) => {
-            let tx = create_airdrop_tx(args, rpc_client.clone(), source_keypair)
-                .await
-                .unwrap();
-            execute_airdrop(tx, rpc_client.clone()).await?;
-        }
-    }
+    #[arg(
+        long,
+        short = 'y',
+        help = "Don't ask for confirmation, e.g. of a priority fee above --max-priority-cost"
+    )]
+    pub yes: bool,
 
-    Ok(())
+    #[arg(
+        long,
+        help = "Don't check before sending that the source wallet holds enough tokens and SOL, e.g. on a test validator"
+    )]
+    pub skip_balance_check: bool,
+
+    #[arg(long, help = "Randomize the order in which recipients are sent to")]
+    pub shuffle: bool,
+
+    #[arg(
+        long,
+        value_name = "SEED",
+        help = "Seed for --shuffle, to reproduce a previous ordering",
+        requires = "shuffle"
+    )]
+    pub seed: Option<u64>,
+
+    #[arg(
+        long,
+        help = "Send to recipients equal to the source wallet instead of skipping them"
+    )]
+    pub allow_self_transfer: bool,
+
+    #[arg(
+        long,
+        value_name = "ATTEMPTS",
+        help = "Failed attempts after which a recipient is moved to the permanently failed list",
+        default_value_t = 3,
+        value_parser = clap::value_parser!(u32).range(1..)
+    )]
+    pub max_attempts: u32,
+
+    #[arg(
+        long,
+        value_name = "PLAN_PATH",
+        help = "Write every planned transaction to a JSON file instead of sending"
+    )]
+    pub plan_out: Option<String>,
+
+    #[arg(
+        long,
+        conflicts_with_all = ["plan_out", "skip_balance_check"],
+        help = "Run every check done before sending, report whether they pass and exit without sending"
+    )]
+    pub preflight_only: bool,
+
+    #[arg(
+        long,
+        value_name = "PATH",
+        help = "Before sending, list the recipients of each transaction, or write them to a CSV at PATH",
+        num_args = 0..=1
+    )]
+    pub batch_report: Option<Option<String>>,
+
+    #[arg(
+        long,
+        help = "Broadcast every transaction without waiting, then confirm them all at the end"
+    )]
+    pub fire_and_forget: bool,
+
+    #[arg(
+        long,
+        help = "Broadcast every transaction, record its signature and exit without confirming anything",
+        conflicts_with_all = ["fire_and_forget", "confirm_in_background", "max_tx_in_flight", "checkpoint_every", "confirm_commitment_escalation", "confirm_via_get_block"]
+    )]
+    pub no_confirm: bool,
+
+    #[arg(
+        long,
+        help = "Send each transaction while the previous one is still confirming, keeping at most two in flight",
+        conflicts_with_all = ["fire_and_forget", "max_tx_in_flight", "continue_on_error"]
+    )]
+    pub confirm_in_background: bool,
+
+    #[arg(
+        long,
+        help = "Keep sending later transactions after one fails, and write all failures to the remaining CSV at the end"
+    )]
+    pub continue_on_error: bool,
+
+    #[arg(
+        long,
+        value_name = "COUNT",
+        help = "Send and confirm up to this many transactions at once",
+        default_value_t = 1,
+        value_parser = clap::value_parser!(u32).range(1..),
+        conflicts_with = "fire_and_forget"
+    )]
+    pub max_tx_in_flight: u32,
+
+    #[arg(
+        long,
+        value_name = "N",
+        help = "Rewrite the remaining CSV with everyone not yet paid after every N confirmed transactions, so a killed run loses little progress",
+        conflicts_with = "fire_and_forget"
+    )]
+    pub checkpoint_every: Option<usize>,
+
+    #[arg(
+        long,
+        value_name = "N",
+        help = "Skip the transactions before the Nth, counting from 1 as in the progress output, after checking in the signatures CSV that everyone they pay was paid"
+    )]
+    pub resume_from_batch: Option<usize>,
+
+    #[arg(
+        long,
+        help = "Print how long blockhash fetch, signing, send and confirmation took across batches"
+    )]
+    pub timings: bool,
+
+    #[arg(
+        long,
+        help = "Instead of a line per failed transaction, print the failures grouped by error at the end and write each failed recipient to failures.csv"
+    )]
+    pub report_failures_only: bool,
+
+    #[arg(
+        long,
+        value_name = "EXPLORER",
+        help = "Print signatures as links to this block explorer, on the cluster of the RPC endpoint, instead of in base58",
+        value_enum
+    )]
+    pub explorer: Option<Explorer>,
+
+    #[arg(
+        long,
+        value_name = "URL",
+        help = "POST each batch's recipients, amounts and signature as JSON to this URL once it confirms; failures are retried and logged but never stop the run",
+        conflicts_with = "no_confirm"
+    )]
+    pub webhook_url: Option<String>,
+
+    #[arg(
+        long,
+        help = "Merge failed recipients into the existing remaining CSV instead of overwriting it"
+    )]
+    pub append_remaining: bool,
+
+    #[arg(
+        long,
+        value_name = "FORMAT",
+        help = "Output format: human-readable text, or one JSON event per line as transactions settle",
+        value_enum,
+        default_value_t = OutputFormat::Text
+    )]
+    pub output_format: OutputFormat,
+
+    #[arg(
+        long,
+        value_name = "COLUMN",
+        help = "CSV column, by header name or zero-based index, holding a label to record with each recipient's signature"
+    )]
+    pub label_column: Option<LabelColumn>,
+
+    #[arg(
+        long,
+        value_name = "COLUMN",
+        help = "CSV column, by header name or zero-based index, giving the decimals each row's amount is written with"
+    )]
+    pub decimals_column: Option<LabelColumn>,
+
+    #[arg(
+        long,
+        value_name = "RETRIES",
+        help = "Times to re-broadcast a transaction whose send failed, with exponential backoff",
+        default_value_t = 2
+    )]
+    pub send_retries: u32,
+
+    #[arg(
+        long,
+        value_name = "TPS",
+        help = "Broadcast at most this many transactions per second across the whole run, retries included [default: unlimited]",
+        value_parser = clap::value_parser!(u32).range(1..)
+    )]
+    pub max_tps: Option<u32>,
+
+    #[arg(
+        long,
+        value_name = "MIN_MS-MAX_MS",
+        help = "Wait a random time, picked uniformly between these bounds in milliseconds, between broadcasts across the whole run [default: no delay]"
+    )]
+    pub delay_between_sends_jitter: Option<SendJitter>,
+
+    #[arg(
+        long,
+        value_name = "URL",
+        help = "Submit transactions as bundles to this Jito block engine, e.g. https://mainnet.block-engine.jito.wtf, instead of through the RPC"
+    )]
+    pub jito_url: Option<String>,
+
+    #[arg(
+        long,
+        value_name = "LAMPORTS",
+        help = "Tip paid to Jito with each bundle, in lamports",
+        default_value_t = MIN_TIP_LAMPORTS,
+        value_parser = clap::value_parser!(u64).range(MIN_TIP_LAMPORTS..)
+    )]
+    pub jito_tip_lamports: u64,
+
+    #[arg(
+        long,
+        value_name = "RETRIES",
+        help = "Extra signature status checks after confirmation times out, with exponential backoff and without re-broadcasting",
+        default_value_t = 3
+    )]
+    pub confirm_retries: u32,
+
+    #[arg(
+        long,
+        value_name = "RETRIES",
+        help = "Extra checks, with exponential backoff, of sent transactions whose outcome is still unknown when a --fire-and-forget or concurrent run ends",
+        default_value_t = 3
+    )]
+    pub reconcile_retries: u32,
+
+    #[arg(
+        long,
+        help = "Count transactions as done once confirmed while sending, then check after the run that every one reached finalized, flagging any that were dropped"
+    )]
+    pub confirm_commitment_escalation: bool,
+
+    #[arg(
+        long = "confirm-via-get-block",
+        help = "After the run, look up every confirmed transaction in the block it landed in and check its result there, for long runs whose signature statuses may have aged out; costs a getBlock request per block"
+    )]
+    pub confirm_via_get_block: bool,
+
+    #[arg(
+        long,
+        value_name = "MILLISECONDS",
+        help = "How often to poll signature statuses while confirming over HTTP",
+        default_value_t = CONFIRM_POLL_INTERVAL.as_millis() as u64,
+        value_parser = clap::value_parser!(u64).range(1..)
+    )]
+    pub poll_interval_ms: u64,
+
+    #[arg(
+        long,
+        value_name = "MILLISECONDS",
+        help = "Longest wait between status polls: each poll that finds a transaction still pending doubles the wait from --poll-interval-ms up to this",
+        default_value_t = MAX_CONFIRM_POLL_INTERVAL.as_millis() as u64,
+        value_parser = clap::value_parser!(u64).range(1..)
+    )]
+    pub max_poll_interval_ms: u64,
+
+    #[arg(
+        long,
+        value_name = "PATH",
+        help = "Where to write recipients that still need paying; gzipped if the path ends in .gz",
+        default_value = REMAINING_RECIPIENTS_PATH
+    )]
+    pub remaining_path: String,
+
+    #[arg(
+        long,
+        value_name = "TEMPLATE",
+        help = "Name the remaining, permanently failed and signature files from a template such as {name}_{mint}_{runid}; {timestamp} is the start time in Unix seconds"
+    )]
+    pub output_template: Option<String>,
+
+    #[arg(
+        long,
+        value_name = "DIR",
+        help = "Directory to write every file the run generates into, created if missing; relative paths given for them are resolved against it"
+    )]
+    pub output_dir: Option<String>,
+
+    #[arg(
+        long,
+        value_name = "RUN_ID",
+        help = "Identifier for this run: recipients it has confirmed are recorded in a state file and skipped when it is restarted. Also {runid} in --output-template"
+    )]
+    pub run_id: Option<String>,
+
+    #[arg(
+        long,
+        value_name = "PATH",
+        help = "State file recording who this --run-id has confirmed [default: run_<RUN_ID>.csv]",
+        requires = "run_id"
+    )]
+    pub state_path: Option<String>,
+
+    #[arg(
+        long,
+        value_name = "PATH",
+        help = "Signatures CSV of an earlier run: recipients a confirmed transaction in it already paid are skipped instead of sent to again"
+    )]
+    pub prior_signatures: Option<String>,
+}
+
+/// Where a run writes the outputs that have no flag of their own, after
+/// `--output-template` and `--output-dir`.
+#[derive(Clone, Debug)]
+struct OutputPaths {
+    /// Where confirmed signatures are logged.
+    signatures: String,
+    /// Where recipients out of attempts go.
+    permanently_failed: String,
+    /// Where `--report-failures-only` details each failure.
+    failures: String,
+    /// Where failed attempts are counted.
+    attempts: String,
+}
+
+impl Default for OutputPaths {
+    fn default() -> Self {
+        Self {
+            signatures: SIGNATURE_LOG_PATH.to_string(),
+            permanently_failed: PERMANENTLY_FAILED_PATH.to_string(),
+            failures: FAILURES_PATH.to_string(),
+            attempts: RECIPIENT_ATTEMPTS_PATH.to_string(),
+        }
+    }
+}
+
+impl AirdropOptions {
+    /// Whether each batch has to stand on its own, because a later batch may
+    /// land before an earlier one or be sent after an earlier one failed.
+    fn needs_independent_batches(&self) -> bool {
+        self.fire_and_forget
+            || self.no_confirm
+            || self.max_tx_in_flight > 1
+            || self.continue_on_error
+            || self.confirm_in_background
+    }
+
+    /// Renames the output files according to `--output-template`, then
+    /// moves every relative output path into `--output-dir`, and returns where
+    /// the outputs without a flag of their own go. An explicit
+    /// `--remaining-path` keeps its name.
+    fn resolve_output_paths(&mut self, mint: Option<&str>) -> Result<OutputPaths, Box<dyn Error>> {
+        let mut paths = OutputPaths::default();
+        if let Some(template) = &self.output_template {
+            let template = OutputTemplate::new(template, mint, self.run_id.as_deref())?;
+            if self.remaining_path == REMAINING_RECIPIENTS_PATH {
+                self.remaining_path = template.apply(REMAINING_RECIPIENTS_PATH);
+            }
+            paths.permanently_failed = template.apply(PERMANENTLY_FAILED_PATH);
+            paths.failures = template.apply(FAILURES_PATH);
+            paths.signatures = template.apply(SIGNATURE_LOG_PATH);
+        }
+
+        let Some(dir) = self.output_dir.clone() else {
+            return Ok(paths);
+        };
+        self.remaining_path = in_output_dir(&dir, &self.remaining_path);
+        paths.permanently_failed = in_output_dir(&dir, &paths.permanently_failed);
+        paths.failures = in_output_dir(&dir, &paths.failures);
+        paths.signatures = in_output_dir(&dir, &paths.signatures);
+        paths.attempts = in_output_dir(&dir, &paths.attempts);
+        if let Some(run_id) = &self.run_id {
+            let state_path = self
+                .state_path
+                .clone()
+                .unwrap_or_else(|| RunState::default_path(run_id));
+            self.state_path = Some(in_output_dir(&dir, &state_path));
+        }
+        if let Some(plan_out) = &self.plan_out {
+            self.plan_out = Some(in_output_dir(&dir, plan_out));
+        }
+        if let Some(Some(batch_report)) = &self.batch_report {
+            self.batch_report = Some(Some(in_output_dir(&dir, batch_report)));
+        }
+        Ok(paths)
+    }
+
+    /// The state of the `--run-id` run, if there is one.
+    fn run_state(&self) -> Result<Option<RunState>, Box<dyn Error>> {
+        let Some(run_id) = &self.run_id else {
+            return Ok(None);
+        };
+        let path = self
+            .state_path
+            .clone()
+            .unwrap_or_else(|| RunState::default_path(run_id));
+        RunState::load(&path).map(Some)
+    }
+
+    /// Lamports tipped to Jito across `transactions`, none without
+    /// `--jito-url`.
+    fn jito_tips(&self, transactions: usize) -> u64 {
+        match self.jito_url {
+            Some(_) => self.jito_tip_lamports.saturating_mul(transactions as u64),
+            None => 0,
+        }
+    }
+
+    fn poll_interval(&self) -> Duration {
+        Duration::from_millis(self.poll_interval_ms)
+    }
+
+    fn poll_backoff(&self) -> PollBackoff {
+        PollBackoff::new(
+            self.poll_interval(),
+            Duration::from_millis(self.max_poll_interval_ms),
+        )
+    }
+}
+
+/// `path` inside `dir`, unless it is absolute.
+fn in_output_dir(dir: &str, path: &str) -> String {
+    if Path::new(path).is_absolute() {
+        return path.to_string();
+    }
+    Path::new(dir).join(path).display().to_string()
+}
+
+/// What a run works out before it starts and builds up as it goes, as opposed
+/// to the options it was started with. One is made per command and passed
+/// alongside its options, which stay as they were parsed, so nothing a run
+/// records can be left behind in them for a later build or send to pick up
+/// again.
+#[derive(Default)]
+struct RunContext {
+    /// Where the outputs without a flag of their own go.
+    paths: OutputPaths,
+    /// Decimals amounts are read, sent and printed with: the mint's for
+    /// token airdrops and SOL's otherwise.
+    decimals: u8,
+    /// Microlamports added to the priority fee, set by `retry-failed`.
+    fee_escalation: u64,
+    /// The RPC endpoint, whose cluster `--explorer` links point at.
+    rpc_url: String,
+    explorer: Option<Explorer>,
+
+    /// The `--webhook-url` endpoint, if confirmations are reported to one.
+    webhook: Option<Webhook>,
+    /// The shared `--max-tps` and `--delay-between-sends-jitter` limiter, if
+    /// sends are limited at all.
+    send_limiter: Option<SendLimiter>,
+    /// The `--jito-url` block engine, if bundles are sent through one.
+    jito: Option<JitoClient>,
+
+    /// Recipients left out of the batches by `--stop-on-insufficient-balance`,
+    /// written to the remaining CSV along with any that fail.
+    cut_off: Mutex<Vec<Recipient>>,
+
+    /// With `--only-missing-atas`, the recipients whose ATA each batch
+    /// creates, by batch id, so those of a failed batch can be retried.
+    ata_recipients: Mutex<HashMap<String, Vec<Recipient>>>,
+
+    /// Who this run pays and who of them is still unpaid, kept up to date as
+    /// batches confirm so the remaining CSV can still be written if the run
+    /// panics.
+    unpaid: Mutex<UnpaidRecipients>,
+
+    /// Batch id, signature and blockhash of each transaction confirmed this
+    /// run, checked for finality with `--confirm-commitment-escalation` and
+    /// against the ledger with `--confirm-via-get-block`.
+    confirmed: Mutex<Vec<(String, Signature, Hash)>>,
+}
+
+impl RunContext {
+    fn new(options: &AirdropOptions, paths: OutputPaths, decimals: u8, rpc_url: &str) -> Self {
+        let limited = options.max_tps.is_some() || options.delay_between_sends_jitter.is_some();
+        Self {
+            paths,
+            decimals,
+            rpc_url: rpc_url.to_string(),
+            explorer: options.explorer,
+            webhook: options.webhook_url.as_deref().map(Webhook::new),
+            send_limiter: limited
+                .then(|| SendLimiter::new(options.max_tps, options.delay_between_sends_jitter)),
+            jito: options.jito_url.as_deref().map(JitoClient::new),
+            ..Default::default()
+        }
+    }
+
+    /// How to print `signature`: as an `--explorer` link if one was asked for,
+    /// otherwise in base58.
+    fn show_signature(&self, signature: &Signature) -> String {
+        match self.explorer {
+            Some(explorer) => explorer.transaction_url(signature, &self.rpc_url),
+            None => signature.to_string(),
+        }
+    }
+
+    fn send_limiter(&self) -> Option<&SendLimiter> {
+        self.send_limiter.as_ref()
+    }
+
+    fn webhook(&self) -> Option<&Webhook> {
+        self.webhook.as_ref()
+    }
+
+    fn jito(&self) -> Option<&JitoClient> {
+        self.jito.as_ref()
+    }
+
+    /// Where to broadcast transactions: the `--jito-url` block engine if
+    /// there is one, otherwise `rpc_client`.
+    fn broadcaster<'a>(&'a self, rpc_client: &'a RpcClient) -> Broadcaster<'a> {
+        match self.jito() {
+            Some(jito) => Broadcaster::Jito(JitoSender { rpc_client, jito }),
+            None => Broadcaster::Rpc(rpc_client),
+        }
+    }
+
+    /// Whether `--stop-on-insufficient-balance` left recipients unsent, who
+    /// go to the remaining CSV even if every batch confirms.
+    fn has_cut_off(&self) -> bool {
+        !self.cut_off.lock().unwrap().is_empty()
+    }
+
+    /// Starts tracking the recipients of `batches` as unpaid.
+    fn track_unpaid(&self, batches: &[Batch]) {
+        let mut unpaid = self.unpaid.lock().unwrap();
+        unpaid.recipients = batches
+            .iter()
+            .flat_map(|batch| batch.recipients().cloned())
+            .collect();
+        unpaid.superseded = unpaid
+            .recipients
+            .iter()
+            .map(|recipient| recipient.pubkey)
+            .collect();
+    }
+
+    /// Stops tracking the recipients of `batch` as unpaid.
+    fn mark_paid(&self, batch: &Batch) {
+        let mut unpaid = self.unpaid.lock().unwrap();
+        unpaid
+            .recipients
+            .retain(|recipient| recipient.batch_id.as_deref() != Some(batch.id.as_str()));
+    }
+}
+
+/// See [`RunContext::track_unpaid`].
+#[derive(Debug, Default)]
+struct UnpaidRecipients {
+    /// Everyone the run's batches pay, whose rows in an appended remaining
+    /// CSV this run replaces.
+    superseded: HashSet<Pubkey>,
+    recipients: Vec<Recipient>,
+}
+
+/// A signed transaction together with the transfers it pays out.
+struct Batch {
+    /// Stable id derived from who the batch pays, recorded in every output
+    /// file so a batch can be traced across them.
+    id: String,
+    transaction: Transaction,
+    transfers: Vec<Transfer>,
+    timings: BatchTimings,
+}
+
+impl Batch {
+    fn recipients(&self) -> impl Iterator<Item = &Recipient> {
+        self.transfers.iter().map(|transfer| &transfer.recipient)
+    }
+
+    /// Records this batch's confirmed signature and who it paid, in the
+    /// signature log and, with `--run-id`, the run's state file.
+    fn log_signature(
+        &self,
+        args: &AirdropOptions,
+        run: &RunContext,
+        run_state: &mut Option<RunState>,
+    ) -> Result<(), Box<dyn Error>> {
+        let signature = &self.transaction.signatures[0];
+        if args.confirm_commitment_escalation || args.confirm_via_get_block {
+            run.confirmed.lock().unwrap().push((
+                self.id.clone(),
+                *signature,
+                self.transaction.message.recent_blockhash,
+            ));
+        }
+        if let Some(run_state) = run_state {
+            run_state.record(
+                signature,
+                self.recipients().map(|recipient| &recipient.pubkey),
+            )?;
+        }
+        if let Some(webhook) = run.webhook() {
+            webhook.notify(self.confirmed_notification(run.decimals));
+        }
+        run.mark_paid(self);
+        append_signature_log(
+            &run.paths.signatures,
+            signature,
+            &self.id,
+            self.transfers
+                .iter()
+                .map(|transfer| (&transfer.recipient, transfer.amount)),
+            run.decimals,
+        )
+    }
+
+    /// Records in the signature log that this batch never reached the
+    /// cluster, with the error its send failed with.
+    fn log_send_failure(&self, run: &RunContext, err: &str) -> Result<(), Box<dyn Error>> {
+        append_send_failure_log(
+            &run.paths.signatures,
+            &self.id,
+            self.transfers
+                .iter()
+                .map(|transfer| (&transfer.recipient, transfer.amount)),
+            err,
+            run.decimals,
+        )
+    }
+
+    /// The `--webhook-url` body for this batch confirming.
+    fn confirmed_notification(&self, decimals: u8) -> serde_json::Value {
+        serde_json::json!({
+            "batch_id": self.id,
+            "signature": self.transaction.signatures[0].to_string(),
+            "recipients": self
+                .transfers
+                .iter()
+                .map(|transfer| serde_json::json!({
+                    "address": transfer.recipient.pubkey.to_string(),
+                    "amount": base_units_to_ui_amount(transfer.amount, decimals),
+                    "label": transfer.recipient.label,
+                }))
+                .collect::<Vec<_>>(),
+        })
+    }
+
+    /// The ndjson event for broadcasting this batch with `--no-confirm`.
+    fn sent_event(&self, index: usize) -> Event {
+        Event::Sent {
+            index,
+            batch_id: self.id.clone(),
+            signature: self.transaction.signatures[0].to_string(),
+            recipients: self
+                .recipients()
+                .map(|recipient| recipient.pubkey.to_string())
+                .collect(),
+        }
+    }
+
+    /// The ndjson event for reconciling this batch after it was reported
+    /// failed.
+    fn reconciled_event(&self, index: usize, outcome: Outcome) -> Event {
+        Event::Reconciled {
+            index,
+            batch_id: self.id.clone(),
+            signature: self.transaction.signatures[0].to_string(),
+            outcome,
+            recipients: self
+                .recipients()
+                .map(|recipient| recipient.pubkey.to_string())
+                .collect(),
+        }
+    }
+
+    /// The ndjson event for this batch settling, failed if `error` is set.
+    fn event(&self, index: usize, error: Option<String>) -> Event {
+        let signature = self.transaction.signatures[0].to_string();
+        let recipients = self
+            .recipients()
+            .map(|recipient| recipient.pubkey.to_string())
+            .collect();
+        match error {
+            Some(error) => Event::Failed {
+                index,
+                batch_id: self.id.clone(),
+                signature,
+                error,
+                recipients,
+            },
+            None => Event::Confirmed {
+                index,
+                batch_id: self.id.clone(),
+                signature,
+                recipients,
+            },
+        }
+    }
+}
+
+/// A single recipient's payout within a batch. `amount` is what is actually
+/// sent, which differs from `recipient.amount` with `--target-balance`.
+struct Transfer {
+    recipient: Recipient,
+    destination: Pubkey,
+    amount: u64,
+    /// The token account or, for SOL, the wallet the transfer is paid from.
+    source: Pubkey,
+    creates_ata: bool,
+    /// Accounts appended to the transfer by the hook's extra-meta resolution.
+    extra_accounts: Vec<AccountMeta>,
+}
+
+/// A token account the airdrop sends from, and how much of it this run has
+/// handed out so far.
+#[derive(Clone)]
+struct Source {
+    address: Pubkey,
+    authority: Arc<dyn Signer>,
+    balance: u64,
+    sent: u64,
+    recipients: usize,
+}
+
+impl Source {
+    fn remaining(&self) -> u64 {
+        self.balance.saturating_sub(self.sent)
+    }
+}
+
+/// Whether `sources`, drained in order the way transfers are assigned to
+/// them, can pay every one of `parts`.
+fn can_cover(sources: &[Source], parts: &[u64]) -> bool {
+    let mut remaining: Vec<u64> = sources.iter().map(Source::remaining).collect();
+    let mut current = 0;
+    for part in parts {
+        while current + 1 < remaining.len() && remaining[current] < *part {
+            current += 1;
+        }
+        match remaining.get_mut(current) {
+            Some(left) if *left >= *part => *left -= part,
+            _ => return false,
+        }
+    }
+    true
+}
+
+#[derive(Parser, Debug)]
+struct SnapshotArgs {
+    #[arg(
+        value_name = "MINT_ADDRESS",
+        help = "The address of the token whose holders to snapshot"
+    )]
+    pub mint_address: String,
+
+    #[arg(
+        value_name = "OUTPUT_CSV_PATH",
+        help = "Where to write the recipients CSV"
+    )]
+    pub output_csv_path: String,
+
+    #[arg(
+        long,
+        value_name = "AMOUNT",
+        help = "Minimum balance an owner must hold, in base units",
+        default_value_t = 0
+    )]
+    pub min_balance: u64,
+
+    #[arg(
+        long,
+        help = "Split the account query into pages by owner prefix, for mints with many holders"
+    )]
+    pub paginate: bool,
+}
+
+#[derive(Parser, Debug)]
+struct MintInfoArgs {
+    #[arg(value_name = "TOKEN_ADDRESS", help = "The mint to describe")]
+    pub token_address: Pubkey,
+}
+
+#[derive(Parser, Debug)]
+struct LintArgs {
+    #[arg(
+        value_name = "RECIPIENTS_CSV_PATH",
+        help = "The recipients CSV to check"
+    )]
+    pub recipients_csv_path: String,
+
+    #[arg(
+        long,
+        value_name = "OUTPUT_PATH",
+        help = "Write a cleaned copy of the CSV [default: <RECIPIENTS_CSV_PATH>.fixed.csv]",
+        num_args = 0..=1
+    )]
+    pub fix: Option<Option<String>>,
+}
+
+#[derive(Parser, Debug)]
+struct RemoveArgs {
+    #[arg(value_name = "ADDRESS", help = "The recipient to remove")]
+    pub address: Pubkey,
+
+    #[arg(
+        long,
+        value_name = "PATH",
+        help = "The CSV to remove them from",
+        default_value = REMAINING_RECIPIENTS_PATH
+    )]
+    pub from: String,
+}
+
+#[derive(Parser, Debug)]
+struct CompareArgs {
+    #[arg(value_name = "OLD_CSV_PATH", help = "The earlier recipients CSV")]
+    pub old_csv_path: String,
+
+    #[arg(
+        value_name = "NEW_CSV_PATH",
+        help = "The recipients CSV to compare it with"
+    )]
+    pub new_csv_path: String,
+
+    #[arg(
+        long,
+        value_name = "PATH",
+        help = "The CSV to write the differences to",
+        default_value = "recipients_diff.csv"
+    )]
+    pub out: String,
+
+    #[arg(
+        long,
+        value_name = "DECIMALS",
+        help = "Decimal places the amounts are read with",
+        default_value_t = TOKEN_DECIMALS
+    )]
+    pub decimals: u8,
+}
+
+#[derive(Parser, Debug)]
+struct RequeueArgs {
+    #[arg(value_name = "ADDRESS", help = "The recipient to requeue")]
+    pub address: Pubkey,
+
+    #[arg(
+        long,
+        value_name = "PATH",
+        help = "The CSV to take them from",
+        default_value = PERMANENTLY_FAILED_PATH
+    )]
+    pub from: String,
+
+    #[arg(
+        long,
+        value_name = "PATH",
+        help = "The CSV to add them to, created if missing",
+        default_value = REMAINING_RECIPIENTS_PATH
+    )]
+    pub to: String,
+}
+
+async fn load_config(args: &Args) -> Result<solana_cli_config::Config, Box<dyn Error>> {
+    if let Some(config_file) = &args.config_file {
+        // An explicitly requested config file has to be usable, unlike the
+        // default one, which may simply not exist yet.
+        solana_cli_config::Config::load(config_file).map_err(|err| {
+            match err.kind() {
+                std::io::ErrorKind::NotFound => {
+                    format!("Config file not found at {}", config_file)
+                }
+                _ => format!("Invalid config file at {}: {}", config_file, err),
+            }
+            .into()
+        })
+    } else if let Some(config_file) = &*solana_cli_config::CONFIG_FILE {
+        Ok(solana_cli_config::Config::load(config_file).unwrap_or_default())
+    } else {
+        Ok(solana_cli_config::Config::default())
+    }
+}
+
+/// The config file `load_config` reads, if any.
+fn config_file_path(args: &Args) -> Option<&str> {
+    args.config_file
+        .as_deref()
+        .or(solana_cli_config::CONFIG_FILE.as_deref())
+}
+
+/// Adds the USD price settings to a `--dump-config` command.
+fn add_price_config(command: &mut serde_json::Value, recipients: &RecipientsArgs) {
+    command["price"] = recipients.price.map(|price| price.to_string()).into();
+    command["price_url"] = recipients.price_url.clone().into();
+    command["price_field"] = recipients.price_field.clone().into();
+}
+
+/// Prints the settings a run would use once flags, the config file and
+/// defaults have been resolved.
+fn dump_config(
+    config_file: Option<&str>,
+    rpc_urls: &[String],
+    rpc_timeout_secs: u64,
+    websocket_url: Option<&str>,
+    keypair_path: &str,
+    output_paths: &OutputPaths,
+    command: &Commands,
+) -> Result<(), Box<dyn Error>> {
+    let command = match command {
+        Commands::Airdrop(args) => {
+            let mut command = options_config("airdrop", &args.options, output_paths);
+            command["token_address"] = args.token_address.clone().into();
+            command["recipients_csv_path"] = args.recipients.recipients_csv_path.clone().into();
+            command["amount"] = args.recipients.amount.clone().into();
+            command["override_csv_amounts"] = args.recipients.override_csv_amounts.into();
+            add_price_config(&mut command, &args.recipients);
+            command["max_instructions_per_tx"] = MAX_INSTRUCTIONS_PER_TX.into();
+            add_token_config(&mut command, &args.token);
+            command
+        }
+        Commands::AirdropSol(args) => {
+            let mut command = options_config("airdrop-sol", &args.options, output_paths);
+            command["recipients_csv_path"] = args.recipients.recipients_csv_path.clone().into();
+            command["amount"] = args.recipients.amount.clone().into();
+            command["override_csv_amounts"] = args.recipients.override_csv_amounts.into();
+            add_price_config(&mut command, &args.recipients);
+            command["max_transfers_per_tx"] = MAX_SOL_TRANSFERS_PER_TX.into();
+            command
+        }
+        Commands::RetryFailed(args) => {
+            let mut command = options_config("retry-failed", &args.options, output_paths);
+            command["token_address"] = args.token_address.clone().into();
+            command["from"] = args.from.clone().into();
+            command["escalate_fee"] = args.escalate_fee.into();
+            command["max_instructions_per_tx"] = MAX_INSTRUCTIONS_PER_TX.into();
+            add_token_config(&mut command, &args.token);
+            command
+        }
+        Commands::Snapshot(args) => serde_json::json!({
+            "name": "snapshot",
+            "mint_address": args.mint_address,
+            "output_csv_path": args.output_csv_path,
+            "min_balance": args.min_balance,
+            "paginate": args.paginate,
+        }),
+        Commands::MintInfo(args) => serde_json::json!({
+            "name": "mint-info",
+            "token_address": args.token_address.to_string(),
+        }),
+        Commands::Lint(args) => serde_json::json!({
+            "name": "lint",
+            "recipients_csv_path": args.recipients_csv_path,
+            "fix": match &args.fix {
+                Some(Some(path)) => serde_json::Value::from(path.clone()),
+                Some(None) => true.into(),
+                None => serde_json::Value::Null,
+            },
+        }),
+        Commands::Remove(args) => serde_json::json!({
+            "name": "remove",
+            "address": args.address.to_string(),
+            "from": args.from,
+        }),
+        Commands::Requeue(args) => serde_json::json!({
+            "name": "requeue",
+            "address": args.address.to_string(),
+            "from": args.from,
+            "to": args.to,
+        }),
+        Commands::Compare(args) => serde_json::json!({
+            "name": "compare",
+            "old_csv_path": args.old_csv_path,
+            "new_csv_path": args.new_csv_path,
+            "out": args.out,
+            "decimals": args.decimals,
+        }),
+    };
+
+    let config = serde_json::json!({
+        "config_file": config_file,
+        "json_rpc_url": rpc_urls[0],
+        "fallback_rpc_urls": rpc_urls[1..],
+        "rpc_timeout_secs": rpc_timeout_secs,
+        "websocket_url": websocket_url,
+        "keypair_path": keypair_path,
+        "commitment": CommitmentConfig::confirmed().commitment.to_string(),
+        "command": command,
+    });
+    println!("{}", serde_json::to_string_pretty(&config)?);
+    Ok(())
+}
+
+/// The resolved `AirdropOptions` of a subcommand, for `dump_config`.
+fn options_config(name: &str, options: &AirdropOptions, paths: &OutputPaths) -> serde_json::Value {
+    serde_json::json!({
+        "name": name,
+        "recipients_glob": options.recipients_glob,
+        "priority_fee": options.priority_fee,
+        "fee_source": options.fee_source.as_ref().map(|source| source.to_string()),
+        "fee_field": options.fee_field,
+        "fee_schedule": options.fee_schedule,
+        "cu_limit": options.cu_limit,
+        "shuffle": options.shuffle,
+        "seed": options.seed,
+        "allow_self_transfer": options.allow_self_transfer,
+        "expected_total": options.expected_total,
+        "max_recipients": options.max_recipients,
+        "expected_total_tolerance": options.expected_total_tolerance,
+        "expect_hash": options.expect_hash,
+        "min_sol_reserve": options.min_sol_reserve,
+        "max_priority_cost": options.max_priority_cost,
+        "yes": options.yes,
+        "skip_balance_check": options.skip_balance_check,
+        "max_attempts": options.max_attempts,
+        "plan_out": options.plan_out,
+        "preflight_only": options.preflight_only,
+        "batch_report": match &options.batch_report {
+            Some(Some(path)) => path.as_str().into(),
+            Some(None) => true.into(),
+            None => serde_json::Value::Null,
+        },
+        "fire_and_forget": options.fire_and_forget,
+        "no_confirm": options.no_confirm,
+        "max_tx_in_flight": options.max_tx_in_flight,
+        "checkpoint_every": options.checkpoint_every,
+        "resume_from_batch": options.resume_from_batch,
+        "continue_on_error": options.continue_on_error,
+        "confirm_in_background": options.confirm_in_background,
+        "append_remaining": options.append_remaining,
+        "remaining_path": options.remaining_path,
+        "output_dir": options.output_dir,
+        "permanently_failed_path": paths.permanently_failed,
+        "signatures_path": paths.signatures,
+        "attempts_path": paths.attempts,
+        "output_template": options.output_template,
+        "run_id": options.run_id,
+        "state_path": options.state_path,
+        "prior_signatures": options.prior_signatures,
+        "timings": options.timings,
+        "report_failures_only": options.report_failures_only,
+        "explorer": options
+            .explorer
+            .map(|explorer| format!("{:?}", explorer).to_lowercase()),
+        "webhook_url": options.webhook_url,
+        "send_retries": options.send_retries,
+        "max_tps": options.max_tps,
+        "delay_between_sends_jitter": options
+            .delay_between_sends_jitter
+            .map(|jitter| jitter.to_string()),
+        "jito_url": options.jito_url,
+        "jito_tip_lamports": options.jito_tip_lamports,
+        "confirm_retries": options.confirm_retries,
+        "reconcile_retries": options.reconcile_retries,
+        "confirm_commitment_escalation": options.confirm_commitment_escalation,
+        "confirm_via_get_block": options.confirm_via_get_block,
+        "poll_interval_ms": options.poll_interval_ms,
+        "max_poll_interval_ms": options.max_poll_interval_ms,
+        "label_column": options.label_column.as_ref().map(|column| match column {
+            LabelColumn::Index(index) => index.to_string(),
+            LabelColumn::Name(name) => name.clone(),
+        }),
+        "decimals_column": options.decimals_column.as_ref().map(|column| match column {
+            LabelColumn::Index(index) => index.to_string(),
+            LabelColumn::Name(name) => name.clone(),
+        }),
+        "output_format": format!("{:?}", options.output_format).to_lowercase(),
+    })
+}
+
+/// Adds the resolved `TokenOptions` to a subcommand's `dump_config` output.
+fn add_token_config(command: &mut serde_json::Value, token: &TokenOptions) {
+    command["target_balance"] = token.target_balance.into();
+    command["ata_owner"] = token.ata_owner.map(|owner| owner.to_string()).into();
+    command["verify_before_resume"] = token.verify_before_resume.into();
+    command["memo"] = token.memo.clone().into();
+    command["check_hook_accounts"] = token.check_hook_accounts.into();
+    command["amount_per_transfer_cap"] = token.amount_per_transfer_cap.clone().into();
+    command["account_commitment"] = token.account_commitment.to_string().into();
+    command["ata_program"] = token.ata_program.to_string().into();
+    command["token_account_size"] = token.token_account_size.into();
+    command["transfer_authority"] = token
+        .transfer_authority
+        .map(|authority| authority.to_string())
+        .into();
+    command["signers"] = token.signer.clone().into();
+    command["as_delegate"] = token.as_delegate.into();
+    command["separate_ata_creation"] = token.separate_ata_creation.into();
+    command["only_missing_atas"] = token.only_missing_atas.into();
+    command["min_recipient_balance_sol"] = token.min_recipient_balance_sol.clone().into();
+    command["extra_accounts"] = token
+        .extra_account
+        .iter()
+        .map(ExtraAccount::to_string)
+        .collect::<Vec<_>>()
+        .into();
+    command["source_token_accounts"] = token
+        .source_token_account
+        .iter()
+        .map(SourceTokenAccount::to_string)
+        .collect::<Vec<_>>()
+        .into();
+}
+
+const MAX_INSTRUCTIONS_PER_TX: usize = 4;
+/// Idempotent ATA creates share the payer, mint and programs, so many fit in
+/// a transaction once they don't travel with a hooked transfer each.
+const MAX_ATA_CREATES_PER_TX: usize = 10;
+/// System transfers are small and need no extra accounts, so far more fit in
+/// a transaction than hooked token transfers.
+const MAX_SOL_TRANSFERS_PER_TX: usize = 20;
+const SOL_DECIMALS: u8 = 9;
+/// Most airdrops are well under this. A file far bigger than expected is
+/// more likely the wrong file than a deliberate run, so going above it takes
+/// `--max-recipients`.
+const DEFAULT_MAX_RECIPIENTS: usize = 10_000;
+
+/// Reads the recipients CSV, checks it against `--max-recipients`, then
+/// applies `--shuffle` and drops the source wallet unless
+/// `--allow-self-transfer` is set, along with anyone owed nothing.
+async fn prepare_recipients(
+    source: &RecipientsArgs,
+    options: &AirdropOptions,
+    source_pubkey: &Pubkey,
+    decimals: u8,
+) -> Result<Vec<Recipient>, Box<dyn Error>> {
+    // With a price, amounts are read as USD and converted once checked.
+    let price = match (&source.price, &source.price_url) {
+        (Some(price), _) => Some(*price),
+        (None, Some(url)) => {
+            let price = UsdPrice::fetch(url, source.price_field.as_deref()).await?;
+            progress!("Price from {}: ${}", url, price);
+            Some(price)
+        }
+        (None, None) => None,
+    };
+    let amount_decimals = if price.is_some() {
+        USD_DECIMALS
+    } else {
+        decimals
+    };
+    let amount = source
+        .amount
+        .as_deref()
+        .map(|amount| ui_amount_to_base_units(amount, amount_decimals))
+        .transpose()?;
+    let patterns: Vec<&str> = std::iter::once(source.recipients_csv_path.as_str())
+        .chain(options.recipients_glob.iter().map(String::as_str))
+        .collect();
+    let paths = expand_recipient_paths(&patterns)?;
+    if amount.is_some() {
+        for path in paths.iter() {
+            if !has_csv_amounts(path)? {
+                continue;
+            }
+            if !source.override_csv_amounts {
+                return Err(format!(
+                    "{} has its own amounts, which AMOUNT would replace; pass --override-csv-amounts to send AMOUNT to everyone anyway",
+                    path
+                )
+                .into());
+            }
+            progress!(
+                "Ignoring the amount column of {} because AMOUNT was given ⚠️",
+                path
+            );
+        }
+    }
+    let mut recipients = read_recipients_csvs(
+        &paths,
+        amount_decimals,
+        amount,
+        options.label_column.as_ref(),
+        options.decimals_column.as_ref(),
+    )?;
+    if recipients.len() > options.max_recipients {
+        return Err(format!(
+            "The recipients CSV lists {} recipients, more than the limit of {}; pass --max-recipients {} or higher if that many is intended",
+            recipients.len(),
+            options.max_recipients,
+            recipients.len()
+        )
+        .into());
+    }
+    if let Some(expected_total) = &options.expected_total {
+        check_expected_total(
+            &recipients,
+            expected_total,
+            options.expected_total_tolerance.as_deref(),
+            amount_decimals,
+        )?;
+    }
+    if let Some(price) = price {
+        convert_usd_amounts(&mut recipients, price, decimals)?;
+    }
+    if options.shuffle {
+        let seed = options.seed.unwrap_or_else(|| rand::thread_rng().gen());
+        progress!("Shuffling recipients with seed {}", seed);
+        recipients.shuffle(&mut StdRng::seed_from_u64(seed));
+    }
+
+    if let Some(run_state) = options.run_state()? {
+        let recipient_count = recipients.len();
+        recipients.retain(|recipient| !run_state.is_confirmed(&recipient.pubkey));
+        let skipped = recipient_count - recipients.len();
+        if run_state.confirmed_count() > 0 {
+            progress!(
+                "Skipping {} recipient(s) already confirmed by run {}",
+                skipped,
+                options.run_id.as_deref().unwrap_or_default()
+            );
+        }
+    }
+
+    if !options.allow_self_transfer {
+        let recipient_count = recipients.len();
+        recipients.retain(|recipient| recipient.pubkey != *source_pubkey);
+        let skipped = recipient_count - recipients.len();
+        if skipped > 0 {
+            progress!(
+                "Skipping {} recipient(s) equal to the source wallet ⚠️ (use --allow-self-transfer to keep them)",
+                skipped
+            );
+        }
+    }
+
+    // Nothing would be sent to them, but they could still cost an ATA create
+    // and a slot in a transaction.
+    let recipient_count = recipients.len();
+    recipients.retain(|recipient| recipient.amount > 0);
+    let skipped = recipient_count - recipients.len();
+    if skipped > 0 {
+        progress!(
+            "Skipping {} recipient(s) with an amount of 0, which would be an empty transfer",
+            skipped
+        );
+    }
+
+    progress!(
+        "Recipients: {:?}",
+        recipients
+            .iter()
+            .map(|recipient| recipient.pubkey)
+            .collect::<Vec<_>>()
+    );
+    if let Some(amount) = &source.amount {
+        progress!("Amount: {}", amount);
+    }
+    check_total_fits(&recipients)?;
+
+    Ok(recipients)
+}
+
+/// Replaces each recipient's USD amount with what it buys at `price`,
+/// printing both so the conversion can be checked before anything is sent.
+fn convert_usd_amounts(
+    recipients: &mut [Recipient],
+    price: UsdPrice,
+    decimals: u8,
+) -> Result<(), Box<dyn Error>> {
+    let mut usd_total: u64 = 0;
+    let mut token_total: u64 = 0;
+    for recipient in recipients.iter_mut() {
+        let usd = recipient.amount;
+        recipient.amount = price.usd_to_base_units(usd, decimals).map_err(|err| {
+            format!(
+                "Could not convert the amount for {}: {}",
+                recipient.pubkey, err
+            )
+        })?;
+        progress!(
+            "{}: ${} = {}",
+            recipient.pubkey,
+            base_units_to_ui_amount(usd, USD_DECIMALS),
+            base_units_to_ui_amount(recipient.amount, decimals)
+        );
+        usd_total = usd_total.saturating_add(usd);
+        token_total = token_total.saturating_add(recipient.amount);
+    }
+    progress!(
+        "Converted ${} into {} at ${} each, rounding each amount down",
+        base_units_to_ui_amount(usd_total, USD_DECIMALS),
+        base_units_to_ui_amount(token_total, decimals),
+        price
+    );
+    Ok(())
+}
+
+/// Fails if the recipient amounts add up to more than a `u64` of base units,
+/// which no mint or wallet can hold. The running totals later on saturate
+/// rather than overflow, so without this check they would quietly report
+/// `u64::MAX` instead of the real total.
+fn check_total_fits(recipients: &[Recipient]) -> Result<(), Box<dyn Error>> {
+    let mut total: u64 = 0;
+    for (index, recipient) in recipients.iter().enumerate() {
+        total = total.checked_add(recipient.amount).ok_or_else(|| {
+            format!(
+                "The recipient amounts add up to more than {} base units, the most a token amount can be, by recipient {} ({}); check the CSV's amounts and decimals",
+                u64::MAX,
+                index + 1,
+                recipient.pubkey
+            )
+        })?;
+    }
+    Ok(())
+}
+
+/// Checks that the recipient amounts add up to `expected_total`, give or take
+/// `tolerance`, so a file cut off mid-export is caught before anything is sent.
+fn check_expected_total(
+    recipients: &[Recipient],
+    expected_total: &str,
+    tolerance: Option<&str>,
+    decimals: u8,
+) -> Result<(), Box<dyn Error>> {
+    let expected = ui_amount_to_base_units(expected_total, decimals)
+        .map_err(|err| format!("Invalid --expected-total: {}", err))?;
+    let tolerance = tolerance
+        .map(|tolerance| ui_amount_to_base_units(tolerance, decimals))
+        .transpose()
+        .map_err(|err| format!("Invalid --expected-total-tolerance: {}", err))?
+        .unwrap_or(0);
+    let total: u128 = recipients
+        .iter()
+        .map(|recipient| recipient.amount as u128)
+        .sum();
+
+    if total.abs_diff(expected as u128) > tolerance as u128 {
+        return Err(format!(
+            "Recipient amounts add up to {}, but --expected-total is {} (tolerance {}); is the CSV complete?",
+            base_units_to_ui_amount(u64::try_from(total).unwrap_or(u64::MAX), decimals),
+            base_units_to_ui_amount(expected, decimals),
+            base_units_to_ui_amount(tolerance, decimals)
+        )
+        .into());
+    }
+    progress!(
+        "Recipient amounts add up to {}, matching --expected-total",
+        base_units_to_ui_amount(u64::try_from(total).unwrap_or(u64::MAX), decimals)
+    );
+    Ok(())
+}
+
+/// The compute budget instructions prepended to every transaction, looking
+/// the price up first if `--fee-source` is set. A zero price or limit is left
+/// out entirely rather than set, to save transaction space.
+async fn budget_instructions(
+    options: &AirdropOptions,
+    run: &RunContext,
+    rpc_client: &RpcClient,
+    fee_payer: &Pubkey,
+    writable_accounts: &[Pubkey],
+) -> Result<Vec<Instruction>, Box<dyn Error>> {
+    let priority_fee = match &options.fee_source {
+        Some(fee_source) => {
+            resolve_priority_fee(
+                fee_source,
+                options.fee_field.as_deref(),
+                rpc_client,
+                writable_accounts,
+                options.priority_fee.unwrap_or_default(),
+            )
+            .await
+        }
+        None => options.priority_fee.unwrap_or_default(),
+    }
+    .saturating_add(run.fee_escalation);
+    check_priority_cost(options, priority_fee)?;
+    if let Some(schedule) = &options.fee_schedule {
+        let max_price = schedule.max_price().saturating_add(run.fee_escalation);
+        if max_price > priority_fee {
+            progress!("The --fee-schedule goes up to {} microlamports", max_price);
+            check_priority_cost(options, max_price)?;
+        }
+    }
+
+    let mut instructions = Vec::new();
+    if priority_fee > 0 {
+        instructions.push(ComputeBudgetInstruction::set_compute_unit_price(
+            priority_fee,
+        ));
+    }
+    if options.cu_limit > 0 {
+        instructions.push(ComputeBudgetInstruction::set_compute_unit_limit(
+            options.cu_limit,
+        ));
+    }
+    // Every bundle is a single transaction, so each one carries its own tip.
+    if let Some(jito) = run.jito() {
+        let tip_account = jito
+            .tip_account()
+            .await
+            .map_err(|err| format!("Could not get a Jito tip account: {}", err))?;
+        progress!(
+            "Tipping {} ({} lamports per transaction)",
+            tip_account,
+            options.jito_tip_lamports
+        );
+        instructions.push(system_instruction::transfer(
+            fee_payer,
+            &tip_account,
+            options.jito_tip_lamports,
+        ));
+    }
+    Ok(instructions)
+}
+
+/// `budget_instructions` for the `number`th transaction built, counting from
+/// 1, with its `--fee-schedule` price in place of the static one if the
+/// schedule covers it. `--escalate-fee` still applies on top.
+fn scheduled_budget(
+    options: &AirdropOptions,
+    run: &RunContext,
+    budget_instructions: &[Instruction],
+    number: usize,
+) -> Vec<Instruction> {
+    let Some(price) = options
+        .fee_schedule
+        .as_ref()
+        .and_then(|schedule| schedule.price_for(number))
+    else {
+        return budget_instructions.to_vec();
+    };
+    let price = price.saturating_add(run.fee_escalation);
+    let mut instructions: Vec<Instruction> = budget_instructions
+        .iter()
+        .filter(|instruction| !is_compute_unit_price(instruction))
+        .cloned()
+        .collect();
+    if price > 0 {
+        instructions.insert(0, ComputeBudgetInstruction::set_compute_unit_price(price));
+    }
+    instructions
+}
+
+/// Shows what `priority_fee` microlamports per compute unit costs per
+/// transaction at the `--cu-limit`, and asks before going on if that's over
+/// `--max-priority-cost`. A limit of 0 is costed at the most a transaction
+/// can use. This catches a price entered in lamports instead of
+/// microlamports, which costs a million times more than intended.
+fn check_priority_cost(options: &AirdropOptions, priority_fee: u64) -> Result<(), Box<dyn Error>> {
+    if priority_fee == 0 {
+        return Ok(());
+    }
+    let compute_units = match options.cu_limit {
+        0 => MAX_CU_LIMIT,
+        cu_limit => cu_limit,
+    };
+    let cost = (priority_fee as u128 * compute_units as u128).div_ceil(1_000_000);
+    let cost = u64::try_from(cost).unwrap_or(u64::MAX);
+    progress!(
+        "Priority fee: {} SOL per transaction ({} microlamports × {} CU)",
+        base_units_to_ui_amount(cost, SOL_DECIMALS),
+        priority_fee,
+        compute_units
+    );
+
+    let max_cost = ui_amount_to_base_units(&options.max_priority_cost, SOL_DECIMALS)
+        .map_err(|err| format!("Invalid --max-priority-cost: {}", err))?;
+    if cost <= max_cost {
+        return Ok(());
+    }
+    let warning = format!(
+        "⚠️  The priority fee of {} microlamports per CU costs {} SOL per transaction, over --max-priority-cost of {} SOL. --priority-fee is in microlamports, not lamports.",
+        priority_fee,
+        base_units_to_ui_amount(cost, SOL_DECIMALS),
+        options.max_priority_cost
+    );
+    if options.yes {
+        progress!("{}", warning);
+        return Ok(());
+    }
+    println!("{}", warning);
+    // A preflight gate must not wait for an answer nobody is there to give.
+    if options.preflight_only || !stdin().is_terminal() {
+        return Err("Pass --yes to send with this priority fee".into());
+    }
+    print!("Continue? [y/N] ");
+    stdout().flush()?;
+    let mut answer = String::new();
+    stdin().read_line(&mut answer)?;
+    if matches!(answer.trim(), "y" | "Y" | "yes") {
+        Ok(())
+    } else {
+        Err("Aborted".into())
+    }
+}
+
+/// What building the batches for `airdrop` needs that is only worked out
+/// once, however many times they are built.
+struct TokenAirdrop {
+    /// Who is paid, after skipping prior payments.
+    recipients: Vec<Recipient>,
+    /// How many recipients were read, before any were skipped.
+    recipient_count: usize,
+    recipients_hash: String,
+    token_pubkey: Pubkey,
+    mint_account: Account,
+    hook_program: Option<Pubkey>,
+    program_client: Arc<dyn ProgramClient<ProgramRpcClientSendTransaction>>,
+    sources: Vec<Source>,
+    budget_instructions: Vec<Instruction>,
+}
+
+/// Reads and prices the recipients for `airdrop` and checks the mint, its
+/// transfer hook and the source accounts.
+async fn prepare_airdrop(
+    args: &AirdropArgs,
+    run: &RunContext,
+    rpc_client: &Arc<RpcClient>,
+    source_keypair: &Arc<dyn Signer>,
+) -> Result<TokenAirdrop, Box<dyn Error>> {
+    let source_pubkey = &source_keypair.pubkey();
+    let decimals = run.decimals;
+    let recipients = prepare_recipients(
+        &args.recipients,
+        &args.options,
+        args.token
+            .transfer_authority
+            .as_ref()
+            .unwrap_or(source_pubkey),
+        decimals,
+    )
+    .await?;
+    let recipients_hash = check_recipients_hash(&args.options, &recipients)?;
+    let recipient_count = recipients.len();
+    let mut recipients = match &args.options.prior_signatures {
+        Some(path) => skip_prior_payments(rpc_client, path, recipients, decimals).await?,
+        None => recipients,
+    };
+    if let Some(ata_owner) = args.token.ata_owner {
+        for recipient in recipients.iter_mut() {
+            recipient.ata_owner = Some(ata_owner);
+        }
+    }
+
+    let token_pubkey = Pubkey::from_str(&args.token_address)
+        .map_err(|_| format!("Invalid token address {}", args.token_address))?;
+    let mint_account = check_mint(rpc_client, &token_pubkey).await?;
+    let hook_program = hook::check_transfer_hook(rpc_client, &token_pubkey).await?;
+
+    progress!("Source: {:?}", source_keypair.pubkey());
+    progress!("Token: {:?}", token_pubkey);
+
+    let program_client: Arc<dyn ProgramClient<ProgramRpcClientSendTransaction>> = Arc::new(
+        ProgramRpcClient::new(rpc_client.clone(), ProgramRpcClientSendTransaction),
+    );
+
+    let sources = load_sources(
+        args,
+        run,
+        rpc_client,
+        source_keypair,
+        &token_pubkey,
+        &args.token.ata_program,
+    )
+    .await?;
+
+    let mut writable_accounts = vec![token_pubkey];
+    writable_accounts.extend(sources.iter().map(|source| source.address));
+    let budget_instructions = budget_instructions(
+        &args.options,
+        run,
+        rpc_client,
+        source_pubkey,
+        &writable_accounts,
+    )
+    .await?;
+
+    Ok(TokenAirdrop {
+        recipients,
+        recipient_count,
+        recipients_hash,
+        token_pubkey,
+        mint_account,
+        hook_program,
+        program_client,
+        sources,
+        budget_instructions,
+    })
+}
+
+/// Builds the batches for `airdrop` from what [`prepare_airdrop`] worked out,
+/// with fresh blockhashes and the ATAs as they are now. With `separate_atas`,
+/// missing ATAs are created by batches of their own, which come first and pay
+/// no one, and the transfers after them assume the ATAs exist.
+async fn create_airdrop_tx(
+    args: &AirdropArgs,
+    run: &RunContext,
+    rpc_client: &Arc<RpcClient>,
+    source_keypair: &Arc<dyn Signer>,
+    airdrop: &TokenAirdrop,
+    separate_atas: bool,
+) -> Result<Vec<Batch>, Box<dyn Error>> {
+    let source_pubkey = &source_keypair.pubkey();
+    let decimals = run.decimals;
+    let TokenAirdrop {
+        recipients,
+        recipient_count,
+        recipients_hash,
+        token_pubkey,
+        mint_account,
+        hook_program,
+        program_client,
+        budget_instructions,
+        ..
+    } = airdrop;
+    let (recipient_count, token_pubkey, hook_program) =
+        (*recipient_count, *token_pubkey, *hook_program);
+    // Building again starts over from the full balances.
+    let mut sources = airdrop.sources.clone();
+    // Only the recipients this build cuts off are left for later.
+    run.cut_off.lock().unwrap().clear();
+
+    let mut batches: Vec<Batch> = Vec::new();
+    let mut instructions: Vec<Instruction> = Vec::new();
+    let mut batch_transfers: Vec<Transfer> = Vec::new();
+
+    let ata_program = &args.token.ata_program;
+    let mut current_source = 0;
+    let mut shortfall = false;
+
+    let account_commitment = CommitmentConfig {
+        commitment: args.token.account_commitment,
+    };
+    let current_balances = if args.token.target_balance || args.token.verify_before_resume {
+        let destinations: Vec<Pubkey> = recipients
+            .iter()
+            .map(|recipient| ata_address(ata_program, recipient.token_owner(), &token_pubkey))
+            .collect();
+        fetch_token_balances(rpc_client, &destinations, account_commitment).await?
+    } else {
+        Vec::new()
+    };
+    let min_wallet_balance = args
+        .token
+        .min_recipient_balance_sol
+        .as_deref()
+        .map(|balance| ui_amount_to_base_units(balance, SOL_DECIMALS))
+        .transpose()
+        .map_err(|err| format!("Invalid --min-recipient-balance-sol: {}", err))?;
+    let wallet_balances = match min_wallet_balance {
+        Some(_) => {
+            let wallets: Vec<Pubkey> = recipients
+                .iter()
+                .map(|recipient| *recipient.token_owner())
+                .collect();
+            fetch_sol_balances(rpc_client, &wallets, account_commitment).await?
+        }
+        None => Vec::new(),
+    };
+    let transfer_cap = args
+        .token
+        .amount_per_transfer_cap
+        .as_deref()
+        .map(|cap| ui_amount_to_base_units(cap, decimals))
+        .transpose()
+        .map_err(|err| format!("Invalid --amount-per-transfer-cap: {}", err))?;
+    if transfer_cap == Some(0) {
+        return Err("--amount-per-transfer-cap must be more than 0".into());
+    }
+    let mut topped_up_wallets: HashSet<Pubkey> = HashSet::new();
+    let mut top_ups: u64 = 0;
+
+    let mut already_at_target = 0;
+    let mut already_received = 0;
+    let mut scheduled_atas: HashSet<Pubkey> = HashSet::new();
+    let mut duplicate_creates = 0;
+    let mut memo_required: Vec<Pubkey> = Vec::new();
+    let mut wrong_program: Vec<Pubkey> = Vec::new();
+    let mut hook_rejected: Vec<(Pubkey, Vec<Pubkey>)> = Vec::new();
+    let mut split_recipients = 0;
+    let mut split_transfers = 0;
+    // Whether each hook account looked up so far exists, so accounts every
+    // transfer shares are only fetched once.
+    let mut hook_accounts: HashMap<Pubkey, bool> = HashMap::new();
+    // With `separate_atas`, the creates, the ATAs they create and whose they
+    // are.
+    let mut ata_creations: Vec<(Instruction, Pubkey, Recipient)> = Vec::new();
+
+    for (index, recipient) in recipients.iter().enumerate() {
+        // A transaction that timed out may still have landed after the
+        // remaining CSV was written, so don't pay anyone who already holds
+        // what they were owed.
+        if args.token.verify_before_resume && current_balances[index] >= recipient.amount {
+            progress!(
+                "Skipping {}: already holds {} of the {} owed",
+                recipient.pubkey,
+                base_units_to_ui_amount(current_balances[index], decimals),
+                base_units_to_ui_amount(recipient.amount, decimals)
+            );
+            already_received += 1;
+            continue;
+        }
+
+        let amount = if args.token.target_balance {
+            recipient.amount.saturating_sub(current_balances[index])
+        } else {
+            recipient.amount
+        };
+        if args.token.target_balance && amount == 0 {
+            already_at_target += 1;
+            continue;
+        }
+
+        let parts = split_transfer(amount, transfer_cap);
+        if args.token.stop_on_insufficient_balance && !can_cover(&sources[current_source..], &parts)
+        {
+            let cut_off = &recipients[index..];
+            if args.options.output_format == OutputFormat::Ndjson {
+                events::emit(&Event::CutOff {
+                    index,
+                    recipient: recipient.pubkey.to_string(),
+                    amount: base_units_to_ui_amount(amount, decimals),
+                    cut_off: cut_off.len(),
+                })?;
+            } else {
+                println!(
+                    "The source can't cover the {} for recipient {} of {} ({}): stopping there, and {} recipient(s) from there on will be written to {}",
+                    base_units_to_ui_amount(amount, decimals),
+                    index + 1,
+                    recipients.len(),
+                    recipient.pubkey,
+                    cut_off.len(),
+                    args.options.remaining_path
+                );
+            }
+            run.cut_off.lock().unwrap().extend_from_slice(cut_off);
+            break;
+        }
+
+        let mut recipient_instructions: Vec<Instruction> = Vec::new();
+
+        let destination = ata_address(ata_program, recipient.token_owner(), &token_pubkey);
+        progress!("Destination ATA: {}", destination);
+
+        // An ATA that an earlier recipient already creates, e.g. with a shared
+        // ATA owner, doesn't need another create, and reading it back from the
+        // RPC could return stale state.
+        let mut already_scheduled = scheduled_atas.contains(&destination);
+        if already_scheduled {
+            duplicate_creates += 1;
+        }
+        let existing_account = if already_scheduled {
+            None
+        } else {
+            fetch_account(rpc_client, &destination, account_commitment).await?
+        };
+        // The address is derived for Token-2022, so an account there owned
+        // by any other program, such as a classic SPL Token account created
+        // by mistake, can neither be paid nor replaced by a create.
+        if let Some(account) = existing_account
+            .as_ref()
+            .filter(|account| account.owner != spl_token_2022::id())
+        {
+            progress!(
+                "Skipping {}: {} is owned by {}, not the Token-2022 program, so the transfer would fail ⚠️",
+                recipient.pubkey,
+                destination,
+                owner_program_name(&account.owner)
+            );
+            wrong_program.push(recipient.pubkey);
+            continue;
+        }
+        // A hook that keeps per-recipient state, such as an allowlist entry,
+        // rejects transfers whose state account doesn't exist. The accounts
+        // are resolved from the current source, the one the transfer is most
+        // likely to come from.
+        if let Some(hook_program) = hook_program.filter(|_| args.token.check_hook_accounts) {
+            let source = &sources[current_source];
+            let instruction = hooked_transfer_instruction(
+                program_client,
+                &source.address,
+                &token_pubkey,
+                &destination,
+                &source.authority.pubkey(),
+                amount,
+                decimals,
+            )
+            .await
+            .map_err(|err| {
+                format!(
+                    "Could not resolve the transfer hook accounts for {}: {}",
+                    recipient.pubkey, err
+                )
+            })?;
+            let mut missing = Vec::new();
+            for meta in instruction.accounts[4..].iter() {
+                if meta.pubkey == hook_program {
+                    continue;
+                }
+                let exists = match hook_accounts.get(&meta.pubkey) {
+                    Some(exists) => *exists,
+                    None => {
+                        let exists = fetch_account(rpc_client, &meta.pubkey, account_commitment)
+                            .await?
+                            .is_some();
+                        hook_accounts.insert(meta.pubkey, exists);
+                        exists
+                    }
+                };
+                if !exists {
+                    missing.push(meta.pubkey);
+                }
+            }
+            if !missing.is_empty() {
+                progress!(
+                    "Skipping {}: the transfer hook needs {:?}, which don't exist, so the transfer would be rejected ⚠️",
+                    recipient.pubkey,
+                    missing
+                );
+                hook_rejected.push((recipient.pubkey, missing));
+                continue;
+            }
+        }
+        let mut creates_ata = existing_account.is_none() && !already_scheduled;
+        if creates_ata {
+            scheduled_atas.insert(destination);
+        }
+        let mut transfer_memo = None;
+        if existing_account.is_some_and(|account| requires_memo(&account.data)) {
+            match &args.token.memo {
+                Some(memo) => {
+                    transfer_memo = Some(spl_memo::build_memo(memo.as_bytes(), &[]));
+                    recipient_instructions.extend(transfer_memo.clone());
+                }
+                None => {
+                    progress!(
+                        "Skipping {}: {} requires a memo and no --memo was given",
+                        recipient.pubkey,
+                        destination
+                    );
+                    memo_required.push(recipient.pubkey);
+                    continue;
+                }
+            }
+        }
+        let create_ata = || {
+            create_ata_instruction(
+                ata_program,
+                source_pubkey,
+                recipient.token_owner(),
+                &token_pubkey,
+            )
+        };
+        if creates_ata && separate_atas {
+            ata_creations.push((create_ata(), destination, recipient.clone()));
+            creates_ata = false;
+        } else if creates_ata {
+            recipient_instructions.push(create_ata());
+        }
+
+        // New wallets get enough SOL to act on what they receive. A wallet
+        // listed twice is only topped up once.
+        if let Some(min_balance) = min_wallet_balance {
+            let wallet = *recipient.token_owner();
+            let top_up = min_balance.saturating_sub(wallet_balances[index]);
+            if top_up > 0 && topped_up_wallets.insert(wallet) {
+                recipient_instructions.push(system_instruction::transfer(
+                    source_pubkey,
+                    &wallet,
+                    top_up,
+                ));
+                top_ups = top_ups.saturating_add(top_up);
+            }
+        }
+
+        let split = parts.len() > 1;
+        if split {
+            progress!(
+                "Splitting the {} for {} into {} transfers of at most {}",
+                base_units_to_ui_amount(amount, decimals),
+                recipient.pubkey,
+                parts.len(),
+                base_units_to_ui_amount(transfer_cap.unwrap_or_default(), decimals)
+            );
+            split_recipients += 1;
+            split_transfers += parts.len();
+        }
+        for (part_index, part) in parts.into_iter().enumerate() {
+            // Later parts pay into the account the first part created or
+            // found, and need their own memo if the account requires one.
+            if part_index > 0 {
+                creates_ata = false;
+                already_scheduled = !separate_atas && scheduled_atas.contains(&destination);
+                if already_scheduled {
+                    duplicate_creates += 1;
+                }
+                recipient_instructions.extend(transfer_memo.clone());
+            }
+
+            // Sources are drained in order: once the current one can't cover a
+            // transfer, the rest come from the next.
+            let previous_source = current_source;
+            while current_source + 1 < sources.len() && sources[current_source].remaining() < part {
+                current_source += 1;
+                progress!("Sending from {}", sources[current_source].address);
+            }
+            let switched_source = current_source != previous_source;
+            let source = &mut sources[current_source];
+            shortfall |= source.remaining() < part;
+            source.sent = source.sent.saturating_add(part);
+            source.recipients += usize::from(part_index == 0 || switched_source);
+            let source_address = source.address;
+            let authority = source.authority.pubkey();
+
+            let mut instruction = hooked_transfer_instruction(
+                program_client,
+                &source_address,
+                &token_pubkey,
+                &destination,
+                &authority,
+                part,
+                decimals,
+            )
+            .await
+            .map_err(|err| {
+                format!(
+                    "Could not build the transfer to {}: {}",
+                    recipient.pubkey, err
+                )
+            })?;
+            instruction.accounts.extend(
+                args.token
+                    .extra_account
+                    .iter()
+                    .map(ExtraAccount::to_account_meta),
+            );
+
+            // transfer_checked takes source, mint, destination and authority;
+            // anything after that was added for the transfer hook.
+            let extra_accounts = instruction.accounts[4..].to_vec();
+            recipient_instructions.push(instruction);
+
+            // A recipient's create-ATA and transfer are only ever added together,
+            // so they always share a transaction: the flush happens before the
+            // group is appended, never in the middle of it. Each transaction
+            // sends from a single source, so it needs at most one extra signer.
+            let switches_source = batch_transfers
+                .last()
+                .is_some_and(|transfer| transfer.source != source_address);
+            if switches_source || needs_flush(instructions.len(), recipient_instructions.len()) {
+                let authority = batch_authority(&sources, &batch_transfers);
+                batches.push(
+                    build_batch(
+                        rpc_client.as_ref(),
+                        &[source_keypair.as_ref(), authority.as_ref()],
+                        ata_program,
+                        &scheduled_budget(
+                            &args.options,
+                            run,
+                            budget_instructions,
+                            batches.len() + 1,
+                        ),
+                        std::mem::take(&mut instructions),
+                        std::mem::take(&mut batch_transfers),
+                    )
+                    .await?,
+                );
+
+                // Unless batches are sent strictly in order and stop at the first
+                // failure, an ATA is only shared within a transaction. Separate
+                // creates have all been confirmed before any transfer is sent.
+                if args.options.needs_independent_batches() && !separate_atas {
+                    scheduled_atas.clear();
+                    if already_scheduled {
+                        recipient_instructions.insert(0, create_ata());
+                        creates_ata = true;
+                        duplicate_creates -= 1;
+                    }
+                    if creates_ata {
+                        scheduled_atas.insert(destination);
+                    }
+                }
+            }
+
+            instructions.append(&mut recipient_instructions);
+            batch_transfers.push(Transfer {
+                recipient: Recipient {
+                    amount: if split { part } else { recipient.amount },
+                    ..recipient.clone()
+                },
+                destination,
+                amount: part,
+                source: source_address,
+                creates_ata,
+                extra_accounts,
+            });
+        }
+    }
+
+    if split_recipients > 0 {
+        progress!(
+            "Split {} recipient(s) above --amount-per-transfer-cap into {} transfers",
+            split_recipients,
+            split_transfers
+        );
+    }
+
+    if already_at_target > 0 {
+        progress!(
+            "Skipped {} recipient(s) already at or above their target balance, which would be a transfer of 0",
+            already_at_target
+        );
+    }
+
+    if duplicate_creates > 0 {
+        progress!(
+            "Skipped {} duplicate ATA create(s) for accounts created earlier in this run",
+            duplicate_creates
+        );
+    }
+
+    if already_received > 0 {
+        progress!(
+            "Skipped {} recipient(s) that already received their amount",
+            already_received
+        );
+    }
+
+    if !memo_required.is_empty() {
+        progress!(
+            "Skipped {} recipient(s) whose token account requires a memo, pass --memo to include them: {:?}",
+            memo_required.len(),
+            memo_required
+        );
+    }
+    if !wrong_program.is_empty() {
+        progress!(
+            "Skipped {} recipient(s) whose ATA address holds an account of another program: {:?}",
+            wrong_program.len(),
+            wrong_program
+        );
+    }
+    if !hook_rejected.is_empty() {
+        progress!(
+            "Skipped {} recipient(s) the transfer hook would reject for missing accounts:",
+            hook_rejected.len()
+        );
+        for (recipient, missing) in hook_rejected.iter() {
+            progress!("  {}: {:?}", recipient, missing);
+        }
+    }
+
+    if !instructions.is_empty() {
+        let authority = batch_authority(&sources, &batch_transfers);
+        batches.push(
+            build_batch(
+                rpc_client.as_ref(),
+                &[source_keypair.as_ref(), authority.as_ref()],
+                ata_program,
+                &scheduled_budget(&args.options, run, budget_instructions, batches.len() + 1),
+                instructions,
+                batch_transfers,
+            )
+            .await?,
+        );
+    }
+
+    let mut ata_batches: Vec<Batch> = Vec::new();
+    for chunk in ata_creations.chunks(MAX_ATA_CREATES_PER_TX) {
+        let instructions: Vec<Instruction> = chunk
+            .iter()
+            .map(|(instruction, _destination, _recipient)| instruction.clone())
+            .collect();
+        let destinations: Vec<Pubkey> = chunk
+            .iter()
+            .map(|(_instruction, destination, _recipient)| *destination)
+            .collect();
+        let mut batch = build_batch(
+            rpc_client.as_ref(),
+            &[source_keypair.as_ref()],
+            ata_program,
+            budget_instructions,
+            instructions,
+            Vec::new(),
+        )
+        .await?;
+        batch.id = format!(
+            "ata-{}",
+            batch_id(destinations.iter().map(|destination| (*destination, 0)))
+        );
+        if args.token.only_missing_atas {
+            run.ata_recipients.lock().unwrap().insert(
+                batch.id.clone(),
+                chunk
+                    .iter()
+                    .map(|(_instruction, _destination, recipient)| recipient.clone())
+                    .collect(),
+            );
+        }
+        ata_batches.push(batch);
+    }
+    if args.token.only_missing_atas {
+        // The transfers are left for the airdrop itself.
+        progress!(
+            "{} of {} recipient(s) are missing an ATA, to be created in {} transaction(s) without sending any tokens",
+            ata_creations.len(),
+            recipient_count,
+            ata_batches.len()
+        );
+        batches = ata_batches;
+    } else if !ata_batches.is_empty() {
+        progress!(
+            "Creating {} ATA(s) in {} transaction(s) of their own before the transfers",
+            ata_creations.len(),
+            ata_batches.len()
+        );
+        ata_batches.append(&mut batches);
+        batches = ata_batches;
+    }
+
+    let ata_creates = ata_creations.len()
+        + batches
+            .iter()
+            .flat_map(|batch| batch.transfers.iter())
+            .filter(|transfer| transfer.creates_ata)
+            .count();
+    let mut ata_rent = 0;
+    if ata_creates > 0 {
+        let standard_len = ata_len(&mint_account.data)?;
+        let account_len = match args.token.token_account_size {
+            Some(size) if size < standard_len => {
+                progress!(
+                    "--token-account-size {} is smaller than the {} bytes a standard ATA for this mint takes, estimating rent with {} ⚠️",
+                    size,
+                    standard_len,
+                    standard_len
+                );
+                standard_len
+            }
+            Some(size) => size,
+            None => standard_len,
+        };
+        let rent = rpc_client
+            .get_minimum_balance_for_rent_exemption(account_len)
+            .await?;
+        ata_rent = rent.saturating_mul(ata_creates as u64);
+        progress!(
+            "Creating {} ATA(s) will cost {} SOL in rent ({} SOL each), paid by the source wallet",
+            ata_creates,
+            base_units_to_ui_amount(ata_rent, SOL_DECIMALS),
+            base_units_to_ui_amount(rent, SOL_DECIMALS)
+        );
+    }
+    if sources.len() > 1 {
+        progress!("Sending from {} source token accounts:", sources.len());
+        for source in sources.iter() {
+            progress!(
+                "  {}: {} recipient(s), {} of its {} tokens",
+                source.address,
+                source.recipients,
+                base_units_to_ui_amount(source.sent, decimals),
+                base_units_to_ui_amount(source.balance, decimals)
+            );
+        }
+    }
+    if shortfall && !args.options.skip_balance_check && !args.token.only_missing_atas {
+        let total_sent = sources
+            .iter()
+            .fold(0u64, |sent, source| sent.saturating_add(source.sent));
+        let total_held = sources
+            .iter()
+            .fold(0u64, |held, source| held.saturating_add(source.balance));
+        return Err(match sources.as_slice() {
+            [source] if args.token.as_delegate => format!(
+                "The airdrop sends {} tokens, but {} may only send {} from {} as its delegate",
+                base_units_to_ui_amount(total_sent, decimals),
+                source.authority.pubkey(),
+                base_units_to_ui_amount(source.balance, decimals),
+                source.address
+            ),
+            [source] => format!(
+                "The airdrop sends {} tokens, but {} only holds {}",
+                base_units_to_ui_amount(total_sent, decimals),
+                source.address,
+                base_units_to_ui_amount(source.balance, decimals)
+            ),
+            _ if total_sent > total_held => format!(
+                "The airdrop sends {} tokens, but the {} source token accounts only hold {}",
+                base_units_to_ui_amount(total_sent, decimals),
+                sources.len(),
+                base_units_to_ui_amount(total_held, decimals)
+            ),
+            _ => format!(
+                "The source token accounts hold {} tokens in total, enough for the {} sent, but a transfer is never split between them and the last one can't cover what is left",
+                base_units_to_ui_amount(total_held, decimals),
+                base_units_to_ui_amount(total_sent, decimals)
+            ),
+        }
+        .into());
+    }
+    let fees = quote_batch_fees(rpc_client, &batches).await;
+    if top_ups > 0 {
+        progress!(
+            "Topping up {} recipient wallet(s) below {} SOL will cost {} SOL, paid by the source wallet",
+            topped_up_wallets.len(),
+            args.token.min_recipient_balance_sol.as_deref().unwrap_or_default(),
+            base_units_to_ui_amount(top_ups, SOL_DECIMALS)
+        );
+    }
+    let spend = ata_rent
+        .saturating_add(top_ups)
+        .saturating_add(jito_tips(&args.options, &batches));
+    check_sol_balance(&args.options, rpc_client, source_pubkey, fees, spend).await?;
+
+    if let Some(plan_path) = &args.options.plan_out {
+        let source_ata = match sources.as_slice() {
+            [source] => Some(&source.address),
+            _ => None,
+        };
+        plan::write_plan(
+            plan_path,
+            Some(&token_pubkey),
+            source_pubkey,
+            source_ata,
+            recipient_count,
+            recipients_hash,
+            &batches,
+        )?;
+        println!(
+            "Wrote {} planned transaction(s) to {}",
+            batches.len(),
+            plan_path
+        );
+    }
+
+    Ok(batches)
+}
+
+/// Builds the batches for `airdrop-sol`: plain system transfers, with none of
+/// the ATA, mint or transfer hook handling that tokens need.
+async fn create_sol_airdrop_tx(
+    args: &AirdropSolArgs,
+    run: &RunContext,
+    rpc_client: Arc<RpcClient>,
+    source_keypair: Arc<dyn Signer>,
+) -> Result<Vec<Batch>, Box<dyn Error>> {
+    let source_pubkey = &source_keypair.pubkey();
+    let recipients =
+        prepare_recipients(&args.recipients, &args.options, source_pubkey, SOL_DECIMALS).await?;
+    let recipients_hash = check_recipients_hash(&args.options, &recipients)?;
+    let recipient_count = recipients.len();
+    let recipients = match &args.options.prior_signatures {
+        Some(path) => skip_prior_payments(&rpc_client, path, recipients, SOL_DECIMALS).await?,
+        None => recipients,
+    };
+    progress!("Source: {:?}", source_pubkey);
+
+    let budget_instructions = budget_instructions(
+        &args.options,
+        run,
+        &rpc_client,
+        source_pubkey,
+        &[*source_pubkey],
+    )
+    .await?;
+
+    let mut batches: Vec<Batch> = Vec::new();
+    for chunk in recipients.chunks(MAX_SOL_TRANSFERS_PER_TX) {
+        let instructions = chunk
+            .iter()
+            .map(|recipient| {
+                system_instruction::transfer(source_pubkey, &recipient.pubkey, recipient.amount)
+            })
+            .collect();
+        let transfers = chunk
+            .iter()
+            .map(|recipient| Transfer {
+                recipient: recipient.clone(),
+                destination: recipient.pubkey,
+                amount: recipient.amount,
+                source: *source_pubkey,
+                creates_ata: false,
+                extra_accounts: Vec::new(),
+            })
+            .collect();
+        batches.push(
+            build_batch(
+                rpc_client.as_ref(),
+                &[source_keypair.as_ref()],
+                &spl_associated_token_account::id(),
+                &scheduled_budget(&args.options, run, &budget_instructions, batches.len() + 1),
+                instructions,
+                transfers,
+            )
+            .await?,
+        );
+    }
+
+    let sent = batches
+        .iter()
+        .flat_map(|batch| batch.transfers.iter())
+        .fold(0u64, |sent, transfer| sent.saturating_add(transfer.amount));
+    let fees = quote_batch_fees(&rpc_client, &batches).await;
+    let spend = sent.saturating_add(jito_tips(&args.options, &batches));
+    check_sol_balance(&args.options, &rpc_client, source_pubkey, fees, spend).await?;
+
+    if let Some(plan_path) = &args.options.plan_out {
+        plan::write_plan(
+            plan_path,
+            None,
+            source_pubkey,
+            None,
+            recipient_count,
+            &recipients_hash,
+            &batches,
+        )?;
+        println!(
+            "Wrote {} planned transaction(s) to {}",
+            batches.len(),
+            plan_path
+        );
+    }
+
+    Ok(batches)
+}
+
+/// Quotes and prints the total fee of `batches`.
+async fn quote_batch_fees(rpc_client: &RpcClient, batches: &[Batch]) -> u64 {
+    let messages: Vec<&Message> = batches
+        .iter()
+        .map(|batch| &batch.transaction.message)
+        .collect();
+    let fees = fees::quote_fees(rpc_client, &messages).await;
+    progress!(
+        "Transaction fees: {} SOL across {} transaction(s)",
+        base_units_to_ui_amount(fees, SOL_DECIMALS),
+        batches.len()
+    );
+    fees
+}
+
+/// Prints and returns the Jito tips `batches` pay, if any.
+fn jito_tips(options: &AirdropOptions, batches: &[Batch]) -> u64 {
+    let tips = options.jito_tips(batches.len());
+    if tips > 0 {
+        progress!(
+            "Jito tips: {} SOL across {} transaction(s)",
+            base_units_to_ui_amount(tips, SOL_DECIMALS),
+            batches.len()
+        );
+    }
+    tips
+}
+
+/// Checks that the fee payer can cover `fees` plus `spend` lamports of rent
+/// or transfers and still keep `--min-sol-reserve`, so a run doesn't fail
+/// halfway for lack of SOL and a shared hot wallet isn't drained.
+async fn check_sol_balance(
+    options: &AirdropOptions,
+    rpc_client: &RpcClient,
+    fee_payer: &Pubkey,
+    fees: u64,
+    spend: u64,
+) -> Result<(), Box<dyn Error>> {
+    if options.skip_balance_check {
+        if options.output_format != OutputFormat::Ndjson {
+            println!(
+                "⚠️  --skip-balance-check: not checking that the source wallet can pay for the airdrop"
+            );
+        }
+        return Ok(());
+    }
+    let reserve = options
+        .min_sol_reserve
+        .as_deref()
+        .map(|reserve| ui_amount_to_base_units(reserve, SOL_DECIMALS))
+        .transpose()
+        .map_err(|err| format!("Invalid --min-sol-reserve: {}", err))?
+        .unwrap_or(0);
+
+    let cost = fees.saturating_add(spend);
+    let balance = rpc_client.get_balance(fee_payer).await?;
+
+    if balance < cost {
+        return Err(format!(
+            "The airdrop costs up to {} SOL ({} SOL in fees, {} SOL sent or paid in rent), but {} only holds {} SOL",
+            base_units_to_ui_amount(cost, SOL_DECIMALS),
+            base_units_to_ui_amount(fees, SOL_DECIMALS),
+            base_units_to_ui_amount(spend, SOL_DECIMALS),
+            fee_payer,
+            base_units_to_ui_amount(balance, SOL_DECIMALS)
+        )
+        .into());
+    }
+    if balance - cost < reserve {
+        return Err(format!(
+            "The airdrop costs up to {} SOL ({} SOL in fees, {} SOL sent or paid in rent), which would leave {} SOL of {}'s {} SOL, below the --min-sol-reserve of {} SOL",
+            base_units_to_ui_amount(cost, SOL_DECIMALS),
+            base_units_to_ui_amount(fees, SOL_DECIMALS),
+            base_units_to_ui_amount(spend, SOL_DECIMALS),
+            base_units_to_ui_amount(balance - cost, SOL_DECIMALS),
+            fee_payer,
+            base_units_to_ui_amount(balance, SOL_DECIMALS),
+            base_units_to_ui_amount(reserve, SOL_DECIMALS)
+        )
+        .into());
+    }
+    progress!(
+        "The airdrop costs up to {} SOL, leaving at least {} SOL in the fee payer",
+        base_units_to_ui_amount(cost, SOL_DECIMALS),
+        base_units_to_ui_amount(balance - cost, SOL_DECIMALS)
+    );
+    Ok(())
+}
+
+/// Names the programs an account found where a Token-2022 account was
+/// expected is most likely owned by.
+fn owner_program_name(owner: &Pubkey) -> String {
+    if *owner == spl_token::id() {
+        format!("the SPL Token program ({})", owner)
+    } else if *owner == system_program::id() {
+        format!("the System program ({})", owner)
+    } else {
+        owner.to_string()
+    }
+}
+
+/// Checks that `mint` is a Token-2022 mint, so a wallet or token account
+/// address passed by mistake is caught before any transaction is built.
+/// Returns the mint account.
+async fn check_mint(rpc_client: &RpcClient, mint: &Pubkey) -> Result<Account, Box<dyn Error>> {
+    let account = rpc_client
+        .get_account_with_commitment(mint, rpc_client.commitment())
+        .await?
+        .value
+        .ok_or_else(|| format!("Token address {} does not exist", mint))?;
+
+    if account.owner == spl_token::id() {
+        return Err(format!(
+            "{} is an SPL Token mint; only Token-2022 mints can be airdropped",
+            mint
+        )
+        .into());
+    }
+    if account.owner != spl_token_2022::id() {
+        return Err(format!(
+            "{} is owned by {}, not the Token-2022 program; is it a wallet address?",
+            mint, account.owner
+        )
+        .into());
+    }
+    if StateWithExtensions::<Mint>::unpack(&account.data).is_err() {
+        return Err(
+            match StateWithExtensions::<TokenAccount>::unpack(&account.data) {
+                Ok(state) => format!(
+                    "{} is a token account, not a mint; its mint is {}",
+                    mint, state.base.mint
+                ),
+                Err(_) => format!("{} is not a mint account", mint),
+            }
+            .into(),
+        );
+    }
+    Ok(account)
+}
+
+/// The decimals of the mint at `token_address`, which amounts are read,
+/// transferred and printed with.
+async fn mint_decimals(rpc_client: &RpcClient, token_address: &str) -> Result<u8, Box<dyn Error>> {
+    let mint = Pubkey::from_str(token_address)
+        .map_err(|_| format!("Invalid token address {}", token_address))?;
+    let account = check_mint(rpc_client, &mint).await?;
+    Ok(StateWithExtensions::<Mint>::unpack(&account.data)?
+        .base
+        .decimals)
+}
+
+/// The Token-2022 ATA of `owner` for `mint`, derived under `ata_program`
+/// rather than always the canonical associated token account program.
+fn ata_address(ata_program: &Pubkey, owner: &Pubkey, mint: &Pubkey) -> Pubkey {
+    Pubkey::find_program_address(
+        &[owner.as_ref(), spl_token_2022::id().as_ref(), mint.as_ref()],
+        ata_program,
+    )
+    .0
+}
+
+/// An idempotent create of `owner`'s ATA for `mint` through `ata_program`.
+fn create_ata_instruction(
+    ata_program: &Pubkey,
+    payer: &Pubkey,
+    owner: &Pubkey,
+    mint: &Pubkey,
+) -> Instruction {
+    let mut instruction =
+        create_associated_token_account_idempotent(payer, owner, mint, &spl_token_2022::id());
+    instruction.program_id = *ata_program;
+    instruction.accounts[1].pubkey = ata_address(ata_program, owner, mint);
+    instruction
+}
+
+/// The token accounts to send from: each `--source-token-account` with its
+/// owner's keypair, or the transfer authority's ATA if none are given. Each is
+/// checked up front, and its balance read for draining them in turn.
+async fn load_sources(
+    args: &AirdropArgs,
+    run: &RunContext,
+    rpc_client: &RpcClient,
+    source_keypair: &Arc<dyn Signer>,
+    mint: &Pubkey,
+    ata_program: &Pubkey,
+) -> Result<Vec<Source>, Box<dyn Error>> {
+    let transfer_authority = load_transfer_authority(&args.token, source_keypair)?;
+    let configured = &args.token.source_token_account;
+    if configured.is_empty() {
+        let address = ata_address(ata_program, &transfer_authority.pubkey(), mint);
+        progress!("Sender ATA: {}", address);
+        let balance = check_source_account(
+            rpc_client,
+            &address,
+            mint,
+            &transfer_authority.pubkey(),
+            false,
+            run.decimals,
+        )
+        .await?;
+        return Ok(vec![Source {
+            address,
+            authority: transfer_authority,
+            balance,
+            sent: 0,
+            recipients: 0,
+        }]);
+    }
+
+    let mut sources: Vec<Source> = Vec::with_capacity(configured.len());
+    for account in configured.iter() {
+        if sources
+            .iter()
+            .any(|source| source.address == account.address)
+        {
+            return Err(format!(
+                "--source-token-account {} is given more than once",
+                account.address
+            )
+            .into());
+        }
+        let authority: Arc<dyn Signer> = match &account.authority {
+            Some(keypair_path) => Arc::new(keypair::load_keypair(keypair_path)?),
+            None => transfer_authority.clone(),
+        };
+        let balance = check_source_account(
+            rpc_client,
+            &account.address,
+            mint,
+            &authority.pubkey(),
+            args.token.as_delegate,
+            run.decimals,
+        )
+        .await?;
+        progress!(
+            "Source token account: {} ({} tokens)",
+            account.address,
+            base_units_to_ui_amount(balance, run.decimals)
+        );
+        sources.push(Source {
+            address: account.address,
+            authority,
+            balance,
+            sent: 0,
+            recipients: 0,
+        });
+    }
+    Ok(sources)
+}
+
+/// The signer for `--transfer-authority`, found among the `--signer`
+/// keypairs, or `--keypair` if no separate authority was given.
+fn load_transfer_authority(
+    token: &TokenOptions,
+    source_keypair: &Arc<dyn Signer>,
+) -> Result<Arc<dyn Signer>, Box<dyn Error>> {
+    let mut signers: Vec<Arc<dyn Signer>> = Vec::with_capacity(token.signer.len());
+    for keypair_path in token.signer.iter() {
+        signers.push(Arc::new(keypair::load_keypair(keypair_path)?));
+    }
+    let Some(authority) = token.transfer_authority else {
+        return Ok(source_keypair.clone());
+    };
+    if authority == source_keypair.pubkey() {
+        return Ok(source_keypair.clone());
+    }
+    let signer = signers
+        .into_iter()
+        .find(|signer| signer.pubkey() == authority)
+        .ok_or_else(|| {
+            format!(
+                "--transfer-authority {} has no keypair; pass it with --signer",
+                authority
+            )
+        })?;
+    progress!("Transfer authority: {}", authority);
+    Ok(signer)
+}
+
+/// The authority of the source that `transfers`, a single batch, send from.
+fn batch_authority(sources: &[Source], transfers: &[Transfer]) -> Arc<dyn Signer> {
+    let address = transfers.last().map(|transfer| transfer.source);
+    sources
+        .iter()
+        .find(|source| Some(source.address) == address)
+        .unwrap_or(&sources[0])
+        .authority
+        .clone()
+}
+
+/// Checks that the source token account exists and is a Token-2022 account
+/// for `mint` that `authority` can transfer from, as its owner or delegate, so
+/// a misconfigured source fails before the run instead of on every
+/// transaction. Returns how much the authority may send: the balance, or for
+/// a delegate, no more than its delegated amount. With `as_delegate` the
+/// authority must be the delegate.
+async fn check_source_account(
+    rpc_client: &RpcClient,
+    source: &Pubkey,
+    mint: &Pubkey,
+    authority: &Pubkey,
+    as_delegate: bool,
+    decimals: u8,
+) -> Result<u64, Box<dyn Error>> {
+    let account = rpc_client
+        .get_account_with_commitment(source, rpc_client.commitment())
+        .await?
+        .value
+        .ok_or_else(|| {
+            format!(
+                "Source token account {} does not exist; {} holds none of {}",
+                source, authority, mint
+            )
+        })?;
+    if account.owner != spl_token_2022::id() {
+        return Err(format!(
+            "Source token account {} is owned by {}, not the Token-2022 program",
+            source, account.owner
+        )
+        .into());
+    }
+    let state = StateWithExtensions::<TokenAccount>::unpack(&account.data)
+        .map_err(|_| format!("Source token account {} is not a token account", source))?;
+    if state.base.mint != *mint {
+        return Err(format!(
+            "Source token account {} holds {}, not {}",
+            source, state.base.mint, mint
+        )
+        .into());
+    }
+    if state.base.is_frozen() {
+        return Err(format!("Source token account {} is frozen", source).into());
+    }
+    if state.base.owner == *authority && !as_delegate {
+        return Ok(state.base.amount);
+    }
+    if state.base.delegate == Some(*authority).into() {
+        progress!(
+            "{} is a delegate of {} for {} tokens",
+            authority,
+            source,
+            base_units_to_ui_amount(state.base.delegated_amount, decimals)
+        );
+        return Ok(state.base.amount.min(state.base.delegated_amount));
+    }
+    if as_delegate {
+        let delegate = Option::<Pubkey>::from(state.base.delegate)
+            .map(|delegate| format!("its delegate is {}", delegate))
+            .unwrap_or_else(|| "it has no delegate".to_string());
+        return Err(format!(
+            "--as-delegate: {} is not the delegate of source token account {}; {}",
+            authority, source, delegate
+        )
+        .into());
+    }
+    Err(format!(
+        "Source token account {} is owned by {}, and {}, which signs for it, is neither its owner nor its delegate",
+        source, state.base.owner, authority
+    )
+    .into())
+}
+
+/// The size of the ATAs created for `mint`: a Token-2022 account with the
+/// `ImmutableOwner` extension and whatever extensions the mint requires of its
+/// token accounts.
+fn ata_len(mint_data: &[u8]) -> Result<usize, Box<dyn Error>> {
+    let mint = StateWithExtensions::<Mint>::unpack(mint_data)?;
+    let mut extensions =
+        ExtensionType::get_required_init_account_extensions(&mint.get_extension_types()?);
+    extensions.push(ExtensionType::ImmutableOwner);
+    Ok(ExtensionType::try_calculate_account_len::<TokenAccount>(
+        &extensions,
+    )?)
+}
+
+/// Whether a token account has the `MemoTransfer` extension with incoming
+/// memos required, in which case a transfer into it fails without one.
+fn requires_memo(data: &[u8]) -> bool {
+    StateWithExtensions::<TokenAccount>::unpack(data)
+        .ok()
+        .and_then(|state| {
+            state
+                .get_extension::<MemoTransfer>()
+                .ok()
+                .map(|extension| bool::from(extension.require_incoming_transfer_memos))
+        })
+        .unwrap_or(false)
+}
+
+/// Whether the pending batch must be sent before adding a recipient's group of
+/// `incoming` instructions. An empty batch is never flushed, so a group is
+/// never split from, or sent without, its own transfer.
+fn needs_flush(batched: usize, incoming: usize) -> bool {
+    batched > 0 && batched + incoming + 1 > MAX_INSTRUCTIONS_PER_TX
+}
+
+/// Checks that every transfer in `transfers` is in `instructions` and, if it
+/// creates its destination ATA, that the create comes before it in the same
+/// transaction. Otherwise the transfer would fail, or a create would be paid
+/// for without the transfer that needs it.
+fn transfers_are_atomic(
+    instructions: &[Instruction],
+    transfers: &[Transfer],
+    ata_program: &Pubkey,
+) -> bool {
+    transfers.iter().all(|transfer| {
+        let destination_is = |instruction: &Instruction, position: usize| {
+            instruction
+                .accounts
+                .get(position)
+                .is_some_and(|meta| meta.pubkey == transfer.destination)
+        };
+        let Some(transfer_index) = instructions.iter().position(|instruction| {
+            instruction.program_id == spl_token_2022::id() && destination_is(instruction, 2)
+                || instruction.program_id == system_program::id() && destination_is(instruction, 1)
+        }) else {
+            return false;
+        };
+        !transfer.creates_ata
+            || instructions[..transfer_index].iter().any(|instruction| {
+                instruction.program_id == *ata_program && destination_is(instruction, 1)
+            })
+    })
+}
+
+/// Prepends the compute budget instructions to `instructions`, then fetches a
+/// blockhash and signs. The first of `signers` pays the fee, and any others
+/// sign only if the transaction needs them.
+async fn build_batch(
+    sender: &impl TransactionSender,
+    signers: &[&dyn Signer],
+    ata_program: &Pubkey,
+    budget_instructions: &[Instruction],
+    instructions: Vec<Instruction>,
+    mut transfers: Vec<Transfer>,
+) -> Result<Batch, Box<dyn Error>> {
+    if !transfers_are_atomic(&instructions, &transfers, ata_program) {
+        return Err(format!(
+            "A recipient's create-ATA and transfer were split across transactions in the batch paying {:?}; this is a bug, nothing in it was sent",
+            transfers
+                .iter()
+                .map(|transfer| transfer.recipient.pubkey)
+                .collect::<Vec<_>>()
+        )
+        .into());
+    }
+    let mut tx_instructions = budget_instructions.to_vec();
+    tx_instructions.extend(instructions);
+
+    let start = Instant::now();
+    let blockhash = fetch_blockhash(sender).await?;
+    let blockhash_time = start.elapsed();
+
+    let start = Instant::now();
+    let message =
+        Message::new_with_blockhash(&tx_instructions, Some(&signers[0].pubkey()), &blockhash);
+    let signer_keys = message.signer_keys();
+    let mut required: Vec<&dyn Signer> = Vec::new();
+    for signer in signers.iter() {
+        let pubkey = signer.pubkey();
+        if signer_keys.contains(&&pubkey) && !required.iter().any(|added| added.pubkey() == pubkey)
+        {
+            required.push(*signer);
+        }
+    }
+    let mut transaction = Transaction::new_unsigned(message);
+    transaction.sign(&required, blockhash);
+    let signing_time = start.elapsed();
+
+    let id = batch_id(
+        transfers
+            .iter()
+            .map(|transfer| (transfer.recipient.pubkey, transfer.amount)),
+    );
+    for transfer in transfers.iter_mut() {
+        transfer.recipient.batch_id = Some(id.clone());
+    }
+
+    Ok(Batch {
+        id,
+        transaction,
+        transfers,
+        timings: BatchTimings {
+            blockhash: Some(blockhash_time),
+            signing: Some(signing_time),
+            ..Default::default()
+        },
+    })
+}
+
+/// Fetches the latest blockhash, retrying a few times so a brief RPC hiccup
+/// while batches are built doesn't abort the run.
+async fn fetch_blockhash(sender: &impl TransactionSender) -> Result<Hash, Box<dyn Error>> {
+    let mut attempt = 0;
+    loop {
+        match sender.get_latest_blockhash().await {
+            Ok(blockhash) => return Ok(blockhash),
+            Err(err) if attempt < BLOCKHASH_FETCH_RETRIES => {
+                let backoff = SEND_RETRY_BACKOFF * 2u32.saturating_pow(attempt);
+                attempt += 1;
+                progress!(
+                    "Could not fetch a blockhash, retrying in {:?} ({}/{}): {}",
+                    backoff,
+                    attempt,
+                    BLOCKHASH_FETCH_RETRIES,
+                    err
+                );
+                tokio::time::sleep(backoff).await;
+            }
+            Err(err) => return Err(format!("Could not fetch a blockhash: {}", err).into()),
+        }
+    }
+}
+
+/// A batch's id: the first 8 bytes, in hex, of `paid_hash` of who it pays.
+fn batch_id(paid: impl Iterator<Item = (Pubkey, u64)>) -> String {
+    to_hex(&paid_hash(paid).to_bytes()[..8])
+}
+
+/// Prints the SHA-256 `paid_hash` of everyone the run is about to pay, in
+/// hex, and fails if `--expect-hash` gave a different one. Returns the hash.
+fn check_recipients_hash(
+    options: &AirdropOptions,
+    recipients: &[Recipient],
+) -> Result<String, Box<dyn Error>> {
+    let hash = to_hex(
+        &paid_hash(
+            recipients
+                .iter()
+                .map(|recipient| (recipient.pubkey, recipient.amount)),
+        )
+        .to_bytes(),
+    );
+    if options.output_format != OutputFormat::Ndjson {
+        println!(
+            "Recipients hash: {} ({} recipient(s))",
+            hash,
+            recipients.len()
+        );
+    }
+    match &options.expect_hash {
+        Some(expected) if !expected.trim().eq_ignore_ascii_case(&hash) => Err(format!(
+            "The recipients hash is {}, but --expect-hash is {}; the recipients or their amounts changed since that hash was taken",
+            hash,
+            expected.trim()
+        )
+        .into()),
+        _ => Ok(hash),
+    }
+}
+
+/// A SHA-256 hash of who is paid how much. The pairs are sorted first, so it
+/// depends only on the set and stays the same across reruns, whatever order
+/// they are sent in.
+fn paid_hash(paid: impl Iterator<Item = (Pubkey, u64)>) -> Hash {
+    let mut paid: Vec<(Pubkey, u64)> = paid.collect();
+    paid.sort();
+    let amounts: Vec<[u8; 8]> = paid
+        .iter()
+        .map(|(_, amount)| amount.to_le_bytes())
+        .collect();
+    let fields: Vec<&[u8]> = paid
+        .iter()
+        .zip(amounts.iter())
+        .flat_map(|((pubkey, _), amount)| [pubkey.as_ref(), amount.as_slice()])
+        .collect();
+    hashv(&fields)
+}
+
+fn to_hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|byte| format!("{:02x}", byte)).collect()
+}
+
+/// Splits `amount` into transfers of at most `cap`: as many full ones as fit,
+/// then the rest.
+fn split_transfer(amount: u64, cap: Option<u64>) -> Vec<u64> {
+    match cap {
+        Some(cap) if amount > cap => {
+            let mut parts = vec![cap; (amount / cap) as usize];
+            if !amount.is_multiple_of(cap) {
+                parts.push(amount % cap);
+            }
+            parts
+        }
+        _ => vec![amount],
+    }
+}
+
+/// Builds a Token-2022 `transfer_checked`, with the accounts the mint's
+/// transfer hook asks for in its ExtraAccountMetaList appended.
+async fn hooked_transfer_instruction(
+    program_client: &Arc<dyn ProgramClient<ProgramRpcClientSendTransaction>>,
+    source: &Pubkey,
+    mint: &Pubkey,
+    destination: &Pubkey,
+    authority: &Pubkey,
+    amount: u64,
+    decimals: u8,
+) -> Result<Instruction, Box<dyn Error + Send + Sync>> {
+    let fetch_account_data_fn = |address| {
+        program_client
+            .get_account(address)
+            .map_ok(|opt| opt.map(|acc| acc.data))
+    };
+    offchain::create_transfer_checked_instruction_with_extra_metas(
+        &spl_token_2022::id(),
+        source,
+        mint,
+        destination,
+        authority,
+        &[],
+        amount,
+        decimals,
+        fetch_account_data_fn,
+    )
+    .await
+}
+
+/// Fetches an account, telling a genuinely missing account (`Ok(None)`) apart
+/// from an RPC failure. Failures are retried a few times and then returned, so
+/// a flaky connection aborts the run instead of being mistaken for a missing
+/// account.
+async fn fetch_account(
+    rpc_client: &RpcClient,
+    address: &Pubkey,
+    commitment: CommitmentConfig,
+) -> Result<Option<Account>, Box<dyn Error>> {
+    let mut attempt = 0;
+    loop {
+        match rpc_client
+            .get_account_with_commitment(address, commitment)
+            .await
+        {
+            Ok(response) => return Ok(response.value),
+            Err(err) if attempt < ACCOUNT_FETCH_RETRIES => {
+                let backoff = SEND_RETRY_BACKOFF * 2u32.saturating_pow(attempt);
+                attempt += 1;
+                progress!(
+                    "Could not fetch {}, retrying in {:?} ({}/{}): {}",
+                    address,
+                    backoff,
+                    attempt,
+                    ACCOUNT_FETCH_RETRIES,
+                    err
+                );
+                tokio::time::sleep(backoff).await;
+            }
+            Err(err) => {
+                return Err(format!("Could not check whether {} exists: {}", address, err).into())
+            }
+        }
+    }
+}
+
+/// Fetches the SOL balance of each account, treating missing accounts as
+/// empty.
+async fn fetch_sol_balances(
+    rpc_client: &RpcClient,
+    accounts: &[Pubkey],
+    commitment: CommitmentConfig,
+) -> Result<Vec<u64>, Box<dyn Error>> {
+    let mut balances: Vec<u64> = Vec::with_capacity(accounts.len());
+    for chunk in accounts.chunks(MAX_MULTIPLE_ACCOUNTS) {
+        balances.extend(
+            rpc_client
+                .get_multiple_accounts_with_commitment(chunk, commitment)
+                .await?
+                .value
+                .into_iter()
+                .map(|account| account.map_or(0, |account| account.lamports)),
+        );
+    }
+    Ok(balances)
+}
+
+/// Fetches the token balance of each account, treating missing accounts as
+/// empty.
+async fn fetch_token_balances(
+    rpc_client: &RpcClient,
+    accounts: &[Pubkey],
+    commitment: CommitmentConfig,
+) -> Result<Vec<u64>, Box<dyn Error>> {
+    let mut balances: Vec<u64> = Vec::with_capacity(accounts.len());
+    for chunk in accounts.chunks(MAX_MULTIPLE_ACCOUNTS) {
+        for account in rpc_client
+            .get_multiple_accounts_with_commitment(chunk, commitment)
+            .await?
+            .value
+        {
+            // An account of another program holds none of this token; the
+            // airdrop skips it and says why.
+            let balance = match account {
+                Some(account) if account.owner == spl_token_2022::id() => {
+                    StateWithExtensions::<TokenAccount>::unpack(&account.data)?
+                        .base
+                        .amount
+                }
+                _ => 0,
+            };
+            balances.push(balance);
+        }
+    }
+    Ok(balances)
+}
+
+async fn send_and_confirm_transaction_with_websocket(
+    pubsub_client: &PubsubClient,
+    sender: &impl TransactionSender,
+    transaction: &Transaction,
+    config: RpcSendTransactionConfig,
+    send_retries: u32,
+    send_limiter: Option<&SendLimiter>,
+    timings: &mut BatchTimings,
+) -> Result<Signature, Box<dyn Error>> {
+    let signature = transaction.signatures[0];
+
+    // Subscribe before sending so the notification can't be missed.
+    let (mut notifications, unsubscribe) = pubsub_client
+        .signature_subscribe(
+            &signature,
+            Some(RpcSignatureSubscribeConfig {
+                commitment: Some(CommitmentConfig::confirmed()),
+                enable_received_notification: Some(false),
+            }),
+        )
+        .await?;
+
+    let start = Instant::now();
+    send_transaction_with_retries(sender, transaction, config, send_retries, send_limiter).await?;
+    timings.send = Some(start.elapsed());
+
+    let start = Instant::now();
+    let notification = tokio::time::timeout(WS_CONFIRM_TIMEOUT, notifications.next()).await;
+    timings.confirm = Some(start.elapsed());
+    drop(notifications);
+    unsubscribe().await;
+
+    match notification {
+        Ok(Some(response)) => match response.value {
+            RpcSignatureResult::ProcessedSignature(ProcessedSignatureResult { err: None }) => {
+                Ok(signature)
+            }
+            RpcSignatureResult::ProcessedSignature(ProcessedSignatureResult { err: Some(err) }) => {
+                Err(err.into())
+            }
+            RpcSignatureResult::ReceivedSignature(_) => {
+                Err("Unexpected received-signature notification".into())
+            }
+        },
+        Ok(None) => Err("WebSocket subscription closed before confirmation".into()),
+        Err(_) => Err(format!("Timed out waiting for confirmation of {}", signature).into()),
+    }
+}
+
+/// The wait between status polls. It starts at the poll interval, so a
+/// transaction that lands quickly is seen quickly, and doubles after every
+/// poll that finds nothing new, up to a cap, so a slow one doesn't have the
+/// RPC polled at the tightest rate the whole time.
+struct PollBackoff {
+    next: Duration,
+    max: Duration,
+}
+
+impl PollBackoff {
+    fn new(interval: Duration, max: Duration) -> Self {
+        Self {
+            next: interval.min(max),
+            max,
+        }
+    }
+
+    async fn wait(&mut self) {
+        tokio::time::sleep(self.next).await;
+        self.next = (self.next * 2).min(self.max);
+    }
+}
+
+async fn confirm_transaction_with_polling(
+    rpc_client: &RpcClient,
+    transaction: &Transaction,
+    signature: Signature,
+    mut backoff: PollBackoff,
+) -> Result<Signature, Box<dyn Error>> {
+    loop {
+        match rpc_client.get_signature_status(&signature).await? {
+            Some(Ok(())) => return Ok(signature),
+            Some(Err(err)) => return Err(err.into()),
+            None => {
+                if !rpc_client
+                    .is_blockhash_valid(
+                        &transaction.message.recent_blockhash,
+                        CommitmentConfig::processed(),
+                    )
+                    .await?
+                {
+                    return Err(
+                        format!("Transaction {} expired before confirmation", signature).into(),
+                    );
+                }
+                backoff.wait().await;
+            }
+        }
+    }
+}
+
+/// Whether a send failed because the transaction itself is invalid, which no
+/// amount of re-broadcasting will fix.
+fn is_permanent_send_error(err: &ClientError) -> bool {
+    matches!(
+        err.kind(),
+        ClientErrorKind::TransactionError(_)
+            | ClientErrorKind::RpcError(RpcError::RpcResponseError {
+                data: RpcResponseErrorData::SendTransactionPreflightFailure(_),
+                ..
+            })
+    )
+}
+
+/// Whether a send was rejected because the transaction has already landed,
+/// e.g. an earlier attempt whose response was lost. Some RPCs only say so in
+/// the error message.
+fn is_already_processed(err: &ClientError) -> bool {
+    err.get_transaction_error() == Some(TransactionError::AlreadyProcessed)
+        || err
+            .to_string()
+            .contains("This transaction has already been processed")
+}
+
+/// Broadcasts `transaction`, retrying transient failures such as dropped
+/// connections or rate limits up to `retries` times with exponential backoff.
+/// A transaction the cluster has already processed counts as sent, since the
+/// transfer went through. Every broadcast, retries included, first waits for
+/// `send_limiter` if there is one.
+async fn send_transaction_with_retries(
+    sender: &impl TransactionSender,
+    transaction: &Transaction,
+    config: RpcSendTransactionConfig,
+    retries: u32,
+    send_limiter: Option<&SendLimiter>,
+) -> Result<Signature, ClientError> {
+    let mut attempt = 0;
+    loop {
+        if let Some(send_limiter) = send_limiter {
+            send_limiter.wait().await;
+        }
+        match sender.send(transaction, config).await {
+            Err(err) if is_already_processed(&err) => {
+                progress!(
+                    "{} was already processed, counting it as sent",
+                    transaction.signatures[0]
+                );
+                return Ok(transaction.signatures[0]);
+            }
+            Err(err) if attempt < retries && !is_permanent_send_error(&err) => {
+                let backoff = SEND_RETRY_BACKOFF * 2u32.saturating_pow(attempt);
+                attempt += 1;
+                progress!(
+                    "Send failed, retrying in {:?} ({}/{}): {}",
+                    backoff,
+                    attempt,
+                    retries,
+                    err
+                );
+                tokio::time::sleep(backoff).await;
+            }
+            result => return result,
+        }
+    }
+}
+
+/// After confirmation gave up, checks the signature's status up to `retries`
+/// more times with exponential backoff. A slow confirmation then doesn't count
+/// as a failure, and the transaction is never re-broadcast. Returns the
+/// original error if it still hasn't landed.
+async fn recheck_confirmation(
+    rpc_client: &RpcClient,
+    signature: Signature,
+    retries: u32,
+    err: Box<dyn Error>,
+) -> Result<Signature, Box<dyn Error>> {
+    for attempt in 0..retries {
+        match rpc_client.get_signature_status(&signature).await {
+            Ok(Some(Ok(()))) => return Ok(signature),
+            Ok(Some(Err(err))) => return Err(err.into()),
+            Ok(None) | Err(_) => {}
+        }
+        let backoff = CONFIRM_RETRY_BACKOFF * 2u32.saturating_pow(attempt);
+        progress!(
+            "{} not confirmed yet, checking again in {:?} ({}/{})",
+            signature,
+            backoff,
+            attempt + 1,
+            retries
+        );
+        tokio::time::sleep(backoff).await;
+    }
+    Err(err)
+}
+
+async fn send_and_confirm_transaction(
+    rpc_client: &RpcClient,
+    pubsub_client: Option<&PubsubClient>,
+    transaction: &Transaction,
+    config: RpcSendTransactionConfig,
+    args: &AirdropOptions,
+    run: &RunContext,
+    timings: &mut BatchTimings,
+) -> Result<Signature, Box<dyn Error>> {
+    let signature = transaction.signatures[0];
+    let result = match pubsub_client {
+        Some(pubsub_client) => {
+            send_and_confirm_transaction_with_websocket(
+                pubsub_client,
+                &run.broadcaster(rpc_client),
+                transaction,
+                config,
+                args.send_retries,
+                run.send_limiter(),
+                timings,
+            )
+            .await
+        }
+        None => {
+            send_and_confirm_transaction_with_polling(
+                rpc_client,
+                transaction,
+                config,
+                args,
+                run,
+                timings,
+            )
+            .await
+        }
+    };
+
+    // Only a transaction that was actually sent is worth checking on again.
+    match result {
+        Err(err) if timings.send.is_some() && args.confirm_retries > 0 => {
+            let start = Instant::now();
+            let result =
+                recheck_confirmation(rpc_client, signature, args.confirm_retries, err).await;
+            timings.confirm = Some(timings.confirm.unwrap_or_default() + start.elapsed());
+            result
+        }
+        result => result,
+    }
+}
+
+async fn send_and_confirm_transaction_with_polling(
+    rpc_client: &RpcClient,
+    transaction: &Transaction,
+    config: RpcSendTransactionConfig,
+    args: &AirdropOptions,
+    run: &RunContext,
+    timings: &mut BatchTimings,
+) -> Result<Signature, Box<dyn Error>> {
+    let start = Instant::now();
+    let signature = send_transaction_with_retries(
+        &run.broadcaster(rpc_client),
+        transaction,
+        config,
+        args.send_retries,
+        run.send_limiter(),
+    )
+    .await?;
+    timings.send = Some(start.elapsed());
+
+    let poll_interval = args.poll_interval();
+    let start = Instant::now();
+    // The spinner writes straight to the terminal, so skip it when quiet. It
+    // polls at its own fixed rate, so a custom interval or backoff skips it too.
+    let result = if QUIET.load(Ordering::Relaxed)
+        || poll_interval != CONFIRM_POLL_INTERVAL
+        || args.max_poll_interval_ms != MAX_CONFIRM_POLL_INTERVAL.as_millis() as u64
+    {
+        confirm_transaction_with_polling(rpc_client, transaction, signature, args.poll_backoff())
+            .await
+    } else {
+        rpc_client
+            .confirm_transaction_with_spinner(
+                &signature,
+                &transaction.message.recent_blockhash,
+                CommitmentConfig::confirmed(),
+            )
+            .await
+            .map(|()| signature)
+            .map_err(|err| err.into())
+    };
+    timings.confirm = Some(start.elapsed());
+
+    result
+}
+
+/// Writes `remaining` to the remaining CSV or, with `--append-remaining`,
+/// merges it into the recipients already there from earlier sessions.
+fn update_remaining_csv(
+    args: &AirdropOptions,
+    run: &RunContext,
+    batches: &[Batch],
+    remaining: &[Recipient],
+) -> Result<(), Box<dyn Error>> {
+    let total = write_remaining(args, run, batches, remaining)?;
+    if args.output_format == OutputFormat::Ndjson {
+        return Ok(());
+    }
+    if args.append_remaining {
+        println!(
+            "{} recipient(s) merged into {} ({} in total)",
+            remaining.len(),
+            args.remaining_path,
+            total
+        );
+    } else {
+        println!("{} recipient(s) written to {}", total, args.remaining_path);
+    }
+    Ok(())
+}
+
+/// Folds the transfers of a recipient split by `--amount-per-transfer-cap`
+/// back into one recipient owed their sum, in the position of the first.
+/// Recipients CSVs list everyone once, and a recipient listed twice is only
+/// read at their first row.
+fn combine_split_parts(recipients: impl Iterator<Item = Recipient>) -> Vec<Recipient> {
+    let mut combined: Vec<Recipient> = Vec::new();
+    let mut positions: HashMap<Pubkey, usize> = HashMap::new();
+    for recipient in recipients {
+        match positions.get(&recipient.pubkey) {
+            Some(&position) => {
+                let first = &mut combined[position];
+                first.amount = first.amount.saturating_add(recipient.amount);
+            }
+            None => {
+                positions.insert(recipient.pubkey, combined.len());
+                combined.push(recipient);
+            }
+        }
+    }
+    combined
+}
+
+/// Writes or merges `remaining` into the remaining CSV and returns the number
+/// of rows now in it.
+fn write_remaining(
+    args: &AirdropOptions,
+    run: &RunContext,
+    batches: &[Batch],
+    remaining: &[Recipient],
+) -> Result<usize, Box<dyn Error>> {
+    let cut_off = run.cut_off.lock().unwrap();
+    let remaining = if cut_off.is_empty() {
+        remaining.to_vec()
+    } else {
+        combine_split_parts(remaining.iter().chain(cut_off.iter()).cloned())
+    };
+    let remaining = remaining.as_slice();
+    if !args.append_remaining {
+        write_remaining_csv(&args.remaining_path, remaining, run.decimals)?;
+        return Ok(remaining.len());
+    }
+    let superseded: HashSet<Pubkey> = batches
+        .iter()
+        .flat_map(|batch| batch.recipients())
+        .map(|recipient| recipient.pubkey)
+        .collect();
+    merge_remaining_csv(&args.remaining_path, remaining, &superseded, run.decimals)
+}
+
+/// After a panic, writes whoever the run hadn't paid yet to the remaining CSV
+/// the way [`write_remaining`] would have, and says what happened.
+fn write_unpaid_after_panic(
+    args: &AirdropOptions,
+    run: &RunContext,
+    panic: &(dyn Any + Send),
+) -> Box<dyn Error> {
+    let message = panic
+        .downcast_ref::<&str>()
+        .copied()
+        .or_else(|| panic.downcast_ref::<String>().map(String::as_str))
+        .unwrap_or("unknown cause");
+    let unpaid = run.unpaid.lock().unwrap_or_else(PoisonError::into_inner);
+    let cut_off = run.cut_off.lock().unwrap_or_else(PoisonError::into_inner);
+    let remaining = combine_split_parts(unpaid.recipients.iter().chain(cut_off.iter()).cloned());
+    let written = if args.append_remaining {
+        merge_remaining_csv(
+            &args.remaining_path,
+            &remaining,
+            &unpaid.superseded,
+            run.decimals,
+        )
+        .map(|_total| ())
+    } else {
+        write_remaining_csv(&args.remaining_path, &remaining, run.decimals)
+    };
+    match written {
+        Ok(()) => format!(
+            "The airdrop panicked ({}). {} recipient(s) not confirmed as paid were written to {}. Transactions in flight at the time may still land: check {} or resume with --prior-signatures or --verify-before-resume so nobody is paid twice",
+            message,
+            remaining.len(),
+            args.remaining_path,
+            run.paths.signatures
+        ),
+        Err(err) => format!(
+            "The airdrop panicked ({}), and the {} recipient(s) not confirmed as paid could not be written to {}: {}. Check {} for who was paid before resuming",
+            message,
+            remaining.len(),
+            args.remaining_path,
+            err,
+            run.paths.signatures
+        ),
+    }
+    .into()
+}
+
+/// Where a batch stands in a run.
+enum BatchState {
+    /// Not confirmed yet: waiting to be sent or still in flight.
+    Pending,
+    Confirmed,
+    Failed(String),
+}
+
+/// The state of every batch of a run, updated as each one confirms or fails.
+/// Who still needs paying is then read straight off it, whatever order the
+/// results came back in and wherever the run stopped: the failed batches,
+/// plus every batch still pending.
+struct BatchQueue<'a> {
+    batches: &'a [Batch],
+    states: Vec<BatchState>,
+}
+
+impl<'a> BatchQueue<'a> {
+    fn new(batches: &'a [Batch]) -> Self {
+        Self {
+            batches,
+            states: batches.iter().map(|_| BatchState::Pending).collect(),
+        }
+    }
+
+    fn confirm(&mut self, index: usize) {
+        self.states[index] = BatchState::Confirmed;
+    }
+
+    fn fail(&mut self, index: usize, err: String) {
+        self.states[index] = BatchState::Failed(err);
+    }
+
+    fn with_state(
+        &self,
+        matches: impl Fn(&BatchState) -> bool,
+    ) -> impl Iterator<Item = (&'a Batch, &BatchState)> {
+        self.batches
+            .iter()
+            .zip(self.states.iter())
+            .filter(move |(_batch, state)| matches(state))
+    }
+
+    fn confirmed(&self) -> usize {
+        self.with_state(|state| matches!(state, BatchState::Confirmed))
+            .count()
+    }
+
+    /// Recipients paid by the confirmed batches.
+    fn paid(&self) -> usize {
+        self.with_state(|state| matches!(state, BatchState::Confirmed))
+            .map(|(batch, _state)| batch.transfers.len())
+            .sum()
+    }
+
+    /// Batches that haven't confirmed or failed, in send order.
+    fn pending(&self) -> Vec<&'a Batch> {
+        self.with_state(|state| matches!(state, BatchState::Pending))
+            .map(|(batch, _state)| batch)
+            .collect()
+    }
+
+    /// Batches that failed and why, in send order.
+    fn failed(&self) -> Vec<(&'a Batch, String)> {
+        self.with_state(|state| matches!(state, BatchState::Failed(_)))
+            .map(|(batch, state)| match state {
+                BatchState::Failed(err) => (batch, err.clone()),
+                _ => unreachable!("only failed batches are selected"),
+            })
+            .collect()
+    }
+
+    /// Every batch not confirmed yet, failed or pending, in send order.
+    fn unpaid(&self) -> impl Iterator<Item = &'a Batch> + '_ {
+        self.with_state(|state| !matches!(state, BatchState::Confirmed))
+            .map(|(batch, _state)| batch)
+    }
+}
+
+/// With `--checkpoint-every`, rewrites the remaining CSV with the recipients
+/// of every batch in `unconfirmed` once `confirmed` is a multiple of the
+/// cadence. Returns whether a checkpoint was written.
+fn checkpoint_remaining<'a>(
+    args: &AirdropOptions,
+    run: &RunContext,
+    batches: &[Batch],
+    confirmed: usize,
+    unconfirmed: impl Iterator<Item = &'a Batch>,
+) -> Result<bool, Box<dyn Error>> {
+    let Some(every) = args.checkpoint_every.filter(|every| *every > 0) else {
+        return Ok(false);
+    };
+    if !confirmed.is_multiple_of(every) {
+        return Ok(false);
+    }
+    let remaining = combine_split_parts(unconfirmed.flat_map(|batch| batch.recipients().cloned()));
+    write_remaining(args, run, batches, &remaining)?;
+    progress!(
+        "Checkpoint: {} recipient(s) not yet paid written to {}",
+        remaining.len(),
+        args.remaining_path
+    );
+    Ok(true)
+}
+
+/// Writes the recipients of `failed` and `unattempted` batches that still need
+/// tokens to the remaining CSV, moving failed recipients that have used up
+/// their attempts to the permanently failed list instead.
+fn record_failures(
+    args: &AirdropOptions,
+    run: &RunContext,
+    batches: &[Batch],
+    failed: &[(&Batch, String)],
+    unattempted: &[&Batch],
+    attempts: &mut RecipientAttempts,
+) -> Result<(), Box<dyn Error>> {
+    if args.report_failures_only {
+        report_failures(args, run, failed)?;
+    }
+
+    let mut remaining: Vec<Recipient> = Vec::new();
+    let mut permanently_failed: Vec<Recipient> = Vec::new();
+
+    let failed_recipients = combine_split_parts(
+        failed
+            .iter()
+            .flat_map(|(batch, _err)| batch.recipients().cloned()),
+    );
+    for recipient in failed_recipients {
+        if attempts.record_failure(&recipient.pubkey) >= args.max_attempts {
+            permanently_failed.push(recipient);
+        } else {
+            remaining.push(recipient);
+        }
+    }
+    remaining.extend(
+        unattempted
+            .iter()
+            .flat_map(|batch| batch.recipients().cloned()),
+    );
+    let remaining = combine_split_parts(remaining.into_iter());
+
+    update_remaining_csv(args, run, batches, &remaining)?;
+
+    if !permanently_failed.is_empty() {
+        append_permanently_failed(
+            &run.paths.permanently_failed,
+            &permanently_failed,
+            run.decimals,
+        )?;
+        for recipient in permanently_failed.iter() {
+            attempts.clear(&recipient.pubkey);
+        }
+        if args.output_format != OutputFormat::Ndjson {
+            println!(
+                "{} recipient(s) failed {} times and were moved to {}",
+                permanently_failed.len(),
+                args.max_attempts,
+                run.paths.permanently_failed
+            );
+        }
+    }
+
+    attempts.save(&run.paths.attempts)
+}
+
+/// Prints `failed` grouped by error, the most widespread first, and writes
+/// every failed recipient with its batch, signature and error to the
+/// failures CSV for drilling down.
+fn report_failures(
+    args: &AirdropOptions,
+    run: &RunContext,
+    failed: &[(&Batch, String)],
+) -> Result<(), Box<dyn Error>> {
+    // Transactions and recipients per error.
+    let mut groups: BTreeMap<&str, (usize, usize)> = BTreeMap::new();
+    for (batch, err) in failed.iter() {
+        let group = groups.entry(err.as_str()).or_default();
+        group.0 += 1;
+        group.1 += batch.transfers.len();
+    }
+    let mut groups: Vec<(&str, (usize, usize))> = groups.into_iter().collect();
+    groups.sort_by_key(|(_err, (transactions, recipients))| {
+        std::cmp::Reverse((*recipients, *transactions))
+    });
+
+    write_failures_csv(
+        &run.paths.failures,
+        failed.iter().flat_map(|(batch, err)| {
+            batch.recipients().map(move |recipient| {
+                (
+                    recipient,
+                    batch.transaction.signatures[0].to_string(),
+                    err.as_str(),
+                )
+            })
+        }),
+        run.decimals,
+    )?;
+
+    if args.output_format != OutputFormat::Ndjson {
+        let recipients: usize = groups
+            .iter()
+            .map(|(_err, (_txs, recipients))| recipients)
+            .sum();
+        println!(
+            "{} transaction(s) paying {} recipient(s) failed ❌:",
+            failed.len(),
+            recipients
+        );
+        for (err, (transactions, recipients)) in groups.iter() {
+            println!(
+                "  {} recipient(s) in {} transaction(s): {}",
+                recipients, transactions, err
+            );
+        }
+        println!("Details of each failure written to {}", run.paths.failures);
+    }
+    Ok(())
+}
+
+/// Prints how many of the recipients in `batches` were paid this round, which
+/// after a failure tells how far a run or a `retry-failed` round got.
+fn print_recipients_paid(args: &AirdropOptions, batches: &[Batch], paid: usize) {
+    if args.output_format == OutputFormat::Text {
+        let total: usize = batches.iter().map(|batch| batch.transfers.len()).sum();
+        println!("{} of {} recipient(s) paid this round", paid, total);
+    }
+}
+
+/// Polls `getSignatureStatuses` until every batch in `pending` has confirmed,
+/// failed, or outlived its blockhash. Returns the error for each batch that
+/// didn't confirm, keyed by batch index.
+async fn verify_signatures(
+    rpc_client: &RpcClient,
+    batches: &[Batch],
+    mut pending: Vec<usize>,
+    mut backoff: PollBackoff,
+) -> Result<BTreeMap<usize, String>, Box<dyn Error>> {
+    let mut failures: BTreeMap<usize, String> = BTreeMap::new();
+
+    while !pending.is_empty() {
+        let mut unconfirmed: Vec<usize> = Vec::new();
+        for chunk in pending.chunks(MAX_SIGNATURE_STATUSES_PER_REQUEST) {
+            let signatures: Vec<Signature> = chunk
+                .iter()
+                .map(|index| batches[*index].transaction.signatures[0])
+                .collect();
+            let statuses = rpc_client.get_signature_statuses(&signatures).await?.value;
+
+            for (index, status) in chunk.iter().zip(statuses) {
+                match status {
+                    Some(status) => match status.err {
+                        Some(err) => {
+                            failures.insert(*index, err.to_string());
+                        }
+                        None if status.satisfies_commitment(CommitmentConfig::confirmed()) => {}
+                        None => unconfirmed.push(*index),
+                    },
+                    None => unconfirmed.push(*index),
+                }
+            }
+        }
+
+        // Anything whose blockhash has expired can no longer land.
+        let mut blockhash_validity: HashMap<Hash, bool> = HashMap::new();
+        pending.clear();
+        for index in unconfirmed {
+            let blockhash = batches[index].transaction.message.recent_blockhash;
+            let is_valid = match blockhash_validity.get(&blockhash) {
+                Some(is_valid) => *is_valid,
+                None => {
+                    let is_valid = rpc_client
+                        .is_blockhash_valid(&blockhash, CommitmentConfig::processed())
+                        .await?;
+                    blockhash_validity.insert(blockhash, is_valid);
+                    is_valid
+                }
+            };
+            if is_valid {
+                pending.push(index);
+            } else {
+                failures.insert(index, "Blockhash expired before confirmation".to_string());
+            }
+        }
+
+        if !pending.is_empty() {
+            backoff.wait().await;
+        }
+    }
+
+    Ok(failures)
+}
+
+/// What a last look at sent-but-unconfirmed transactions found, by batch index.
+#[derive(Default)]
+struct Reconciliation {
+    confirmed: Vec<usize>,
+    failed: BTreeMap<usize, String>,
+    unknown: Vec<usize>,
+}
+
+impl Reconciliation {
+    /// Every reconciled batch index with what was found.
+    fn outcomes(&self) -> impl Iterator<Item = (usize, Outcome)> + '_ {
+        self.confirmed
+            .iter()
+            .map(|index| (*index, Outcome::Confirmed))
+            .chain(self.failed.keys().map(|index| (*index, Outcome::Failed)))
+            .chain(self.unknown.iter().map(|index| (*index, Outcome::Unknown)))
+    }
+}
+
+/// Looks up the final status of each batch in `pending`, searching the
+/// transaction history since they may have been sent long ago. A batch with
+/// no status is only failed once its blockhash has expired, checked before
+/// its status so it can't land in between; until then it's unknown and
+/// checked again, up to `retries` more times with exponential backoff. RPC
+/// errors leave batches unknown rather than aborting.
+async fn reconcile_signatures(
+    rpc_client: &RpcClient,
+    batches: &[Batch],
+    mut pending: Vec<usize>,
+    retries: u32,
+) -> Reconciliation {
+    let mut reconciliation = Reconciliation::default();
+    let mut attempt = 0;
+    loop {
+        let mut unknown: Vec<usize> = Vec::new();
+        for chunk in pending.chunks(MAX_SIGNATURE_STATUSES_PER_REQUEST) {
+            let mut expired: HashSet<usize> = HashSet::new();
+            let mut blockhash_validity: HashMap<Hash, bool> = HashMap::new();
+            for index in chunk {
+                let blockhash = batches[*index].transaction.message.recent_blockhash;
+                let is_valid = match blockhash_validity.get(&blockhash) {
+                    Some(is_valid) => *is_valid,
+                    None => {
+                        // Treat a failed check as still valid, so nothing is
+                        // written off on an RPC error.
+                        let is_valid = rpc_client
+                            .is_blockhash_valid(&blockhash, CommitmentConfig::processed())
+                            .await
+                            .unwrap_or(true);
+                        blockhash_validity.insert(blockhash, is_valid);
+                        is_valid
+                    }
+                };
+                if !is_valid {
+                    expired.insert(*index);
+                }
+            }
+
+            let signatures: Vec<Signature> = chunk
+                .iter()
+                .map(|index| batches[*index].transaction.signatures[0])
+                .collect();
+            let statuses = match rpc_client
+                .get_signature_statuses_with_history(&signatures)
+                .await
+            {
+                Ok(response) => response.value,
+                Err(err) => {
+                    progress!("Could not check {} signature(s): {}", chunk.len(), err);
+                    unknown.extend_from_slice(chunk);
+                    continue;
+                }
+            };
+            for (index, status) in chunk.iter().zip(statuses) {
+                match status {
+                    Some(status) => match status.err {
+                        Some(err) => {
+                            reconciliation.failed.insert(*index, err.to_string());
+                        }
+                        None if status.satisfies_commitment(CommitmentConfig::confirmed()) => {
+                            reconciliation.confirmed.push(*index)
+                        }
+                        None => unknown.push(*index),
+                    },
+                    None if expired.contains(index) => {
+                        reconciliation
+                            .failed
+                            .insert(*index, "Blockhash expired before confirmation".to_string());
+                    }
+                    None => unknown.push(*index),
+                }
+            }
+        }
+
+        if unknown.is_empty() || attempt >= retries {
+            reconciliation.unknown = unknown;
+            return reconciliation;
+        }
+        let backoff = CONFIRM_RETRY_BACKOFF * 2u32.saturating_pow(attempt);
+        attempt += 1;
+        progress!(
+            "{} transaction(s) still unknown, checking again in {:?} ({}/{})",
+            unknown.len(),
+            backoff,
+            attempt,
+            retries
+        );
+        tokio::time::sleep(backoff).await;
+        pending = unknown;
+    }
+}
+
+/// Reconciles the batches in `failures` that were broadcast against their
+/// on-chain status, so the summary and the remaining CSV reflect what landed
+/// rather than what was sent. Batches that confirmed after all are removed
+/// from `failures`, and those still unknown are kept in it to be retried.
+async fn reconcile_failures(
+    args: &AirdropOptions,
+    rpc_client: &RpcClient,
+    batches: &[Batch],
+    failures: &mut BTreeMap<usize, String>,
+    broadcast: &HashSet<usize>,
+) -> Reconciliation {
+    let pending: Vec<usize> = failures
+        .keys()
+        .filter(|index| broadcast.contains(index))
+        .copied()
+        .collect();
+    if pending.is_empty() {
+        return Reconciliation::default();
+    }
+    progress!(
+        "Reconciling {} sent but unconfirmed transaction(s) 🔍",
+        pending.len()
+    );
+    let reconciliation =
+        reconcile_signatures(rpc_client, batches, pending, args.reconcile_retries).await;
+
+    for index in reconciliation.confirmed.iter() {
+        failures.remove(index);
+    }
+    for (index, err) in reconciliation.failed.iter() {
+        failures.insert(*index, err.clone());
+    }
+    for index in reconciliation.unknown.iter() {
+        failures.insert(
+            *index,
+            format!(
+                "Status of {} still unknown; check it before paying these recipients again",
+                batches[*index].transaction.signatures[0]
+            ),
+        );
+    }
+    if args.output_format != OutputFormat::Ndjson {
+        println!(
+            "Reconciled {} transaction(s): {} confirmed, {} failed, {} still unknown",
+            reconciliation.confirmed.len()
+                + reconciliation.failed.len()
+                + reconciliation.unknown.len(),
+            reconciliation.confirmed.len(),
+            reconciliation.failed.len(),
+            reconciliation.unknown.len()
+        );
+    }
+    reconciliation
+}
+
+/// Broadcasts every batch without waiting, then confirms them all in one
+/// verification pass at the end.
+async fn execute_airdrop_fire_and_forget(
+    args: &AirdropOptions,
+    run: &RunContext,
+    batches: Vec<Batch>,
+    rpc_client: Arc<RpcClient>,
+) -> Result<(), Box<dyn Error>> {
+    let config = RpcSendTransactionConfig {
+        skip_preflight: false,
+        preflight_commitment: Some(CommitmentLevel::Processed),
+        ..Default::default()
+    };
+
+    let mut attempts = RecipientAttempts::load(&run.paths.attempts)?;
+    let mut run_state = args.run_state()?;
+    let mut failures: BTreeMap<usize, String> = BTreeMap::new();
+    let mut sent: Vec<usize> = Vec::new();
+    let mut timings: Vec<BatchTimings> = Vec::with_capacity(batches.len());
+
+    for (index, batch) in batches.iter().enumerate() {
+        progress!(
+            "Sending tx {}/{} (batch {}) 📦",
+            index + 1,
+            batches.len(),
+            batch.id
+        );
+        let start = Instant::now();
+        let result = send_transaction_with_retries(
+            &run.broadcaster(rpc_client.as_ref()),
+            &batch.transaction,
+            config,
+            args.send_retries,
+            run.send_limiter(),
+        )
+        .await;
+        timings.push(BatchTimings {
+            send: Some(start.elapsed()),
+            ..batch.timings
+        });
+
+        match result {
+            Ok(signature) => {
+                progress!("Signature: {}", run.show_signature(&signature));
+                sent.push(index);
+            }
+            Err(err) => {
+                progress!("Send failed ❌: {}", err);
+                batch.log_send_failure(run, &err.to_string())?;
+                failures.insert(index, err.to_string());
+            }
+        }
+    }
+
+    progress!("Verifying {} transaction(s) 🔍", sent.len());
+    let broadcast: HashSet<usize> = sent.iter().copied().collect();
+    match verify_signatures(&rpc_client, &batches, sent, args.poll_backoff()).await {
+        Ok(verify_failures) => failures.extend(verify_failures),
+        // Everything sent is reconciled below, so an RPC error here doesn't
+        // lose track of what landed.
+        Err(err) => {
+            progress!("Verification failed: {}", err);
+            for index in broadcast.iter() {
+                failures.insert(*index, err.to_string());
+            }
+        }
+    }
+    let reconciliation =
+        reconcile_failures(args, &rpc_client, &batches, &mut failures, &broadcast).await;
+
+    if args.timings {
+        print_timing_summary(&timings);
+    }
+
+    let ndjson = args.output_format == OutputFormat::Ndjson;
+    for (index, batch) in batches.iter().enumerate() {
+        if ndjson {
+            events::emit(&batch.event(index, failures.get(&index).cloned()))?;
+        }
+        match failures.get(&index) {
+            Some(err) if !ndjson && !args.report_failures_only => println!(
+                "Transaction {}/{} failed ❌: {}",
+                index + 1,
+                batches.len(),
+                err
+            ),
+            Some(_) => {}
+            None => {
+                batch.log_signature(args, run, &mut run_state)?;
+                for recipient in batch.recipients() {
+                    attempts.clear(&recipient.pubkey);
+                }
+            }
+        }
+    }
+    if ndjson {
+        events::emit(&Event::Complete {
+            confirmed: batches.len() - failures.len(),
+            failed: failures.len() - reconciliation.unknown.len(),
+            unknown: reconciliation.unknown.len(),
+        })?;
+    }
+    let paid = batches
+        .iter()
+        .enumerate()
+        .filter(|(index, _batch)| !failures.contains_key(index))
+        .map(|(_index, batch)| batch.transfers.len())
+        .sum();
+    print_recipients_paid(args, &batches, paid);
+
+    if failures.is_empty() {
+        attempts.save(&run.paths.attempts)?;
+        if run.has_cut_off() || (args.append_remaining && Path::new(&args.remaining_path).exists())
+        {
+            update_remaining_csv(args, run, &batches, &[])?;
+        }
+        if !ndjson {
+            println!(
+                "Airdrop complete: {} transaction(s) confirmed",
+                batches.len()
+            );
+        }
+        return Ok(());
+    }
+
+    let failed: Vec<(&Batch, String)> = failures
+        .iter()
+        .map(|(index, err)| (&batches[*index], err.clone()))
+        .collect();
+    record_failures(args, run, &batches, &failed, &[], &mut attempts)?;
+    Err(format!(
+        "{} of {} transaction(s) failed",
+        failures.len(),
+        batches.len()
+    )
+    .into())
+}
+
+/// Broadcasts every batch and records its signature without confirming
+/// anything. Only batches whose broadcast failed are written to the remaining
+/// CSV; the rest have to be checked out of band.
+async fn execute_airdrop_no_confirm(
+    args: &AirdropOptions,
+    run: &RunContext,
+    batches: Vec<Batch>,
+    rpc_client: Arc<RpcClient>,
+) -> Result<(), Box<dyn Error>> {
+    let config = RpcSendTransactionConfig {
+        skip_preflight: false,
+        preflight_commitment: Some(CommitmentLevel::Processed),
+        ..Default::default()
+    };
+
+    let mut attempts = RecipientAttempts::load(&run.paths.attempts)?;
+    let mut failures: BTreeMap<usize, String> = BTreeMap::new();
+    let ndjson = args.output_format == OutputFormat::Ndjson;
+
+    for (index, batch) in batches.iter().enumerate() {
+        progress!(
+            "Sending tx {}/{} (batch {}) 📦",
+            index + 1,
+            batches.len(),
+            batch.id
+        );
+        let result = send_transaction_with_retries(
+            &run.broadcaster(rpc_client.as_ref()),
+            &batch.transaction,
+            config,
+            args.send_retries,
+            run.send_limiter(),
+        )
+        .await;
+
+        match result {
+            Ok(signature) => {
+                if ndjson {
+                    events::emit(&batch.sent_event(index))?;
+                } else {
+                    println!(
+                        "Sent tx {}/{}, not confirmed: {}",
+                        index + 1,
+                        batches.len(),
+                        run.show_signature(&signature)
+                    );
+                }
+                // Not the run state: nothing here is known to have landed. Nor
+                // the remaining CSV, which only lists what failed to send.
+                run.mark_paid(batch);
+                append_signature_log(
+                    &run.paths.signatures,
+                    &signature,
+                    &batch.id,
+                    batch
+                        .transfers
+                        .iter()
+                        .map(|transfer| (&transfer.recipient, transfer.amount)),
+                    run.decimals,
+                )?;
+            }
+            Err(err) => {
+                if ndjson {
+                    events::emit(&batch.event(index, Some(err.to_string())))?;
+                } else if !args.report_failures_only {
+                    println!(
+                        "Transaction {}/{} failed to send ❌: {}",
+                        index + 1,
+                        batches.len(),
+                        err
+                    );
+                }
+                batch.log_send_failure(run, &err.to_string())?;
+                failures.insert(index, err.to_string());
+            }
+        }
+    }
+
+    let sent = batches.len() - failures.len();
+    if ndjson {
+        events::emit(&Event::Complete {
+            confirmed: 0,
+            failed: failures.len(),
+            unknown: sent,
+        })?;
+    } else {
+        println!(
+            "⚠️  {} transaction(s) sent and NOT confirmed; their signatures are in {}, check them before paying anyone again",
+            sent, run.paths.signatures
+        );
+    }
+
+    if failures.is_empty() {
+        attempts.save(&run.paths.attempts)?;
+        if run.has_cut_off() || (args.append_remaining && Path::new(&args.remaining_path).exists())
+        {
+            update_remaining_csv(args, run, &batches, &[])?;
+        }
+        return Ok(());
+    }
+
+    let failed: Vec<(&Batch, String)> = failures
+        .iter()
+        .map(|(index, err)| (&batches[*index], err.clone()))
+        .collect();
+    record_failures(args, run, &batches, &failed, &[], &mut attempts)?;
+    Err(format!(
+        "{} of {} transaction(s) failed to send",
+        failures.len(),
+        batches.len()
+    )
+    .into())
+}
+
+/// Sends and confirms batches in order, stopping at the first failure. With
+/// `--confirm-in-background` the next batch is sent while the previous one is
+/// still confirming, so one extra batch may be in flight when a failure stops
+/// the run; its outcome is waited for and accounted for before anything is
+/// written to the remaining CSV.
+async fn execute_airdrop(
+    args: &AirdropOptions,
+    run: &RunContext,
+    batches: Vec<Batch>,
+    rpc_client: Arc<RpcClient>,
+    websocket_url: Option<String>,
+) -> Result<(), Box<dyn Error>> {
+    let config = RpcSendTransactionConfig {
+        skip_preflight: false,
+        preflight_commitment: Some(CommitmentLevel::Processed),
+        ..Default::default()
+    };
+
+    let pubsub_client = connect_pubsub(websocket_url).await;
+    let mut attempts = RecipientAttempts::load(&run.paths.attempts)?;
+    let mut run_state = args.run_state()?;
+    let mut timings: Vec<BatchTimings> = Vec::with_capacity(batches.len());
+    let ndjson = args.output_format == OutputFormat::Ndjson;
+    let window = if args.confirm_in_background { 2 } else { 1 };
+
+    let send = |index: usize| {
+        let batch = &batches[index];
+        let rpc_client = &rpc_client;
+        let pubsub_client = pubsub_client.as_ref();
+        async move {
+            progress!("Sending tx (batch {}) 📦", batch.id);
+            let mut batch_timings = batch.timings;
+            let result = send_and_confirm_transaction(
+                rpc_client,
+                pubsub_client,
+                &batch.transaction,
+                config,
+                args,
+                run,
+                &mut batch_timings,
+            )
+            .await;
+            (index, batch_timings, result)
+        }
+    };
+
+    // Results come back in the order the batches were sent.
+    let mut in_flight = futures_util::stream::FuturesOrdered::new();
+    let mut next_index = 0;
+    while next_index < batches.len().min(window) {
+        in_flight.push_back(send(next_index));
+        next_index += 1;
+    }
+
+    let mut queue = BatchQueue::new(&batches);
+    let mut checkpointed = false;
+    while let Some((index, batch_timings, result)) = in_flight.next().await {
+        let batch = &batches[index];
+        timings.push(batch_timings);
+
+        let err = match result {
+            Ok(signature) => {
+                record_confirmed(
+                    args,
+                    run,
+                    batch,
+                    index,
+                    signature,
+                    &mut attempts,
+                    &mut run_state,
+                )?;
+                queue.confirm(index);
+                checkpointed |=
+                    checkpoint_remaining(args, run, &batches, queue.confirmed(), queue.unpaid())?;
+                if next_index < batches.len() {
+                    in_flight.push_back(send(next_index));
+                    next_index += 1;
+                }
+                continue;
+            }
+            Err(err) => err,
+        };
+        report_failed(
+            args,
+            run,
+            batch,
+            &batch_timings,
+            index,
+            batches.len(),
+            err.as_ref(),
+        )?;
+        queue.fail(index, err.to_string());
+
+        // A batch sent while this one was confirming may still land, so wait
+        // for it rather than listing its recipients as unpaid.
+        while let Some((index, batch_timings, result)) = in_flight.next().await {
+            let batch = &batches[index];
+            timings.push(batch_timings);
+            match result {
+                Ok(signature) => {
+                    record_confirmed(
+                        args,
+                        run,
+                        batch,
+                        index,
+                        signature,
+                        &mut attempts,
+                        &mut run_state,
+                    )?;
+                    queue.confirm(index);
+                }
+                Err(err) => {
+                    report_failed(
+                        args,
+                        run,
+                        batch,
+                        &batch_timings,
+                        index,
+                        batches.len(),
+                        err.as_ref(),
+                    )?;
+                    queue.fail(index, err.to_string());
+                }
+            }
+        }
+
+        let failed = queue.failed();
+        if ndjson {
+            events::emit(&Event::Complete {
+                confirmed: queue.confirmed(),
+                failed: failed.len(),
+                unknown: 0,
+            })?;
+        }
+        print_recipients_paid(args, &batches, queue.paid());
+        // Everything still pending was never sent.
+        record_failures(
+            args,
+            run,
+            &batches,
+            &failed,
+            &queue.pending(),
+            &mut attempts,
+        )?;
+        if args.timings {
+            print_timing_summary(&timings);
+        }
+        return Err(err);
+    }
+
+    attempts.save(&run.paths.attempts)?;
+    // A checkpoint mustn't be left listing recipients who have since been paid.
+    if run.has_cut_off()
+        || ((args.append_remaining || checkpointed) && Path::new(&args.remaining_path).exists())
+    {
+        update_remaining_csv(args, run, &batches, &[])?;
+    }
+
+    if args.timings {
+        print_timing_summary(&timings);
+    }
+
+    if ndjson {
+        events::emit(&Event::Complete {
+            confirmed: batches.len(),
+            failed: 0,
+            unknown: 0,
+        })?;
+    } else {
+        println!(
+            "Airdrop complete: {} transaction(s) confirmed",
+            batches.len()
+        );
+    }
+    print_recipients_paid(args, &batches, queue.paid());
+
+    Ok(())
+}
+
+/// Logs a batch that `execute_airdrop` saw confirm and resets its recipients'
+/// failed attempts.
+fn record_confirmed(
+    args: &AirdropOptions,
+    run: &RunContext,
+    batch: &Batch,
+    index: usize,
+    signature: Signature,
+    attempts: &mut RecipientAttempts,
+    run_state: &mut Option<RunState>,
+) -> Result<(), Box<dyn Error>> {
+    progress!("Done ✅");
+    progress!("Signature: {}", run.show_signature(&signature));
+    batch.log_signature(args, run, run_state)?;
+    if args.output_format == OutputFormat::Ndjson {
+        events::emit(&batch.event(index, None))?;
+    }
+    for recipient in batch.recipients() {
+        attempts.clear(&recipient.pubkey);
+    }
+    Ok(())
+}
+
+/// Reports a batch that `execute_airdrop` saw fail, logging it as never
+/// sent if it failed before reaching the cluster.
+fn report_failed(
+    args: &AirdropOptions,
+    run: &RunContext,
+    batch: &Batch,
+    timings: &BatchTimings,
+    index: usize,
+    total: usize,
+    err: &dyn Error,
+) -> Result<(), Box<dyn Error>> {
+    if args.output_format == OutputFormat::Ndjson {
+        events::emit(&batch.event(index, Some(err.to_string())))?;
+    } else if !args.report_failures_only {
+        println!("Transaction {}/{} failed ❌: {}", index + 1, total, err);
+    }
+    if timings.send.is_none() {
+        batch.log_send_failure(run, &err.to_string())?;
+    }
+    Ok(())
+}
+
+/// Connects to the WebSocket endpoint, or returns `None` to fall back to HTTP
+/// polling if there is none or it isn't usable.
+async fn connect_pubsub(websocket_url: Option<String>) -> Option<PubsubClient> {
+    let url = websocket_url?;
+    match PubsubClient::new(&url).await {
+        Ok(client) => Some(client),
+        Err(err) => {
+            progress!(
+                "Could not connect to {}, falling back to polling: {}",
+                url,
+                err
+            );
+            None
+        }
+    }
+}
+
+/// Sends and confirms up to `--max-tx-in-flight` batches at once, carrying on
+/// past failures. Each result is tied back to its batch index rather than to
+/// completion order, so the remaining CSV ends up with exactly the recipients
+/// of the batches that failed, however the sends interleave. With
+/// `--continue-on-error` alone this sends one batch at a time, in order.
+async fn execute_airdrop_concurrent(
+    args: &AirdropOptions,
+    run: &RunContext,
+    batches: Vec<Batch>,
+    rpc_client: Arc<RpcClient>,
+    websocket_url: Option<String>,
+) -> Result<(), Box<dyn Error>> {
+    let config = RpcSendTransactionConfig {
+        skip_preflight: false,
+        preflight_commitment: Some(CommitmentLevel::Processed),
+        ..Default::default()
+    };
+
+    let pubsub_client = connect_pubsub(websocket_url).await;
+    let mut attempts = RecipientAttempts::load(&run.paths.attempts)?;
+    let mut run_state = args.run_state()?;
+    let mut timings: Vec<BatchTimings> = Vec::with_capacity(batches.len());
+    let mut failures: BTreeMap<usize, String> = BTreeMap::new();
+    let mut broadcast: HashSet<usize> = HashSet::new();
+    let mut queue = BatchQueue::new(&batches);
+    let mut checkpointed = false;
+    let ndjson = args.output_format == OutputFormat::Ndjson;
+
+    let total = batches.len();
+    let mut results = futures_util::stream::iter(batches.iter().enumerate())
+        .map(|(index, batch)| {
+            let rpc_client = &rpc_client;
+            let pubsub_client = pubsub_client.as_ref();
+            async move {
+                progress!("Sending tx {}/{} (batch {}) 📦", index + 1, total, batch.id);
+                let mut batch_timings = batch.timings;
+                let result = send_and_confirm_transaction(
+                    rpc_client,
+                    pubsub_client,
+                    &batch.transaction,
+                    config,
+                    args,
+                    run,
+                    &mut batch_timings,
+                )
+                .await;
+                (index, batch_timings, result)
+            }
+        })
+        .buffer_unordered(args.max_tx_in_flight as usize);
+
+    while let Some((index, batch_timings, result)) = results.next().await {
+        let batch = &batches[index];
+        timings.push(batch_timings);
+        if batch_timings.send.is_some() {
+            broadcast.insert(index);
+        }
+        match result {
+            Ok(signature) => {
+                progress!(
+                    "Done ✅ {}/{}: {}",
+                    index + 1,
+                    batches.len(),
+                    run.show_signature(&signature)
+                );
+                batch.log_signature(args, run, &mut run_state)?;
+                for recipient in batch.recipients() {
+                    attempts.clear(&recipient.pubkey);
+                }
+                if ndjson {
+                    events::emit(&batch.event(index, None))?;
+                }
+                queue.confirm(index);
+                // Batches in flight, failed so far or not yet sent are all
+                // still unpaid.
+                checkpointed |=
+                    checkpoint_remaining(args, run, &batches, queue.confirmed(), queue.unpaid())?;
+            }
+            Err(err) => {
+                if ndjson {
+                    events::emit(&batch.event(index, Some(err.to_string())))?;
+                } else if !args.report_failures_only {
+                    println!(
+                        "Transaction {}/{} failed ❌: {}",
+                        index + 1,
+                        batches.len(),
+                        err
+                    );
+                }
+                if batch_timings.send.is_none() {
+                    batch.log_send_failure(run, &err.to_string())?;
+                }
+                queue.fail(index, err.to_string());
+                failures.insert(index, err.to_string());
+            }
+        }
+    }
+
+    // A timed-out confirmation may still have landed.
+    let reconciliation =
+        reconcile_failures(args, &rpc_client, &batches, &mut failures, &broadcast).await;
+    for index in reconciliation.confirmed.iter() {
+        let batch = &batches[*index];
+        batch.log_signature(args, run, &mut run_state)?;
+        for recipient in batch.recipients() {
+            attempts.clear(&recipient.pubkey);
+        }
+    }
+    if ndjson {
+        for (index, outcome) in reconciliation.outcomes() {
+            events::emit(&batches[index].reconciled_event(index, outcome))?;
+        }
+    }
+
+    if args.timings {
+        print_timing_summary(&timings);
+    }
+    if ndjson {
+        events::emit(&Event::Complete {
+            confirmed: batches.len() - failures.len(),
+            failed: failures.len() - reconciliation.unknown.len(),
+            unknown: reconciliation.unknown.len(),
+        })?;
+    }
+    let paid = batches
+        .iter()
+        .enumerate()
+        .filter(|(index, _batch)| !failures.contains_key(index))
+        .map(|(_index, batch)| batch.transfers.len())
+        .sum();
+    print_recipients_paid(args, &batches, paid);
+
+    if failures.is_empty() {
+        attempts.save(&run.paths.attempts)?;
+        if run.has_cut_off()
+            || ((args.append_remaining || checkpointed) && Path::new(&args.remaining_path).exists())
+        {
+            update_remaining_csv(args, run, &batches, &[])?;
+        }
+        if !ndjson {
+            println!(
+                "Airdrop complete: {} transaction(s) confirmed",
+                batches.len()
+            );
+        }
+        return Ok(());
+    }
+
+    let failed: Vec<(&Batch, String)> = failures
+        .iter()
+        .map(|(index, err)| (&batches[*index], err.clone()))
+        .collect();
+    record_failures(args, run, &batches, &failed, &[], &mut attempts)?;
+    Err(format!(
+        "{} of {} transaction(s) failed",
+        failures.len(),
+        batches.len()
+    )
+    .into())
+}
+
+/// Drops the batches before `--resume-from-batch`, once the signature log
+/// shows a confirmed transaction paying each of their transfers. This run's
+/// batches may be packed differently from the earlier run's, so which batch
+/// paid whom doesn't matter, only that everyone skipped was paid. Their
+/// recipients are left out of the remaining CSV too.
+async fn skip_to_resume_point(
+    options: &AirdropOptions,
+    run: &RunContext,
+    rpc_client: &RpcClient,
+    mut batches: Vec<Batch>,
+) -> Result<Vec<Batch>, Box<dyn Error>> {
+    let Some(number) = options.resume_from_batch else {
+        return Ok(batches);
+    };
+    if number == 0 || number > batches.len() {
+        return Err(format!(
+            "--resume-from-batch {} is out of range: this run has {} transaction(s), numbered from 1",
+            number,
+            batches.len()
+        )
+        .into());
+    }
+
+    let log = &run.paths.signatures;
+    if !Path::new(log).exists() {
+        return Err(format!(
+            "--resume-from-batch {} skips transactions that can't be checked: there is no {} from the earlier run",
+            number, log
+        )
+        .into());
+    }
+    let mut paid = confirmed_payments(rpc_client, log, run.decimals).await?;
+    let mut unconfirmed: Vec<Pubkey> = Vec::new();
+    for transfer in batches[..number - 1]
+        .iter()
+        .flat_map(|batch| batch.transfers.iter())
+    {
+        let paid = paid.entry(transfer.recipient.pubkey).or_default();
+        match paid.checked_sub(transfer.amount) {
+            Some(left) => *paid = left,
+            None => unconfirmed.push(transfer.recipient.pubkey),
+        }
+    }
+    if !unconfirmed.is_empty() {
+        return Err(format!(
+            "{} recipient(s) in the transactions before tx {} have no confirmed payment in {}, so they can't be skipped: {:?}. Resume from an earlier transaction, or rerun with --prior-signatures {} instead",
+            unconfirmed.len(),
+            number,
+            log,
+            unconfirmed,
+            log
+        )
+        .into());
+    }
+
+    let skipped: usize = batches
+        .drain(..number - 1)
+        .map(|batch| batch.transfers.len())
+        .sum();
+    progress!(
+        "Resuming from tx {} (batch {}), skipping {} transaction(s) paying {} recipient(s)",
+        number,
+        batches[0].id,
+        number - 1,
+        skipped
+    );
+    Ok(batches)
+}
+
+/// Sends the built batches the way the options ask for, unless they were only
+/// written out as a plan.
+async fn run_airdrop(
+    options: &AirdropOptions,
+    run: &RunContext,
+    batches: Vec<Batch>,
+    rpc_client: Arc<RpcClient>,
+    websocket_url: Option<String>,
+) -> Result<(), Box<dyn Error>> {
+    let batches = skip_to_resume_point(options, run, &rpc_client, batches).await?;
+    if let Some(path) = &options.batch_report {
+        plan::write_batch_report(path.as_deref(), &batches, run.decimals)?;
+    }
+
+    if options.plan_out.is_some() {
+        // The plan has been written and nothing is sent.
+        Ok(())
+    } else if options.preflight_only {
+        let transfers: usize = batches.iter().map(|batch| batch.transfers.len()).sum();
+        if options.output_format == OutputFormat::Ndjson {
+            events::emit(&Event::PreflightPassed {
+                transfers,
+                transactions: batches.len(),
+            })?;
+        } else {
+            println!(
+                "✅ Preflight passed: {} transfer(s) in {} transaction(s) are ready to send; nothing was sent",
+                transfers,
+                batches.len()
+            );
+        }
+        Ok(())
+    } else {
+        // Nothing sent from here on can land in this slot or an earlier one.
+        let first_slot = if options.confirm_via_get_block {
+            Some(rpc_client.get_slot().await?)
+        } else {
+            None
+        };
+        // A panic skips writing the remaining CSV, so whoever is still unpaid
+        // is tracked until sending is over and written out if one happens.
+        run.track_unpaid(&batches);
+        let result = AssertUnwindSafe(execute_batches(
+            options,
+            run,
+            batches,
+            rpc_client.clone(),
+            websocket_url,
+        ))
+        .catch_unwind()
+        .await
+        .unwrap_or_else(|panic| Err(write_unpaid_after_panic(options, run, panic.as_ref())));
+        // Whatever failed, what did confirm still has to finalize and be
+        // found in the ledger.
+        let finality = if options.confirm_commitment_escalation {
+            verify_finality(options, run, &rpc_client).await
+        } else {
+            Ok(())
+        };
+        let reconciled = match first_slot {
+            Some(first_slot) => reconcile_with_blocks(options, run, &rpc_client, first_slot).await,
+            None => Ok(()),
+        };
+        if let Some(webhook) = run.webhook() {
+            webhook.finish().await;
+        }
+        result.and(finality).and(reconciled)
+    }
+}
+
+/// Sends `batches` the way the options ask for.
+async fn execute_batches(
+    options: &AirdropOptions,
+    run: &RunContext,
+    batches: Vec<Batch>,
+    rpc_client: Arc<RpcClient>,
+    websocket_url: Option<String>,
+) -> Result<(), Box<dyn Error>> {
+    if options.no_confirm {
+        execute_airdrop_no_confirm(options, run, batches, rpc_client).await
+    } else if options.fire_and_forget {
+        execute_airdrop_fire_and_forget(options, run, batches, rpc_client).await
+    } else if options.max_tx_in_flight > 1 || options.continue_on_error {
+        execute_airdrop_concurrent(options, run, batches, rpc_client, websocket_url).await
+    } else {
+        execute_airdrop(options, run, batches, rpc_client, websocket_url).await
+    }
+}
+
+/// Looks up every transaction this run confirmed in the block it landed in
+/// and takes its result from there, rather than from signature statuses,
+/// which nodes only keep for recent transactions. The slots statuses still
+/// report are read first; whatever is left is searched for in every block
+/// since `first_slot`. A transaction that failed in its block, or is in none
+/// of them, fails the run.
+async fn reconcile_with_blocks(
+    options: &AirdropOptions,
+    run: &RunContext,
+    rpc_client: &RpcClient,
+    first_slot: Slot,
+) -> Result<(), Box<dyn Error>> {
+    let confirmed = run.confirmed.lock().unwrap().clone();
+    if confirmed.is_empty() {
+        return Ok(());
+    }
+    progress!(
+        "Reconciling {} confirmed transaction(s) against the blocks they landed in 🔍",
+        confirmed.len()
+    );
+
+    let mut unresolved: HashSet<Signature> = confirmed
+        .iter()
+        .map(|(_id, signature, _blockhash)| *signature)
+        .collect();
+    let mut results: HashMap<Signature, Option<TransactionError>> = HashMap::new();
+    let mut hinted_slots: BTreeSet<Slot> = BTreeSet::new();
+    let signatures: Vec<Signature> = unresolved.iter().copied().collect();
+    for chunk in signatures.chunks(MAX_SIGNATURE_STATUSES_PER_REQUEST) {
+        let statuses = rpc_client
+            .get_signature_statuses_with_history(chunk)
+            .await?
+            .value;
+        hinted_slots.extend(statuses.into_iter().flatten().map(|status| status.slot));
+    }
+    for slot in hinted_slots.iter() {
+        read_block_results(rpc_client, *slot, &mut unresolved, &mut results).await?;
+    }
+
+    if !unresolved.is_empty() {
+        let last_slot = rpc_client
+            .get_slot_with_commitment(CommitmentConfig::confirmed())
+            .await?;
+        let slots = rpc_client
+            .get_blocks_with_commitment(first_slot, Some(last_slot), CommitmentConfig::confirmed())
+            .await?;
+        progress!(
+            "Searching {} block(s) for {} transaction(s) without a status",
+            slots.len(),
+            unresolved.len()
+        );
+        for slot in slots {
+            if unresolved.is_empty() {
+                break;
+            }
+            if !hinted_slots.contains(&slot) {
+                read_block_results(rpc_client, slot, &mut unresolved, &mut results).await?;
+            }
+        }
+    }
+
+    let mut failed: Vec<(&str, &Signature, String)> = Vec::new();
+    for (id, signature, _blockhash) in confirmed.iter() {
+        match results.get(signature) {
+            Some(None) => {}
+            Some(Some(err)) => {
+                failed.push((id, signature, format!("failed in its block: {}", err)))
+            }
+            None => failed.push((
+                id,
+                signature,
+                format!("is in no block since slot {}", first_slot),
+            )),
+        }
+    }
+    if options.output_format != OutputFormat::Ndjson {
+        for (id, signature, reason) in failed.iter() {
+            println!(
+                "Transaction {} (batch {}) {} ❌",
+                run.show_signature(signature),
+                id,
+                reason
+            );
+        }
+        println!(
+            "{} of {} confirmed transaction(s) succeeded in the ledger",
+            confirmed.len() - failed.len(),
+            confirmed.len()
+        );
+    }
+    if failed.is_empty() {
+        return Ok(());
+    }
+    Err(format!(
+        "{} confirmed transaction(s) did not succeed in the ledger, so their recipients may not have been paid; check batch(es) {}",
+        failed.len(),
+        failed
+            .iter()
+            .map(|(id, _signature, _reason)| *id)
+            .collect::<Vec<_>>()
+            .join(", ")
+    )
+    .into())
+}
+
+/// Records the result of each transaction still in `unresolved` that is in
+/// the block at `slot`. A slot that was skipped has no block and is passed
+/// over.
+async fn read_block_results(
+    rpc_client: &RpcClient,
+    slot: Slot,
+    unresolved: &mut HashSet<Signature>,
+    results: &mut HashMap<Signature, Option<TransactionError>>,
+) -> Result<(), Box<dyn Error>> {
+    let config = RpcBlockConfig {
+        encoding: Some(UiTransactionEncoding::Base64),
+        transaction_details: Some(TransactionDetails::Accounts),
+        rewards: Some(false),
+        commitment: Some(CommitmentConfig::confirmed()),
+        max_supported_transaction_version: Some(0),
+    };
+    let block = match rpc_client.get_block_with_config(slot, config).await {
+        Ok(block) => block,
+        Err(err) if is_skipped_slot(&err) => return Ok(()),
+        Err(err) => {
+            return Err(format!("Could not fetch the block at slot {}: {}", slot, err).into())
+        }
+    };
+    for transaction in block.transactions.unwrap_or_default() {
+        let EncodedTransaction::Accounts(accounts) = &transaction.transaction else {
+            continue;
+        };
+        let Some(signature) = accounts
+            .signatures
+            .first()
+            .and_then(|signature| Signature::from_str(signature).ok())
+        else {
+            continue;
+        };
+        if unresolved.remove(&signature) {
+            results.insert(signature, transaction.meta.and_then(|meta| meta.err));
+        }
+    }
+    Ok(())
+}
+
+/// Whether `err` says the slot has no block, because its leader skipped it
+/// or the block was never confirmed.
+fn is_skipped_slot(err: &ClientError) -> bool {
+    matches!(
+        err.kind(),
+        ClientErrorKind::RpcError(RpcError::RpcResponseError { code, .. })
+            if *code == JSON_RPC_SERVER_ERROR_SLOT_SKIPPED
+                || *code == JSON_RPC_SERVER_ERROR_BLOCK_NOT_AVAILABLE
+                || *code == JSON_RPC_SERVER_ERROR_LONG_TERM_STORAGE_SLOT_SKIPPED
+    )
+}
+
+/// Waits for every transaction this run confirmed to reach `finalized`, for
+/// up to `FINALITY_TIMEOUT`. One whose status has gone once its blockhash
+/// expired was confirmed on a fork that was abandoned, so its recipients were
+/// never paid and the run fails. One that is still only confirmed at the
+/// deadline is warned about rather than failed.
+async fn verify_finality(
+    options: &AirdropOptions,
+    run: &RunContext,
+    rpc_client: &RpcClient,
+) -> Result<(), Box<dyn Error>> {
+    let mut pending = run.confirmed.lock().unwrap().clone();
+    if pending.is_empty() {
+        return Ok(());
+    }
+    let total = pending.len();
+    progress!(
+        "Checking that {} confirmed transaction(s) reach finalized 🔍",
+        total
+    );
+
+    let deadline = Instant::now() + FINALITY_TIMEOUT;
+    let mut dropped: Vec<(String, Signature, Hash)> = Vec::new();
+    let mut backoff = options.poll_backoff();
+    loop {
+        let mut unfinalized = Vec::new();
+        for chunk in pending.chunks(MAX_SIGNATURE_STATUSES_PER_REQUEST) {
+            // Checked before the statuses, so a transaction can't land in
+            // between and be taken for dropped.
+            let mut blockhash_validity: HashMap<Hash, bool> = HashMap::new();
+            for (_id, _signature, blockhash) in chunk {
+                if !blockhash_validity.contains_key(blockhash) {
+                    let is_valid = rpc_client
+                        .is_blockhash_valid(blockhash, CommitmentConfig::processed())
+                        .await?;
+                    blockhash_validity.insert(*blockhash, is_valid);
+                }
+            }
+
+            let signatures: Vec<Signature> = chunk
+                .iter()
+                .map(|(_id, signature, _blockhash)| *signature)
+                .collect();
+            let statuses = rpc_client
+                .get_signature_statuses_with_history(&signatures)
+                .await?
+                .value;
+            for (entry, status) in chunk.iter().zip(statuses) {
+                match status {
+                    Some(status)
+                        if status.err.is_none()
+                            && status.satisfies_commitment(CommitmentConfig::finalized()) => {}
+                    None if !blockhash_validity[&entry.2] => dropped.push(entry.clone()),
+                    _ => unfinalized.push(entry.clone()),
+                }
+            }
+        }
+
+        pending = unfinalized;
+        if pending.is_empty() || Instant::now() >= deadline {
+            break;
+        }
+        backoff.wait().await;
+    }
+
+    for (id, signature, _blockhash) in pending.iter() {
+        progress!(
+            "⚠️  Transaction {} (batch {}) is confirmed but not yet finalized; check it later",
+            run.show_signature(signature),
+            id
+        );
+    }
+    if options.output_format != OutputFormat::Ndjson {
+        for (id, signature, _blockhash) in dropped.iter() {
+            println!(
+                "Transaction {} (batch {}) was confirmed but dropped before finalizing ❌",
+                run.show_signature(signature),
+                id
+            );
+        }
+        println!(
+            "{} of {} confirmed transaction(s) finalized, {} dropped, {} not yet finalized",
+            total - dropped.len() - pending.len(),
+            total,
+            dropped.len(),
+            pending.len()
+        );
+    }
+    if dropped.is_empty() {
+        return Ok(());
+    }
+    Err(format!(
+        "{} confirmed transaction(s) were dropped before finalizing, so their recipients were not paid; pay batch(es) {} again",
+        dropped.len(),
+        dropped
+            .iter()
+            .map(|(id, _signature, _blockhash)| id.as_str())
+            .collect::<Vec<_>>()
+            .join(", ")
+    )
+    .into())
+}
+
+/// With `--separate-ata-creation`, sends and confirms the ATA-only batches at
+/// the front of `batches`, then rebuilds the transfers of `airdrop` with fresh
+/// blockhashes and returns them. An ATA whose create failed is simply created
+/// again alongside its transfer. Anything else is returned as it is,
+/// including batches that are only planned or checked.
+async fn send_ata_phase(
+    args: &AirdropArgs,
+    run: &RunContext,
+    airdrop: &TokenAirdrop,
+    batches: Vec<Batch>,
+    rpc_client: Arc<RpcClient>,
+    source_keypair: Arc<dyn Signer>,
+    websocket_url: Option<String>,
+) -> Result<Vec<Batch>, Box<dyn Error>> {
+    let ata_batches = batches
+        .iter()
+        .take_while(|batch| batch.transfers.is_empty())
+        .count();
+    if ata_batches == 0 || args.options.plan_out.is_some() || args.options.preflight_only {
+        return Ok(batches);
+    }
+
+    progress!(
+        "ATA phase: creating ATAs in {} transaction(s) 🏗️",
+        ata_batches
+    );
+    let failed = send_ata_batches(
+        &args.options,
+        run,
+        &batches[..ata_batches],
+        &rpc_client,
+        websocket_url,
+    )
+    .await
+    .len();
+    if failed > 0 {
+        progress!(
+            "ATA phase: {} of {} transaction(s) confirmed, {} failed; their ATAs will be created alongside their transfers ⚠️",
+            ata_batches - failed,
+            ata_batches,
+            failed
+        );
+    } else {
+        progress!("ATA phase: all {} transaction(s) confirmed ✅", ata_batches);
+    }
+
+    progress!("Transfer phase: building transfers 💸");
+    create_airdrop_tx(args, run, &rpc_client, &source_keypair, airdrop, false).await
+}
+
+/// Sends and confirms ATA-only batches, `--max-tx-in-flight` at a time, and
+/// returns the ones that failed with their errors.
+async fn send_ata_batches<'a>(
+    options: &AirdropOptions,
+    run: &RunContext,
+    batches: &'a [Batch],
+    rpc_client: &Arc<RpcClient>,
+    websocket_url: Option<String>,
+) -> Vec<(&'a Batch, String)> {
+    let config = RpcSendTransactionConfig {
+        skip_preflight: false,
+        preflight_commitment: Some(CommitmentLevel::Processed),
+        ..Default::default()
+    };
+    let pubsub_client = connect_pubsub(websocket_url).await;
+    let mut results = futures_util::stream::iter(batches.iter().enumerate())
+        .map(|(index, batch)| {
+            let pubsub_client = pubsub_client.as_ref();
+            async move {
+                progress!(
+                    "Sending ATA tx {}/{} (batch {}) 📦",
+                    index + 1,
+                    batches.len(),
+                    batch.id
+                );
+                let result = send_and_confirm_transaction(
+                    rpc_client,
+                    pubsub_client,
+                    &batch.transaction,
+                    config,
+                    options,
+                    run,
+                    &mut BatchTimings::default(),
+                )
+                .await;
+                (batch, result)
+            }
+        })
+        .buffer_unordered(options.max_tx_in_flight as usize);
+
+    let mut failed: Vec<(&Batch, String)> = Vec::new();
+    while let Some((batch, result)) = results.next().await {
+        match result {
+            Ok(signature) => progress!(
+                "ATA batch {} confirmed: {}",
+                batch.id,
+                run.show_signature(&signature)
+            ),
+            Err(err) => {
+                progress!("ATA batch {} failed ❌: {}", batch.id, err);
+                failed.push((batch, err.to_string()));
+            }
+        }
+    }
+    failed
+}
+
+/// With `--only-missing-atas`, sends the ATA-only batches and nothing else.
+/// The recipients of a batch that failed are written to the remaining CSV,
+/// so running `--only-missing-atas` on it creates what is still missing.
+async fn create_missing_atas(
+    options: &AirdropOptions,
+    run: &RunContext,
+    batches: Vec<Batch>,
+    rpc_client: Arc<RpcClient>,
+    websocket_url: Option<String>,
+) -> Result<(), Box<dyn Error>> {
+    if options.plan_out.is_some() || options.preflight_only {
+        return run_airdrop(options, run, batches, rpc_client, websocket_url).await;
+    }
+    let failed = send_ata_batches(options, run, &batches, &rpc_client, websocket_url).await;
+
+    let ata_recipients = run.ata_recipients.lock().unwrap();
+    let atas = |batch: &Batch| ata_recipients.get(&batch.id).map_or(0, Vec::len);
+    let failed_atas: usize = failed.iter().map(|(batch, _err)| atas(batch)).sum();
+    let created = batches.iter().map(atas).sum::<usize>() - failed_atas;
+    if options.output_format == OutputFormat::Ndjson {
+        for (batch, err) in failed.iter() {
+            events::emit(&Event::Failed {
+                index: batches
+                    .iter()
+                    .position(|other| other.id == batch.id)
+                    .unwrap_or_default(),
+                batch_id: batch.id.clone(),
+                signature: batch.transaction.signatures[0].to_string(),
+                error: err.clone(),
+                recipients: ata_recipients
+                    .get(&batch.id)
+                    .into_iter()
+                    .flatten()
+                    .map(|recipient| recipient.pubkey.to_string())
+                    .collect(),
+            })?;
+        }
+        events::emit(&Event::AtasCreated {
+            created,
+            transactions: batches.len() - failed.len(),
+            failed: failed.len(),
+        })?;
+    } else {
+        println!(
+            "Created {} ATA(s) in {} transaction(s); no tokens were sent",
+            created,
+            batches.len() - failed.len()
+        );
+    }
+    if failed.is_empty() {
+        return Ok(());
+    }
+
+    let remaining: Vec<Recipient> = failed
+        .iter()
+        .flat_map(|(batch, _err)| ata_recipients.get(&batch.id).cloned().unwrap_or_default())
+        .collect();
+    drop(ata_recipients);
+    update_remaining_csv(options, run, &[], &remaining)?;
+    if options.output_format != OutputFormat::Ndjson {
+        for (batch, err) in failed.iter() {
+            println!("ATA batch {} failed ❌: {}", batch.id, err);
+        }
+    }
+    Err(format!(
+        "{} of {} ATA transaction(s) failed, leaving {} ATA(s) to create",
+        failed.len(),
+        batches.len(),
+        failed_atas
+    )
+    .into())
+}
+
+/// Checks that the RPC endpoint answers before doing anything, retrying with
+/// exponential backoff so an endpoint that is briefly restarting when a
+/// scheduled job starts doesn't fail the whole command.
+async fn wait_for_rpc(rpc_client: &RpcClient) -> Result<(), Box<dyn Error>> {
+    let mut attempt = 0;
+    loop {
+        match rpc_client.get_latest_blockhash().await {
+            Ok(_) => return Ok(()),
+            Err(err) if attempt < RPC_WARMUP_RETRIES => {
+                let backoff = RPC_WARMUP_BACKOFF * 2u32.saturating_pow(attempt);
+                attempt += 1;
+                progress!(
+                    "RPC endpoint {} is not responding, retrying in {:?} ({}/{}): {}",
+                    rpc_client.url(),
+                    backoff,
+                    attempt,
+                    RPC_WARMUP_RETRIES,
+                    err
+                );
+                tokio::time::sleep(backoff).await;
+            }
+            Err(err) => {
+                return Err(format!(
+                    "RPC endpoint {} is unavailable after {} attempts: {}",
+                    rpc_client.url(),
+                    attempt + 1,
+                    err
+                )
+                .into())
+            }
+        }
+    }
+}
+
+#[tokio::main]
+async fn main() -> Result<(), Box<dyn std::error::Error>> {
+    let matches = Args::command().get_matches();
+    let mut args = Args::from_arg_matches(&matches)?;
+    let output_paths = match &mut args.command {
+        Commands::Airdrop(command) => {
+            let command = command.as_mut();
+            command
+                .options
+                .resolve_output_paths(Some(&command.token_address))?
+        }
+        Commands::AirdropSol(command) => command.options.resolve_output_paths(None)?,
+        Commands::RetryFailed(command) => {
+            let command = command.as_mut();
+            let remaining_path_given = command.options.remaining_path != REMAINING_RECIPIENTS_PATH;
+            // By default, retry what an earlier run left in the same
+            // --output-dir.
+            if let Some(dir) = &command.options.output_dir {
+                if command.from == REMAINING_RECIPIENTS_PATH {
+                    command.from = in_output_dir(dir, &command.from);
+                }
+            }
+            let output_paths = command
+                .options
+                .resolve_output_paths(Some(&command.token_address))?;
+            // retry-failed updates the file it reads unless the failures were
+            // asked to go somewhere else.
+            if !remaining_path_given {
+                command.options.remaining_path = command.from.clone();
+            }
+            output_paths
+        }
+        Commands::Snapshot(_)
+        | Commands::MintInfo(_)
+        | Commands::Lint(_)
+        | Commands::Remove(_)
+        | Commands::Requeue(_)
+        | Commands::Compare(_) => OutputPaths::default(),
+    };
+    QUIET.store(args.quiet, Ordering::Relaxed);
+
+    let cli_config = load_config(&args).await?;
+    let config_file = config_file_path(&args).map(str::to_string);
+    let keypair_path = args.keypair.unwrap_or(cli_config.keypair_path);
+
+    // --cluster beats SOLANA_RPC_URL, which is only a fallback, but an
+    // explicit --rpc beats both.
+    let rpc_from_flag = matches.value_source("rpc") == Some(ValueSource::CommandLine);
+    let (rpc_urls, cluster_websocket_url) = match (args.rpc, args.cluster) {
+        (rpc, Some(cluster)) if rpc_from_flag => {
+            progress!(
+                "Both --rpc and --cluster were given, using --rpc {} instead of {} ⚠️",
+                rpc.join(","),
+                cluster.rpc_url()
+            );
+            (rpc, None)
+        }
+        (_, Some(cluster)) => (
+            vec![cluster.rpc_url().to_string()],
+            Some(solana_cli_config::Config::compute_websocket_url(
+                cluster.rpc_url(),
+            )),
+        ),
+        (rpc, None) if !rpc.is_empty() => (rpc, None),
+        (_, None) => (vec![cli_config.json_rpc_url], None),
+    };
+    // The first endpoint is the primary one; the rest are only used while it
+    // fails.
+    let cluster = rpc_urls[0].clone();
+    let websocket_url = args
+        .ws
+        .or(cluster_websocket_url)
+        .or_else(|| Some(cli_config.websocket_url).filter(|url| !url.is_empty()));
+    if args.dump_config {
+        return dump_config(
+            config_file.as_deref(),
+            &rpc_urls,
+            args.rpc_timeout_secs,
+            websocket_url.as_deref(),
+            &keypair_path,
+            &output_paths,
+            &args.command,
+        );
+    }
+
+    // Linting is offline, so it doesn't wait for an RPC that may not be up.
+    if let Commands::Lint(args) = &args.command {
+        return lint::lint_recipients(args);
+    }
+    // So are editing the remaining and failed CSVs and comparing recipient lists.
+    match &args.command {
+        Commands::Remove(args) => return requeue::remove_recipient(args),
+        Commands::Requeue(args) => return requeue::requeue_recipient(args),
+        Commands::Compare(args) => return compare::compare_recipients(args),
+        _ => {}
+    }
+
+    let output_dir = match &args.command {
+        Commands::Airdrop(command) => command.options.output_dir.as_deref(),
+        Commands::AirdropSol(command) => command.options.output_dir.as_deref(),
+        Commands::RetryFailed(command) => command.options.output_dir.as_deref(),
+        Commands::Snapshot(_)
+        | Commands::MintInfo(_)
+        | Commands::Lint(_)
+        | Commands::Remove(_)
+        | Commands::Requeue(_)
+        | Commands::Compare(_) => None,
+    };
+    if let Some(dir) = output_dir {
+        fs::create_dir_all(dir).map_err(|err| format!("Could not create {}: {}", dir, err))?;
+    }
+    let rpc_client = Arc::new(failover::rpc_client(
+        &rpc_urls,
+        Duration::from_secs(args.rpc_timeout_secs),
+        CommitmentConfig::confirmed(),
+    ));
+    wait_for_rpc(&rpc_client).await?;
+
+    match args.command {
+        Commands::Airdrop(args) => {
+            // Keep stdout to the event stream.
+            if args.options.output_format == OutputFormat::Ndjson {
+                QUIET.store(true, Ordering::Relaxed);
+            }
+            let decimals = mint_decimals(&rpc_client, &args.token_address).await?;
+            let run = &RunContext::new(&args.options, output_paths, decimals, &cluster);
+            let source_keypair: Arc<dyn Signer> = Arc::new(keypair::load_keypair(&keypair_path)?);
+            let airdrop = prepare_airdrop(&args, run, &rpc_client, &source_keypair).await?;
+            let batches = create_airdrop_tx(
+                &args,
+                run,
+                &rpc_client,
+                &source_keypair,
+                &airdrop,
+                args.token.separate_ata_creation || args.token.only_missing_atas,
+            )
+            .await?;
+            if args.token.only_missing_atas {
+                create_missing_atas(&args.options, run, batches, rpc_client, websocket_url).await?;
+            } else {
+                let batches = send_ata_phase(
+                    &args,
+                    run,
+                    &airdrop,
+                    batches,
+                    rpc_client.clone(),
+                    source_keypair,
+                    websocket_url.clone(),
+                )
+                .await?;
+                run_airdrop(&args.options, run, batches, rpc_client, websocket_url).await?;
+            }
+        }
+        Commands::AirdropSol(args) => {
+            if args.options.output_format == OutputFormat::Ndjson {
+                QUIET.store(true, Ordering::Relaxed);
+            }
+            let run = &RunContext::new(&args.options, output_paths, SOL_DECIMALS, &cluster);
+            let source_keypair = Arc::new(keypair::load_keypair(&keypair_path)?);
+            let batches =
+                create_sol_airdrop_tx(&args, run, rpc_client.clone(), source_keypair).await?;
+            run_airdrop(&args.options, run, batches, rpc_client, websocket_url).await?;
+        }
+        Commands::RetryFailed(args) => {
+            let RetryFailedArgs {
+                token_address,
+                from,
+                escalate_fee,
+                mut options,
+                token,
+            } = *args;
+            if options.output_format == OutputFormat::Ndjson {
+                QUIET.store(true, Ordering::Relaxed);
+            }
+            if !Path::new(&from).exists() {
+                return Err(
+                    format!("{} does not exist, so there is nothing to retry", from).into(),
+                );
+            }
+
+            // Everyone paid this round is dropped from the file and whoever
+            // fails again is written back in their place.
+            options.append_remaining = true;
+            if options.label_column.is_none() {
+                options.label_column = Some(LabelColumn::Name(LABEL_HEADER.to_string()));
+            }
+            if escalate_fee > 0 {
+                progress!("Adding {} microlamports to the priority fee", escalate_fee);
+            }
+            let args = AirdropArgs {
+                token_address,
+                recipients: RecipientsArgs {
+                    recipients_csv_path: from,
+                    amount: None,
+                    override_csv_amounts: false,
+                    price: None,
+                    price_url: None,
+                    price_field: None,
+                },
+                options,
+                token,
+            };
+            let decimals = mint_decimals(&rpc_client, &args.token_address).await?;
+            let run = &RunContext {
+                fee_escalation: escalate_fee,
+                ..RunContext::new(&args.options, output_paths, decimals, &cluster)
+            };
+
+            let source_keypair: Arc<dyn Signer> = Arc::new(keypair::load_keypair(&keypair_path)?);
+            let airdrop = prepare_airdrop(&args, run, &rpc_client, &source_keypair).await?;
+            let batches = create_airdrop_tx(
+                &args,
+                run,
+                &rpc_client,
+                &source_keypair,
+                &airdrop,
+                args.token.separate_ata_creation || args.token.only_missing_atas,
+            )
+            .await?;
+            if args.token.only_missing_atas {
+                create_missing_atas(&args.options, run, batches, rpc_client, websocket_url).await?;
+            } else {
+                let batches = send_ata_phase(
+                    &args,
+                    run,
+                    &airdrop,
+                    batches,
+                    rpc_client.clone(),
+                    source_keypair,
+                    websocket_url.clone(),
+                )
+                .await?;
+                run_airdrop(&args.options, run, batches, rpc_client, websocket_url).await?;
+            }
+        }
+        Commands::Snapshot(args) => {
+            snapshot::take_snapshot(args, &rpc_client).await?;
+        }
+        Commands::MintInfo(args) => {
+            mint_info::print_mint_info(&args, &rpc_client).await?;
+        }
+        Commands::Lint(_) | Commands::Remove(_) | Commands::Requeue(_) | Commands::Compare(_) => {
+            unreachable!("offline commands run before connecting")
+        }
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use {
+        super::*,
+        mock::{transient_error, MockCluster},
+        solana_client::rpc_request::RpcRequest,
+        solana_sdk::{program_pack::Pack, signature::Keypair},
+        spl_token_2022::{instruction::transfer_checked, state::AccountState},
+        std::path::Path,
+    };
+
+    fn recipient(pubkey: Pubkey, amount: u64) -> Recipient {
+        Recipient {
+            pubkey,
+            amount,
+            ata_owner: None,
+            label: None,
+            batch_id: None,
+        }
+    }
+
+    /// A transfer of 1 token from a fixed source into `owner`'s ATA for `mint`.
+    fn token_transfer(owner: &Pubkey, mint: &Pubkey, creates_ata: bool) -> (Transfer, Instruction) {
+        let ata_program = spl_associated_token_account::id();
+        let source = Pubkey::new_unique();
+        let destination = ata_address(&ata_program, owner, mint);
+        let instruction = transfer_checked(
+            &spl_token_2022::id(),
+            &source,
+            mint,
+            &destination,
+            &Pubkey::new_unique(),
+            &[],
+            1,
+            TOKEN_DECIMALS,
+        )
+        .unwrap();
+        let transfer = Transfer {
+            recipient: recipient(*owner, 1),
+            destination,
+            amount: 1,
+            source,
+            creates_ata,
+            extra_accounts: Vec::new(),
+        };
+        (transfer, instruction)
+    }
+
+    fn create_ata(owner: &Pubkey, mint: &Pubkey) -> Instruction {
+        create_ata_instruction(
+            &spl_associated_token_account::id(),
+            &Pubkey::new_unique(),
+            owner,
+            mint,
+        )
+    }
+
+    /// A signed batch paying `count` new wallets 1 lamport each from `payer`.
+    fn sol_batch(payer: &Keypair, count: usize) -> Batch {
+        let mut transfers: Vec<Transfer> = (0..count)
+            .map(|_| {
+                let wallet = Pubkey::new_unique();
+                Transfer {
+                    recipient: recipient(wallet, 1),
+                    destination: wallet,
+                    amount: 1,
+                    source: payer.pubkey(),
+                    creates_ata: false,
+                    extra_accounts: Vec::new(),
+                }
+            })
+            .collect();
+        let instructions: Vec<Instruction> = transfers
+            .iter()
+            .map(|transfer| {
+                system_instruction::transfer(&transfer.source, &transfer.destination, 1)
+            })
+            .collect();
+        let transaction = Transaction::new_signed_with_payer(
+            &instructions,
+            Some(&payer.pubkey()),
+            &[payer],
+            Hash::new_unique(),
+        );
+        let id = batch_id(
+            transfers
+                .iter()
+                .map(|transfer| (transfer.recipient.pubkey, transfer.amount)),
+        );
+        for transfer in transfers.iter_mut() {
+            transfer.recipient.batch_id = Some(id.clone());
+        }
+        Batch {
+            id,
+            transaction,
+            transfers,
+            timings: BatchTimings::default(),
+        }
+    }
+
+    /// Five two-recipient batches.
+    fn five_batches() -> Vec<Batch> {
+        let payer = Keypair::new();
+        (0..5).map(|_| sol_batch(&payer, 2)).collect()
+    }
+
+    fn ids<'a>(batches: impl IntoIterator<Item = &'a Batch>) -> Vec<&'a str> {
+        batches.into_iter().map(|batch| batch.id.as_str()).collect()
+    }
+
+    #[test]
+    fn new_queue_has_every_batch_pending() {
+        let batches = five_batches();
+        let queue = BatchQueue::new(&batches);
+        assert_eq!(queue.confirmed(), 0);
+        assert_eq!(queue.paid(), 0);
+        assert_eq!(ids(queue.pending()), ids(&batches));
+        assert!(queue.failed().is_empty());
+        assert_eq!(ids(queue.unpaid()), ids(&batches));
+    }
+
+    /// Fails batch `failed`, confirms the rest out of order, and checks that
+    /// only the failed batch is left to pay.
+    fn check_single_failure(failed: usize) {
+        let batches = five_batches();
+        let mut queue = BatchQueue::new(&batches);
+        for index in (0..batches.len()).rev() {
+            if index == failed {
+                queue.fail(index, "blockhash expired".to_string());
+            } else {
+                queue.confirm(index);
+            }
+        }
+        assert_eq!(queue.confirmed(), 4);
+        assert_eq!(queue.paid(), 8);
+        assert!(queue.pending().is_empty());
+        let failures = queue.failed();
+        assert_eq!(failures.len(), 1);
+        assert_eq!(failures[0].0.id, batches[failed].id);
+        assert_eq!(failures[0].1, "blockhash expired");
+        assert_eq!(ids(queue.unpaid()), [batches[failed].id.as_str()]);
+    }
+
+    #[test]
+    fn first_batch_failing_leaves_only_it_unpaid() {
+        check_single_failure(0);
+    }
+
+    #[test]
+    fn middle_batch_failing_leaves_only_it_unpaid() {
+        check_single_failure(2);
+    }
+
+    #[test]
+    fn last_batch_failing_leaves_only_it_unpaid() {
+        check_single_failure(4);
+    }
+
+    #[test]
+    fn run_stopped_early_leaves_failed_and_pending_batches_unpaid_in_order() {
+        let batches = five_batches();
+        let mut queue = BatchQueue::new(&batches);
+        queue.confirm(0);
+        queue.fail(3, "insufficient funds".to_string());
+        queue.confirm(2);
+        assert_eq!(queue.confirmed(), 2);
+        assert_eq!(ids(queue.pending()), ids([&batches[1], &batches[4]]));
+        assert_eq!(
+            ids(queue.failed().into_iter().map(|(batch, _err)| batch)),
+            ids([&batches[3]])
+        );
+        assert_eq!(
+            ids(queue.unpaid()),
+            ids([&batches[1], &batches[3], &batches[4]])
+        );
+    }
+
+    /// The options of a SOL airdrop writing its files into `dir` and polling
+    /// fast, plus `extra` flags, and a run of them.
+    fn options(dir: &Path, extra: &[&str]) -> (AirdropOptions, RunContext) {
+        let flags = [
+            "thook",
+            "recipients.csv",
+            "--output-dir",
+            dir.to_str().unwrap(),
+            "--poll-interval-ms",
+            "10",
+        ];
+        let mut options = AirdropSolArgs::try_parse_from(flags.iter().chain(extra))
+            .unwrap()
+            .options;
+        let paths = options.resolve_output_paths(None).unwrap();
+        let run = RunContext::new(&options, paths, SOL_DECIMALS, "http://localhost:8899");
+        (options, run)
+    }
+
+    fn send_config() -> RpcSendTransactionConfig {
+        RpcSendTransactionConfig {
+            preflight_commitment: Some(CommitmentLevel::Processed),
+            ..Default::default()
+        }
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn retries_a_transient_send_failure() {
+        let cluster = MockCluster::new();
+        let batch = sol_batch(&Keypair::new(), 1);
+        cluster.fail_next_send(transient_error());
+        let signature =
+            send_transaction_with_retries(&cluster, &batch.transaction, send_config(), 2, None)
+                .await
+                .unwrap();
+        assert_eq!(signature, batch.transaction.signatures[0]);
+        assert_eq!(cluster.sends(), 2);
+        assert!(cluster.landed(&signature));
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn already_processed_on_retry_counts_as_sent() {
+        let cluster = MockCluster::new();
+        let batch = sol_batch(&Keypair::new(), 1);
+        // The first send lands, but its response never arrives, so the retry
+        // is told the transaction was already processed.
+        cluster.lose_next_response();
+        let signature =
+            send_transaction_with_retries(&cluster, &batch.transaction, send_config(), 2, None)
+                .await
+                .unwrap();
+        assert_eq!(signature, batch.transaction.signatures[0]);
+        assert_eq!(cluster.sends(), 2);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn already_processed_through_the_rpc_counts_as_sent() {
+        let cluster = MockCluster::new();
+        let batch = sol_batch(&Keypair::new(), 1);
+        cluster.lose_next_response();
+        let signature = send_transaction_with_retries(
+            &cluster.rpc_client(),
+            &batch.transaction,
+            send_config(),
+            2,
+            None,
+        )
+        .await
+        .unwrap();
+        assert_eq!(signature, batch.transaction.signatures[0]);
+        assert_eq!(cluster.sends(), 2);
+    }
+
+    #[test]
+    fn already_processed_is_recognized_by_its_message() {
+        let err: ClientError = RpcError::RpcResponseError {
+            code: -32002,
+            message: "Transaction simulation failed: This transaction has already been processed"
+                .to_string(),
+            data: RpcResponseErrorData::Empty,
+        }
+        .into();
+        assert!(is_already_processed(&err));
+        assert!(!is_already_processed(&transient_error()));
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn gives_up_after_the_last_retry() {
+        let cluster = MockCluster::new();
+        let batch = sol_batch(&Keypair::new(), 1);
+        for _ in 0..3 {
+            cluster.fail_next_send(transient_error());
+        }
+        assert!(send_transaction_with_retries(
+            &cluster,
+            &batch.transaction,
+            send_config(),
+            2,
+            None
+        )
+        .await
+        .is_err());
+        assert_eq!(cluster.sends(), 3);
+        assert!(!cluster.landed(&batch.transaction.signatures[0]));
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn does_not_retry_an_invalid_transaction() {
+        let cluster = MockCluster::new();
+        let batch = sol_batch(&Keypair::new(), 1);
+        cluster.fail_next_send(
+            ClientErrorKind::TransactionError(TransactionError::InsufficientFundsForFee).into(),
+        );
+        assert!(send_transaction_with_retries(
+            &cluster,
+            &batch.transaction,
+            send_config(),
+            2,
+            None
+        )
+        .await
+        .is_err());
+        assert_eq!(cluster.sends(), 1);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn confirms_a_landed_transaction() {
+        let dir = tempfile::tempdir().unwrap();
+        let (options, run) = options(dir.path(), &[]);
+        let cluster = MockCluster::new();
+        let batch = sol_batch(&Keypair::new(), 2);
+        let mut timings = BatchTimings::default();
+        let signature = send_and_confirm_transaction(
+            &cluster.rpc_client(),
+            None,
+            &batch.transaction,
+            send_config(),
+            &options,
+            &run,
+            &mut timings,
+        )
+        .await
+        .unwrap();
+        assert_eq!(signature, batch.transaction.signatures[0]);
+        assert!(timings.send.is_some());
+        assert!(timings.confirm.is_some());
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn reports_a_transaction_that_failed_on_chain() {
+        let dir = tempfile::tempdir().unwrap();
+        let (options, run) = options(dir.path(), &[]);
+        let cluster = MockCluster::new();
+        let batch = sol_batch(&Keypair::new(), 2);
+        cluster.fail_on_chain(
+            batch.transaction.signatures[0],
+            TransactionError::InsufficientFundsForRent { account_index: 1 },
+        );
+        let err = send_and_confirm_transaction(
+            &cluster.rpc_client(),
+            None,
+            &batch.transaction,
+            send_config(),
+            &options,
+            &run,
+            &mut BatchTimings::default(),
+        )
+        .await
+        .unwrap_err();
+        assert!(err.to_string().contains("rent"), "{}", err);
+        assert_eq!(cluster.sends(), 1);
+    }
+
+    /// The recipients `path` lists, as a set.
+    fn recipients_in(path: &str) -> HashSet<Pubkey> {
+        recipients::read_recipients_csv(path, SOL_DECIMALS, None, None, None)
+            .unwrap()
+            .iter()
+            .map(|recipient| recipient.pubkey)
+            .collect()
+    }
+
+    fn recipients_of<'a>(batches: impl IntoIterator<Item = &'a Batch>) -> HashSet<Pubkey> {
+        batches
+            .into_iter()
+            .flat_map(|batch| batch.recipients())
+            .map(|recipient| recipient.pubkey)
+            .collect()
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn concurrent_failures_are_written_to_the_remaining_csv() {
+        let dir = tempfile::tempdir().unwrap();
+        let (options, run) = options(dir.path(), &["--max-tx-in-flight", "5"]);
+        let cluster = MockCluster::new();
+        let batches = five_batches();
+        // Batches 2 and 4 fail while the others are still in flight.
+        for index in [1, 3] {
+            cluster.fail_on_chain(
+                batches[index].transaction.signatures[0],
+                TransactionError::InsufficientFundsForRent { account_index: 1 },
+            );
+        }
+        let failed = recipients_of([&batches[1], &batches[3]]);
+        let confirmed = recipients_of([&batches[0], &batches[2], &batches[4]]);
+
+        let err = execute_airdrop_concurrent(
+            &options,
+            &run,
+            batches,
+            Arc::new(cluster.rpc_client()),
+            None,
+        )
+        .await
+        .unwrap_err();
+        assert_eq!(err.to_string(), "2 of 5 transaction(s) failed");
+
+        let remaining = recipients_in(&options.remaining_path);
+        assert_eq!(remaining, failed);
+        assert!(remaining.is_disjoint(&confirmed));
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn panic_mid_send_writes_the_unpaid_recipients() {
+        let dir = tempfile::tempdir().unwrap();
+        let (options, run) = options(dir.path(), &[]);
+        let cluster = MockCluster::new();
+        let batches = five_batches();
+        cluster.panic_on_send(batches[2].transaction.signatures[0]);
+        let paid = recipients_of(&batches[..2]);
+        let unpaid = recipients_of(&batches[2..]);
+
+        let err = run_airdrop(
+            &options,
+            &run,
+            batches,
+            Arc::new(cluster.rpc_client()),
+            None,
+        )
+        .await
+        .unwrap_err();
+        assert!(err.to_string().contains("scripted panic"), "{}", err);
+
+        let remaining = recipients_in(&options.remaining_path);
+        assert_eq!(remaining, unpaid);
+        assert!(remaining.is_disjoint(&paid));
+    }
+
+    /// An account of the Token-2022 program holding `state`.
+    fn token_program_account<T: Pack>(state: T) -> Account {
+        let mut data = vec![0; T::LEN];
+        state.pack_into_slice(&mut data);
+        Account {
+            lamports: mock::RENT_EXEMPT_LAMPORTS,
+            data,
+            owner: spl_token_2022::id(),
+            executable: false,
+            rent_epoch: 0,
+        }
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn separate_ata_phase_cuts_off_each_recipient_once() {
+        let dir = tempfile::tempdir().unwrap();
+        let cluster = MockCluster::new();
+        let source_keypair: Arc<dyn Signer> = Arc::new(Keypair::new());
+        let mint = Pubkey::new_unique();
+        cluster.set_account(
+            mint,
+            token_program_account(Mint {
+                supply: 6,
+                decimals: 0,
+                is_initialized: true,
+                ..Default::default()
+            }),
+        );
+        // Enough for the first two recipients but not the third.
+        cluster.set_account(
+            ata_address(
+                &spl_associated_token_account::id(),
+                &source_keypair.pubkey(),
+                &mint,
+            ),
+            token_program_account(TokenAccount {
+                mint,
+                owner: source_keypair.pubkey(),
+                amount: 3,
+                state: AccountState::Initialized,
+                ..Default::default()
+            }),
+        );
+        let (paid, cut_off) = (
+            [Pubkey::new_unique(), Pubkey::new_unique()],
+            Pubkey::new_unique(),
+        );
+        let csv_path = dir.path().join("recipients.csv");
+        std::fs::write(
+            &csv_path,
+            format!(
+                "address,amount\n{},1\n{},2\n{},3\n",
+                paid[0], paid[1], cut_off
+            ),
+        )
+        .unwrap();
+
+        let mut args = AirdropArgs::try_parse_from([
+            "thook",
+            &mint.to_string(),
+            csv_path.to_str().unwrap(),
+            "--output-dir",
+            dir.path().to_str().unwrap(),
+            "--poll-interval-ms",
+            "10",
+            "--separate-ata-creation",
+            "--stop-on-insufficient-balance",
+        ])
+        .unwrap();
+        let paths = args.options.resolve_output_paths(None).unwrap();
+        let run = &RunContext::new(&args.options, paths, 0, "http://localhost:8899");
+        let rpc_client = Arc::new(cluster.rpc_client());
+
+        let airdrop = prepare_airdrop(&args, run, &rpc_client, &source_keypair)
+            .await
+            .unwrap();
+        let batches = create_airdrop_tx(&args, run, &rpc_client, &source_keypair, &airdrop, true)
+            .await
+            .unwrap();
+        assert!(batches[0].transfers.is_empty(), "no ATA phase was built");
+        let batches = send_ata_phase(
+            &args,
+            run,
+            &airdrop,
+            batches,
+            rpc_client.clone(),
+            source_keypair.clone(),
+            None,
+        )
+        .await
+        .unwrap();
+        assert!(batches.iter().all(|batch| !batch.transfers.is_empty()));
+        run_airdrop(&args.options, run, batches, rpc_client, None)
+            .await
+            .unwrap();
+
+        let remaining =
+            recipients::read_recipients_csv(&args.options.remaining_path, 0, None, None, None)
+                .unwrap();
+        assert_eq!(
+            remaining
+                .iter()
+                .map(|recipient| (recipient.pubkey, recipient.amount))
+                .collect::<Vec<_>>(),
+            [(cut_off, 3)]
+        );
+    }
+
+    /// Lands `batches` on `cluster` and logs them in the signature log, as an
+    /// earlier run that confirmed them would have.
+    async fn land_and_log(cluster: &MockCluster, run: &RunContext, batches: &[Batch]) {
+        for batch in batches {
+            TransactionSender::send(cluster, &batch.transaction, send_config())
+                .await
+                .unwrap();
+            append_signature_log(
+                &run.paths.signatures,
+                &batch.transaction.signatures[0],
+                &batch.id,
+                batch
+                    .transfers
+                    .iter()
+                    .map(|transfer| (&transfer.recipient, transfer.amount)),
+                run.decimals,
+            )
+            .unwrap();
+        }
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn resumes_once_the_skipped_batches_are_confirmed() {
+        let dir = tempfile::tempdir().unwrap();
+        let (options, run) = options(dir.path(), &["--resume-from-batch", "3"]);
+        let cluster = MockCluster::new();
+        let batches = five_batches();
+        land_and_log(&cluster, &run, &batches[..2]).await;
+        let expected = ids(&batches[2..]).join(",");
+
+        let batches = skip_to_resume_point(&options, &run, &cluster.rpc_client(), batches)
+            .await
+            .unwrap();
+        assert_eq!(ids(&batches).join(","), expected);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn resumes_when_the_earlier_run_packed_batches_differently() {
+        let dir = tempfile::tempdir().unwrap();
+        let (options, run) = options(dir.path(), &["--resume-from-batch", "2"]);
+        let cluster = MockCluster::new();
+        let batches = five_batches();
+        // The earlier run paid the first batch's recipients in a transaction
+        // of its own, with another id and signature.
+        let payer = Keypair::new();
+        let mut earlier = sol_batch(&payer, 2);
+        for (logged, skipped) in earlier.transfers.iter_mut().zip(&batches[0].transfers) {
+            logged.recipient = skipped.recipient.clone();
+        }
+        land_and_log(&cluster, &run, &[earlier]).await;
+
+        let batches = skip_to_resume_point(&options, &run, &cluster.rpc_client(), batches)
+            .await
+            .unwrap();
+        assert_eq!(batches.len(), 4);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn refuses_to_skip_a_batch_that_never_confirmed() {
+        let dir = tempfile::tempdir().unwrap();
+        let (options, run) = options(dir.path(), &["--resume-from-batch", "3"]);
+        let cluster = MockCluster::new();
+        let batches = five_batches();
+        land_and_log(&cluster, &run, &batches[..1]).await;
+        // Logged, but failed on chain.
+        cluster.fail_on_chain(
+            batches[1].transaction.signatures[0],
+            TransactionError::InsufficientFundsForRent { account_index: 1 },
+        );
+        land_and_log(&cluster, &run, &batches[1..2]).await;
+        let unpaid = batches[1].transfers[0].recipient.pubkey;
+
+        let err = skip_to_resume_point(&options, &run, &cluster.rpc_client(), batches)
+            .await
+            .err()
+            .unwrap()
+            .to_string();
+        assert!(err.starts_with("2 recipient(s)"), "{}", err);
+        assert!(err.contains(&unpaid.to_string()), "{}", err);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn refuses_to_resume_without_a_signature_log() {
+        let dir = tempfile::tempdir().unwrap();
+        let (options, run) = options(dir.path(), &["--resume-from-batch", "2"]);
+        let cluster = MockCluster::new();
+        let err = skip_to_resume_point(&options, &run, &cluster.rpc_client(), five_batches())
+            .await
+            .err()
+            .unwrap();
+        assert!(err.to_string().contains("can't be checked"), "{}", err);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn refuses_to_resume_past_the_last_batch() {
+        let dir = tempfile::tempdir().unwrap();
+        let (options, run) = options(dir.path(), &["--resume-from-batch", "6"]);
+        let cluster = MockCluster::new();
+        let err = skip_to_resume_point(&options, &run, &cluster.rpc_client(), five_batches())
+            .await
+            .err()
+            .unwrap();
+        assert!(err.to_string().contains("out of range"), "{}", err);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn missing_account_is_none() {
+        let cluster = MockCluster::new();
+        let account = fetch_account(
+            &cluster.rpc_client(),
+            &Pubkey::new_unique(),
+            CommitmentConfig::confirmed(),
+        )
+        .await
+        .unwrap();
+        assert!(account.is_none());
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn rpc_error_fetching_an_account_is_an_error() {
+        let cluster = MockCluster::new();
+        cluster.make_unreachable(RpcRequest::GetAccountInfo);
+        let address = Pubkey::new_unique();
+        let err = fetch_account(
+            &cluster.rpc_client(),
+            &address,
+            CommitmentConfig::confirmed(),
+        )
+        .await
+        .unwrap_err();
+        assert!(err.to_string().contains(&address.to_string()), "{}", err);
+    }
+
+    #[test]
+    fn needs_flush_never_flushes_an_empty_batch() {
+        assert!(!needs_flush(0, 1));
+        // A group too big for a transaction of its own is still sent alone.
+        assert!(!needs_flush(0, MAX_INSTRUCTIONS_PER_TX + 1));
+    }
+
+    #[test]
+    fn needs_flush_at_the_instruction_limit() {
+        // The batched instructions, the incoming group and one more must fit.
+        assert!(!needs_flush(1, MAX_INSTRUCTIONS_PER_TX - 2));
+        assert!(needs_flush(1, MAX_INSTRUCTIONS_PER_TX - 1));
+        assert!(!needs_flush(MAX_INSTRUCTIONS_PER_TX - 2, 1));
+        assert!(needs_flush(MAX_INSTRUCTIONS_PER_TX - 1, 1));
+        assert!(needs_flush(MAX_INSTRUCTIONS_PER_TX, 1));
+    }
+
+    #[test]
+    fn create_before_transfer_is_atomic() {
+        let (owner, mint) = (Pubkey::new_unique(), Pubkey::new_unique());
+        let (transfer, instruction) = token_transfer(&owner, &mint, true);
+        assert!(transfers_are_atomic(
+            &[create_ata(&owner, &mint), instruction],
+            &[transfer],
+            &spl_associated_token_account::id()
+        ));
+    }
+
+    #[test]
+    fn create_after_transfer_is_not_atomic() {
+        let (owner, mint) = (Pubkey::new_unique(), Pubkey::new_unique());
+        let (transfer, instruction) = token_transfer(&owner, &mint, true);
+        assert!(!transfers_are_atomic(
+            &[instruction, create_ata(&owner, &mint)],
+            &[transfer],
+            &spl_associated_token_account::id()
+        ));
+    }
+
+    #[test]
+    fn transfer_without_its_create_is_not_atomic() {
+        let (owner, mint) = (Pubkey::new_unique(), Pubkey::new_unique());
+        let (transfer, instruction) = token_transfer(&owner, &mint, true);
+        assert!(!transfers_are_atomic(
+            &[instruction],
+            &[transfer],
+            &spl_associated_token_account::id()
+        ));
+    }
+
+    #[test]
+    fn transfer_into_an_existing_ata_needs_no_create() {
+        let (owner, mint) = (Pubkey::new_unique(), Pubkey::new_unique());
+        let (transfer, instruction) = token_transfer(&owner, &mint, false);
+        assert!(transfers_are_atomic(
+            &[instruction],
+            &[transfer],
+            &spl_associated_token_account::id()
+        ));
+    }
+
+    #[test]
+    fn missing_transfer_is_not_atomic() {
+        let (owner, mint) = (Pubkey::new_unique(), Pubkey::new_unique());
+        let (transfer, _instruction) = token_transfer(&owner, &mint, true);
+        assert!(!transfers_are_atomic(
+            &[create_ata(&owner, &mint)],
+            &[transfer],
+            &spl_associated_token_account::id()
+        ));
+    }
+
+    #[test]
+    fn full_batch_with_every_create_before_its_transfer_is_atomic() {
+        let mint = Pubkey::new_unique();
+        let (first, second) = (Pubkey::new_unique(), Pubkey::new_unique());
+        let (first_transfer, first_instruction) = token_transfer(&first, &mint, true);
+        let (second_transfer, second_instruction) = token_transfer(&second, &mint, true);
+        let instructions = [
+            create_ata(&first, &mint),
+            first_instruction,
+            create_ata(&second, &mint),
+            second_instruction,
+        ];
+        assert_eq!(instructions.len(), MAX_INSTRUCTIONS_PER_TX);
+        assert!(transfers_are_atomic(
+            &instructions,
+            &[first_transfer, second_transfer],
+            &spl_associated_token_account::id()
+        ));
+    }
+
+    #[test]
+    fn sol_transfer_is_atomic() {
+        let (source, wallet) = (Pubkey::new_unique(), Pubkey::new_unique());
+        let transfer = Transfer {
+            recipient: recipient(wallet, 1),
+            destination: wallet,
+            amount: 1,
+            source,
+            creates_ata: false,
+            extra_accounts: Vec::new(),
+        };
+        assert!(transfers_are_atomic(
+            &[system_instruction::transfer(&source, &wallet, 1)],
+            &[transfer],
+            &spl_associated_token_account::id()
+        ));
+    }
+
+    #[test]
+    fn total_of_exactly_u64_max_fits() {
+        let recipients = [
+            recipient(Pubkey::new_unique(), u64::MAX / 2),
+            recipient(Pubkey::new_unique(), u64::MAX / 2 + 1),
+        ];
+        assert!(check_total_fits(&recipients).is_ok());
+    }
+
+    #[test]
+    fn total_past_u64_max_is_rejected() {
+        let second = Pubkey::new_unique();
+        let recipients = [
+            recipient(Pubkey::new_unique(), u64::MAX / 2 + 1),
+            recipient(second, u64::MAX / 2 + 1),
+        ];
+        let err = check_total_fits(&recipients).unwrap_err().to_string();
+        assert!(
+            err.contains(&format!("recipient 2 ({})", second)),
+            "{}",
+            err
+        );
+    }
 }
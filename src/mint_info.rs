@@ -0,0 +1,135 @@
+use {
+    crate::{amount::base_units_to_ui_amount, hook, owner_program_name, MintInfoArgs},
+    solana_client::nonblocking::rpc_client::RpcClient,
+    solana_sdk::pubkey::Pubkey,
+    spl_token_2022::{
+        extension::{
+            default_account_state::DefaultAccountState, metadata_pointer::MetadataPointer,
+            non_transferable::NonTransferable, transfer_fee::TransferFeeConfig, transfer_hook,
+            BaseStateWithExtensions, StateWithExtensions,
+        },
+        state::{AccountState, Mint},
+    },
+    spl_token_metadata_interface::state::TokenMetadata,
+    std::error::Error,
+};
+
+/// Formats an optional authority, which most mints leave unset once they are
+/// set up.
+fn authority(authority: Option<Pubkey>) -> String {
+    authority
+        .map(|authority| authority.to_string())
+        .unwrap_or_else(|| "none".to_string())
+}
+
+/// Prints what matters about a mint before airdropping it: its program,
+/// decimals, supply and authorities, then each extension that changes how a
+/// transfer behaves. Mints of either token program are described, though
+/// only Token-2022 mints can be airdropped.
+pub async fn print_mint_info(
+    args: &MintInfoArgs,
+    rpc_client: &RpcClient,
+) -> Result<(), Box<dyn Error>> {
+    let mint = args.token_address;
+    let account = rpc_client
+        .get_account_with_commitment(&mint, rpc_client.commitment())
+        .await?
+        .value
+        .ok_or_else(|| format!("Token address {} does not exist", mint))?;
+    spl_token_2022::check_spl_token_program_account(&account.owner).map_err(|_| {
+        format!(
+            "{} is owned by {}, not a token program",
+            mint,
+            owner_program_name(&account.owner)
+        )
+    })?;
+    let state = StateWithExtensions::<Mint>::unpack(&account.data)
+        .map_err(|_| format!("{} is not a mint account", mint))?;
+    let decimals = state.base.decimals;
+
+    println!("Mint: {}", mint);
+    println!(
+        "Program: {}",
+        if account.owner == spl_token_2022::id() {
+            format!("Token-2022 ({})", account.owner)
+        } else {
+            format!(
+                "SPL Token ({}); only Token-2022 mints can be airdropped",
+                account.owner
+            )
+        }
+    );
+    println!("Decimals: {}", decimals);
+    println!(
+        "Supply: {} ({} base units)",
+        base_units_to_ui_amount(state.base.supply, decimals),
+        state.base.supply
+    );
+    println!(
+        "Mint authority: {}",
+        authority(state.base.mint_authority.into())
+    );
+    println!(
+        "Freeze authority: {}",
+        authority(state.base.freeze_authority.into())
+    );
+
+    let extensions = state.get_extension_types()?;
+    if extensions.is_empty() {
+        println!("Extensions: none");
+        return Ok(());
+    }
+    println!(
+        "Extensions: {}",
+        extensions
+            .iter()
+            .map(|extension| format!("{:?}", extension))
+            .collect::<Vec<_>>()
+            .join(", ")
+    );
+
+    if let Some(program_id) = transfer_hook::get_program_id(&state) {
+        // The same check the airdrop runs before building any transfers.
+        match hook::check_transfer_hook(rpc_client, &mint).await {
+            Ok(_) => println!(
+                "Transfer hook: {}, extra accounts can be resolved",
+                program_id
+            ),
+            Err(err) => println!(
+                "Transfer hook: {}, extra accounts can't be resolved ⚠️: {}",
+                program_id, err
+            ),
+        }
+    }
+    if let Ok(config) = state.get_extension::<TransferFeeConfig>() {
+        let epoch = rpc_client.get_epoch_info().await?.epoch;
+        let fee = config.get_epoch_fee(epoch);
+        println!(
+            "Transfer fee: {} basis points, at most {} per transfer; recipients get the amount less the fee",
+            u16::from(fee.transfer_fee_basis_points),
+            base_units_to_ui_amount(u64::from(fee.maximum_fee), decimals)
+        );
+    }
+    if state.get_extension::<NonTransferable>().is_ok() {
+        println!("Non-transferable: tokens can't be transferred, so they can't be airdropped ⚠️");
+    }
+    if let Ok(default_state) = state.get_extension::<DefaultAccountState>() {
+        match AccountState::try_from(default_state.state) {
+            Ok(AccountState::Frozen) => println!(
+                "Default account state: frozen; new token accounts must be thawed before they can receive tokens ⚠️"
+            ),
+            Ok(default_state) => println!("Default account state: {:?}", default_state),
+            Err(_) => println!("Default account state: unknown ({})", default_state.state),
+        }
+    }
+    if let Ok(metadata) = state.get_variable_len_extension::<TokenMetadata>() {
+        println!("Name: {}", metadata.name);
+        println!("Symbol: {}", metadata.symbol);
+    } else if let Ok(pointer) = state.get_extension::<MetadataPointer>() {
+        // Metadata kept in another account, e.g. by another program.
+        if let Some(address) = Option::<Pubkey>::from(pointer.metadata_address) {
+            println!("Metadata: stored in {}", address);
+        }
+    }
+    Ok(())
+}
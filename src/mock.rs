@@ -0,0 +1,264 @@
+use {
+    crate::sender::TransactionSender,
+    async_trait::async_trait,
+    serde_json::{json, Value},
+    solana_account_decoder::{UiAccount, UiAccountEncoding},
+    solana_client::{
+        client_error::{ClientError, ClientErrorKind, Result as ClientResult},
+        nonblocking::rpc_client::RpcClient,
+        rpc_client::RpcClientConfig,
+        rpc_config::RpcSendTransactionConfig,
+        rpc_request::{RpcError, RpcRequest},
+        rpc_sender::{RpcSender, RpcTransportStats},
+    },
+    solana_sdk::{
+        account::Account,
+        commitment_config::CommitmentConfig,
+        hash::Hash,
+        pubkey::Pubkey,
+        signature::Signature,
+        transaction::{Transaction, TransactionError},
+    },
+    solana_transaction_status::{
+        EncodedTransaction, TransactionBinaryEncoding, TransactionConfirmationStatus,
+        TransactionStatus,
+    },
+    std::{
+        collections::{HashMap, HashSet, VecDeque},
+        str::FromStr,
+        sync::{Arc, Mutex, MutexGuard},
+    },
+};
+
+/// A scripted stand-in for the cluster, so the send and confirm paths can be
+/// tested without a validator. A transaction lands and confirms as soon as it
+/// is sent, unless it was scripted to fail, but has no effect on the accounts
+/// the cluster serves. Clones share their state, so one
+/// can be handed to an `RpcClient` and another kept to check what landed.
+#[derive(Clone, Default)]
+pub struct MockCluster {
+    state: Arc<Mutex<MockState>>,
+}
+
+#[derive(Default)]
+struct MockState {
+    blockhash: Hash,
+    accounts: HashMap<Pubkey, Account>,
+    /// Errors the next sends fail with, in order, without landing anything.
+    send_errors: VecDeque<ClientError>,
+    /// How many of the next sends land but have their response lost.
+    lost_responses: usize,
+    /// Transactions that land but fail, by signature.
+    on_chain_errors: HashMap<Signature, TransactionError>,
+    /// Transactions whose send panics, by signature.
+    panics: HashSet<Signature>,
+    /// Requests that fail as if the RPC couldn't be reached.
+    unreachable: HashSet<RpcRequest>,
+    /// Transactions that landed, and the error of those that failed.
+    landed: HashMap<Signature, Option<TransactionError>>,
+    /// Every send that reached the cluster, retries included.
+    sends: usize,
+}
+
+/// What the mock charges for rent exemption, whatever the size.
+pub const RENT_EXEMPT_LAMPORTS: u64 = 2_039_280;
+
+/// What the mock charges per transaction.
+pub const FEE_LAMPORTS: u64 = 5_000;
+
+/// The error a dropped connection gives, which is worth retrying.
+pub fn transient_error() -> ClientError {
+    ClientErrorKind::Custom("connection reset by peer".to_string()).into()
+}
+
+impl MockCluster {
+    pub fn new() -> Self {
+        let cluster = Self::default();
+        cluster.state().blockhash = Hash::new_unique();
+        cluster
+    }
+
+    /// A client whose every request is answered by this cluster.
+    pub fn rpc_client(&self) -> RpcClient {
+        RpcClient::new_sender(
+            self.clone(),
+            RpcClientConfig::with_commitment(CommitmentConfig::confirmed()),
+        )
+    }
+
+    fn state(&self) -> MutexGuard<'_, MockState> {
+        self.state.lock().unwrap()
+    }
+
+    pub fn set_account(&self, address: Pubkey, account: Account) {
+        self.state().accounts.insert(address, account);
+    }
+
+    /// Fails the next send that isn't already failing with `err`.
+    pub fn fail_next_send(&self, err: ClientError) {
+        self.state().send_errors.push_back(err);
+    }
+
+    /// Lands the next transaction sent but answers with a transient error,
+    /// as when the response is lost on the way back.
+    pub fn lose_next_response(&self) {
+        self.state().lost_responses += 1;
+    }
+
+    /// Lands the transaction signed `signature` with `err` when it is sent.
+    pub fn fail_on_chain(&self, signature: Signature, err: TransactionError) {
+        self.state().on_chain_errors.insert(signature, err);
+    }
+
+    /// Panics when the transaction signed `signature` is sent, as a bug
+    /// somewhere in the send path would.
+    pub fn panic_on_send(&self, signature: Signature) {
+        self.state().panics.insert(signature);
+    }
+
+    /// Fails every `request` as if the RPC couldn't be reached.
+    pub fn make_unreachable(&self, request: RpcRequest) {
+        self.state().unreachable.insert(request);
+    }
+
+    pub fn sends(&self) -> usize {
+        self.state().sends
+    }
+
+    pub fn landed(&self, signature: &Signature) -> bool {
+        self.state().landed.contains_key(signature)
+    }
+
+    fn status(&self, signature: &Signature) -> Option<TransactionStatus> {
+        let err = self.state().landed.get(signature)?.clone();
+        Some(TransactionStatus {
+            slot: 1,
+            confirmations: Some(1),
+            status: err.clone().map_or(Ok(()), Err),
+            err,
+            confirmation_status: Some(TransactionConfirmationStatus::Confirmed),
+        })
+    }
+}
+
+impl TransactionSender for MockCluster {
+    async fn send(
+        &self,
+        transaction: &Transaction,
+        _config: RpcSendTransactionConfig,
+    ) -> Result<Signature, ClientError> {
+        let mut state = self.state();
+        state.sends += 1;
+        if let Some(err) = state.send_errors.pop_front() {
+            return Err(err);
+        }
+        let signature = transaction.signatures[0];
+        if state.panics.contains(&signature) {
+            drop(state);
+            panic!("scripted panic sending {}", signature);
+        }
+        if state.landed.contains_key(&signature) {
+            return Err(
+                ClientErrorKind::TransactionError(TransactionError::AlreadyProcessed).into(),
+            );
+        }
+        let err = state.on_chain_errors.get(&signature).cloned();
+        state.landed.insert(signature, err);
+        if state.lost_responses > 0 {
+            state.lost_responses -= 1;
+            return Err(transient_error());
+        }
+        Ok(signature)
+    }
+
+    async fn get_latest_blockhash(&self) -> Result<Hash, ClientError> {
+        Ok(self.state().blockhash)
+    }
+}
+
+#[async_trait]
+impl RpcSender for MockCluster {
+    async fn send(&self, request: RpcRequest, params: Value) -> ClientResult<Value> {
+        if self.state().unreachable.contains(&request) {
+            return Err(
+                RpcError::RpcRequestError(format!("{} could not be reached", request)).into(),
+            );
+        }
+        let context = json!({ "slot": 1 });
+        Ok(match request {
+            RpcRequest::GetVersion => json!({ "solana-core": "1.18.0", "feature-set": 0 }),
+            RpcRequest::GetLatestBlockhash => json!({
+                "context": context,
+                "value": {
+                    "blockhash": self.state().blockhash.to_string(),
+                    "lastValidBlockHeight": 1000,
+                },
+            }),
+            RpcRequest::IsBlockhashValid => json!({ "context": context, "value": true }),
+            RpcRequest::GetAccountInfo => {
+                let account = params[0]
+                    .as_str()
+                    .and_then(|address| Pubkey::from_str(address).ok())
+                    .and_then(|address| {
+                        let account = self.state().accounts.get(&address).cloned()?;
+                        Some(UiAccount::encode(
+                            &address,
+                            &account,
+                            UiAccountEncoding::Base64,
+                            None,
+                            None,
+                        ))
+                    });
+                json!({ "context": context, "value": account })
+            }
+            RpcRequest::GetMinimumBalanceForRentExemption => json!(RENT_EXEMPT_LAMPORTS),
+            RpcRequest::GetFeeForMessage => json!({ "context": context, "value": FEE_LAMPORTS }),
+            RpcRequest::GetBalance => json!({ "context": context, "value": 1_000_000_000_000u64 }),
+            RpcRequest::SendTransaction => {
+                let transaction = params[0]
+                    .as_str()
+                    .and_then(|encoded| {
+                        EncodedTransaction::Binary(
+                            encoded.to_string(),
+                            TransactionBinaryEncoding::Base64,
+                        )
+                        .decode()
+                    })
+                    .and_then(|transaction| transaction.into_legacy_transaction())
+                    .ok_or_else(|| RpcError::ParseError("transaction".to_string()))?;
+                json!(TransactionSender::send(
+                    self,
+                    &transaction,
+                    RpcSendTransactionConfig::default()
+                )
+                .await?
+                .to_string())
+            }
+            RpcRequest::GetSignatureStatuses => {
+                let statuses: Vec<Option<TransactionStatus>> = params[0]
+                    .as_array()
+                    .into_iter()
+                    .flatten()
+                    .map(|signature| {
+                        signature
+                            .as_str()
+                            .and_then(|signature| Signature::from_str(signature).ok())
+                            .and_then(|signature| self.status(&signature))
+                    })
+                    .collect();
+                json!({ "context": context, "value": statuses })
+            }
+            request => {
+                return Err(RpcError::RpcRequestError(format!("{} is not mocked", request)).into())
+            }
+        })
+    }
+
+    fn get_transport_stats(&self) -> RpcTransportStats {
+        RpcTransportStats::default()
+    }
+
+    fn url(&self) -> String {
+        "mock".to_string()
+    }
+}
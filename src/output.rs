@@ -0,0 +1,63 @@
+use std::{
+    error::Error,
+    path::Path,
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+/// Names the files a run writes from `--output-template`, so runs of the same
+/// campaign on different machines or jobs don't overwrite each other.
+pub struct OutputTemplate {
+    template: String,
+    mint: String,
+    run_id: Option<String>,
+    timestamp: u64,
+}
+
+impl OutputTemplate {
+    /// Checks the template up front, so a typo fails before anything is sent
+    /// rather than when the first file is written. `mint` is `None` for SOL
+    /// airdrops, where `{mint}` becomes `sol`.
+    pub fn new(
+        template: &str,
+        mint: Option<&str>,
+        run_id: Option<&str>,
+    ) -> Result<Self, Box<dyn Error>> {
+        if !template.contains("{name}") {
+            return Err(format!(
+                "--output-template {} has no {{name}}, so every output file would get the same name",
+                template
+            )
+            .into());
+        }
+        if template.contains("{runid}") && run_id.is_none() {
+            return Err("--output-template uses {runid} but no --run-id was given".into());
+        }
+        Ok(Self {
+            template: template.to_string(),
+            mint: mint.unwrap_or("sol").to_string(),
+            run_id: run_id.map(str::to_string),
+            timestamp: SystemTime::now().duration_since(UNIX_EPOCH)?.as_secs(),
+        })
+    }
+
+    /// The templated name for the file normally written to `default_path`,
+    /// keeping its directory and extension, e.g. `signatures.csv` becomes
+    /// `signatures_<mint>_<runid>.csv` for `{name}_{mint}_{runid}`.
+    pub fn apply(&self, default_path: &str) -> String {
+        let path = Path::new(default_path);
+        let name = path
+            .file_stem()
+            .map(|stem| stem.to_string_lossy())
+            .unwrap_or_default();
+        let mut file_name = self
+            .template
+            .replace("{name}", &name)
+            .replace("{mint}", &self.mint)
+            .replace("{runid}", self.run_id.as_deref().unwrap_or_default())
+            .replace("{timestamp}", &self.timestamp.to_string());
+        if let Some(extension) = path.extension() {
+            file_name = format!("{}.{}", file_name, extension.to_string_lossy());
+        }
+        path.with_file_name(file_name).display().to_string()
+    }
+}
@@ -0,0 +1,221 @@
+use {
+    crate::{amount::base_units_to_ui_amount, Batch},
+    base64::{engine::general_purpose::STANDARD, Engine},
+    csv::Writer,
+    serde::Serialize,
+    solana_sdk::{bs58, instruction::AccountMeta, pubkey::Pubkey},
+    std::{error::Error, fs::File},
+};
+
+#[derive(Serialize)]
+struct Plan {
+    /// Absent for native SOL airdrops.
+    mint: Option<String>,
+    source: String,
+    source_ata: Option<String>,
+    /// How many recipients the run was given, and a hash of them and their
+    /// amounts to compare with `--expect-hash`.
+    recipient_count: usize,
+    recipients_hash: String,
+    transactions: Vec<PlannedTransaction>,
+}
+
+#[derive(Serialize)]
+struct PlannedTransaction {
+    index: usize,
+    batch_id: String,
+    signature: String,
+    /// The signed transaction in base64 wire format, ready for
+    /// `sendTransaction`.
+    transaction: String,
+    instructions: Vec<PlannedInstruction>,
+    transfers: Vec<PlannedTransfer>,
+}
+
+#[derive(Serialize)]
+struct PlannedInstruction {
+    program_id: String,
+    accounts: Vec<PlannedAccount>,
+    /// Instruction data in base58, matching the RPC's `json` encoding.
+    data: String,
+}
+
+#[derive(Serialize)]
+struct PlannedAccount {
+    pubkey: String,
+    is_signer: bool,
+    is_writable: bool,
+}
+
+impl From<&AccountMeta> for PlannedAccount {
+    fn from(meta: &AccountMeta) -> Self {
+        Self {
+            pubkey: meta.pubkey.to_string(),
+            is_signer: meta.is_signer,
+            is_writable: meta.is_writable,
+        }
+    }
+}
+
+#[derive(Serialize)]
+struct PlannedTransfer {
+    recipient: String,
+    label: Option<String>,
+    ata_owner: String,
+    destination_ata: String,
+    /// The token account or, for SOL, the wallet this transfer is paid from.
+    source: String,
+    amount: u64,
+    creates_ata: bool,
+    extra_accounts: Vec<PlannedAccount>,
+}
+
+/// Serializes the full airdrop plan so it can be reviewed, diffed between
+/// runs, or broadcast separately.
+pub fn write_plan(
+    path: &str,
+    mint: Option<&Pubkey>,
+    source: &Pubkey,
+    source_ata: Option<&Pubkey>,
+    recipient_count: usize,
+    recipients_hash: &str,
+    batches: &[Batch],
+) -> Result<(), Box<dyn Error>> {
+    let mut transactions = Vec::with_capacity(batches.len());
+    for (index, batch) in batches.iter().enumerate() {
+        let message = &batch.transaction.message;
+        let instructions = message
+            .instructions
+            .iter()
+            .map(|instruction| PlannedInstruction {
+                program_id: message.account_keys[instruction.program_id_index as usize].to_string(),
+                accounts: instruction
+                    .accounts
+                    .iter()
+                    .map(|account_index| {
+                        let i = *account_index as usize;
+                        PlannedAccount {
+                            pubkey: message.account_keys[i].to_string(),
+                            is_signer: message.is_signer(i),
+                            is_writable: message.is_writable(i),
+                        }
+                    })
+                    .collect(),
+                data: bs58::encode(&instruction.data).into_string(),
+            })
+            .collect();
+
+        let transfers = batch
+            .transfers
+            .iter()
+            .map(|transfer| PlannedTransfer {
+                recipient: transfer.recipient.pubkey.to_string(),
+                label: transfer.recipient.label.clone(),
+                ata_owner: transfer.recipient.token_owner().to_string(),
+                destination_ata: transfer.destination.to_string(),
+                source: transfer.source.to_string(),
+                amount: transfer.amount,
+                creates_ata: transfer.creates_ata,
+                extra_accounts: transfer
+                    .extra_accounts
+                    .iter()
+                    .map(PlannedAccount::from)
+                    .collect(),
+            })
+            .collect();
+
+        transactions.push(PlannedTransaction {
+            index,
+            batch_id: batch.id.clone(),
+            signature: batch.transaction.signatures[0].to_string(),
+            transaction: STANDARD.encode(bincode::serialize(&batch.transaction)?),
+            instructions,
+            transfers,
+        });
+    }
+
+    let plan = Plan {
+        mint: mint.map(Pubkey::to_string),
+        source: source.to_string(),
+        source_ata: source_ata.map(Pubkey::to_string),
+        recipient_count,
+        recipients_hash: recipients_hash.to_string(),
+        transactions,
+    };
+    serde_json::to_writer_pretty(File::create(path)?, &plan)?;
+
+    Ok(())
+}
+
+/// Shows which recipients share each transaction, and which transfers also
+/// create their ATA, as a CSV at `path` or printed if there is no path.
+pub fn write_batch_report(
+    path: Option<&str>,
+    batches: &[Batch],
+    decimals: u8,
+) -> Result<(), Box<dyn Error>> {
+    let Some(path) = path else {
+        for (index, batch) in batches.iter().enumerate() {
+            let creates = batch
+                .transfers
+                .iter()
+                .filter(|transfer| transfer.creates_ata)
+                .count();
+            println!(
+                "Transaction {}/{} (batch {}): {} recipient(s), {} ATA create(s)",
+                index + 1,
+                batches.len(),
+                batch.id,
+                batch.transfers.len(),
+                creates
+            );
+            for transfer in batch.transfers.iter() {
+                println!(
+                    "  {} {} -> {}{}",
+                    transfer.recipient.pubkey,
+                    base_units_to_ui_amount(transfer.amount, decimals),
+                    transfer.destination,
+                    if transfer.creates_ata {
+                        " (creates ATA)"
+                    } else {
+                        ""
+                    }
+                );
+            }
+        }
+        return Ok(());
+    };
+
+    let mut writer = Writer::from_path(path)?;
+    writer.write_record([
+        "transaction",
+        "batch_id",
+        "recipient",
+        "label",
+        "destination",
+        "amount",
+        "creates_ata",
+        "source",
+    ])?;
+    for (index, batch) in batches.iter().enumerate() {
+        for transfer in batch.transfers.iter() {
+            writer.write_record([
+                (index + 1).to_string(),
+                batch.id.clone(),
+                transfer.recipient.pubkey.to_string(),
+                transfer.recipient.label.clone().unwrap_or_default(),
+                transfer.destination.to_string(),
+                base_units_to_ui_amount(transfer.amount, decimals),
+                transfer.creates_ata.to_string(),
+                transfer.source.to_string(),
+            ])?;
+        }
+    }
+    writer.flush()?;
+    println!(
+        "Wrote the recipients of {} transaction(s) to {}",
+        batches.len(),
+        path
+    );
+    Ok(())
+}
@@ -0,0 +1,106 @@
+use {
+    crate::amount::{base_units_to_ui_amount, ui_amount_to_base_units},
+    std::{error::Error, fmt, time::Duration},
+};
+
+/// Decimal places USD amounts are read with, i.e. to the micro-dollar.
+pub const USD_DECIMALS: u8 = 6;
+
+/// Decimal places a price is kept to, enough for tokens worth a tiny
+/// fraction of a cent.
+const PRICE_DECIMALS: u8 = 12;
+
+/// How long a price lookup may take, so a hanging endpoint can't stall the
+/// airdrop.
+const PRICE_LOOKUP_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// The USD price of one whole token, in units of 10^-`PRICE_DECIMALS` USD.
+#[derive(Clone, Copy, Debug)]
+pub struct UsdPrice(u64);
+
+impl UsdPrice {
+    /// Reads a price such as `0.9998` or `1.5e-7`.
+    pub fn parse(price: &str) -> Result<Self, String> {
+        let units = ui_amount_to_base_units(price, PRICE_DECIMALS)
+            .map_err(|err| format!("Invalid price: {}", err))?;
+        if units == 0 {
+            return Err(format!("Invalid price {}: it must be more than 0", price));
+        }
+        Ok(Self(units))
+    }
+
+    /// GETs `url` and reads the price from the response: the whole body if
+    /// it's a number, otherwise the value at the dot-separated `field` path.
+    /// Numbers and numeric strings are both accepted.
+    pub async fn fetch(url: &str, field: Option<&str>) -> Result<Self, Box<dyn Error>> {
+        let body: serde_json::Value = tokio::time::timeout(PRICE_LOOKUP_TIMEOUT, async {
+            reqwest::get(url).await?.error_for_status()?.json().await
+        })
+        .await
+        .map_err(|_| format!("{} did not respond within {:?}", url, PRICE_LOOKUP_TIMEOUT))?
+        .map_err(|err| format!("Could not fetch the price from {}: {}", url, err))?;
+
+        let value = match field {
+            Some(path) => path
+                .split('.')
+                .try_fold(&body, |value, key| value.get(key))
+                .ok_or_else(|| format!("The response of {} has no field {}", url, path))?,
+            None => &body,
+        };
+        let price = match value {
+            // Rounded to the precision kept, since an f64 printed in full
+            // can have more decimal places than that.
+            serde_json::Value::Number(number) => number
+                .as_f64()
+                .map(|price| format!("{:.*}", PRICE_DECIMALS as usize, price)),
+            serde_json::Value::String(price) => Some(price.clone()),
+            _ => None,
+        }
+        .ok_or_else(|| format!("Expected a price from {}, found {}", url, value))?;
+        Ok(Self::parse(&price)?)
+    }
+
+    /// Converts a USD amount, in units of 10^-`USD_DECIMALS` USD, into base
+    /// units of a token with `decimals`. Rounds down, so nobody is sent more
+    /// than their amount is worth.
+    pub fn usd_to_base_units(&self, usd: u64, decimals: u8) -> Result<u64, String> {
+        let too_large = || {
+            format!(
+                "${} is too many tokens at ${} each",
+                base_units_to_ui_amount(usd, USD_DECIMALS),
+                self
+            )
+        };
+        // usd * 10^decimals / price, with both sides scaled to whole units.
+        let scale = 10u128
+            .checked_pow(decimals as u32 + PRICE_DECIMALS as u32 - USD_DECIMALS as u32)
+            .ok_or_else(too_large)?;
+        let base_units = (usd as u128).checked_mul(scale).ok_or_else(too_large)? / self.0 as u128;
+        u64::try_from(base_units).map_err(|_| too_large())
+    }
+}
+
+impl fmt::Display for UsdPrice {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", base_units_to_ui_amount(self.0, PRICE_DECIMALS))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn converts_usd_at_the_price() {
+        let price = UsdPrice::parse("0.5").unwrap();
+        // $1.50 at $0.50 a token, with 9 decimals.
+        assert_eq!(price.usd_to_base_units(1_500_000, 9), Ok(3_000_000_000));
+    }
+
+    #[test]
+    fn rejects_a_mint_with_too_many_decimals() {
+        let price = UsdPrice::parse("1").unwrap();
+        assert!(price.usd_to_base_units(1, 244).is_err());
+        assert!(price.usd_to_base_units(1, u8::MAX).is_err());
+    }
+}
@@ -0,0 +1,397 @@
+use {
+    crate::amount::ui_amount_to_base_units,
+    csv::{Reader, ReaderBuilder, StringRecord, Trim},
+    flate2::read::GzDecoder,
+    solana_sdk::pubkey::Pubkey,
+    std::{
+        collections::{BTreeMap, HashMap, HashSet},
+        convert::Infallible,
+        error::Error,
+        fs::File,
+        io::{BufRead, BufReader, Read},
+        str::FromStr,
+    },
+};
+
+/// Header of the label column in files this tool writes.
+pub const LABEL_HEADER: &str = "label";
+
+/// Header of the column the tool's own output files record batch ids in.
+pub const BATCH_ID_HEADER: &str = "batch_id";
+
+/// The CSV column holding each recipient's label, by header name or
+/// zero-based index.
+#[derive(Clone, Debug, PartialEq)]
+pub enum LabelColumn {
+    Index(usize),
+    Name(String),
+}
+
+impl FromStr for LabelColumn {
+    type Err = Infallible;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        Ok(match s.parse() {
+            Ok(index) => LabelColumn::Index(index),
+            Err(_) => LabelColumn::Name(s.to_string()),
+        })
+    }
+}
+
+/// A recipient and the amount requested for them, in base units.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Recipient {
+    pub pubkey: Pubkey,
+    pub amount: u64,
+    /// Owner of the token account that receives the tokens, if it isn't the
+    /// recipient itself, e.g. a program or custodian.
+    pub ata_owner: Option<Pubkey>,
+    /// The operator's identifier for the recipient, e.g. an email or campaign
+    /// id, carried through to the signature log.
+    pub label: Option<String>,
+    /// The id of the batch that last tried to pay the recipient, set when
+    /// the batch is built or read back from a file the tool wrote.
+    pub batch_id: Option<String>,
+}
+
+impl Recipient {
+    /// The wallet the destination ATA is derived from.
+    pub fn token_owner(&self) -> &Pubkey {
+        self.ata_owner.as_ref().unwrap_or(&self.pubkey)
+    }
+}
+
+/// Whether `path` names a gzip-compressed file, judged by its extension.
+pub fn is_gzip(path: &str) -> bool {
+    path.ends_with(".gz")
+}
+
+/// The byte order mark spreadsheet exports often start UTF-8 files with.
+const UTF8_BOM: &[u8] = b"\xef\xbb\xbf";
+
+/// Opens a file for reading, decompressing it on the fly if it is gzipped.
+/// A leading UTF-8 byte order mark is skipped, so it doesn't end up in the
+/// first header or address.
+pub fn open_input(path: &str) -> Result<Box<dyn Read>, Box<dyn Error>> {
+    let file = File::open(path).map_err(|err| format!("Could not open {}: {}", path, err))?;
+    let mut reader: Box<dyn BufRead> = if is_gzip(path) {
+        Box::new(BufReader::new(GzDecoder::new(BufReader::new(file))))
+    } else {
+        Box::new(BufReader::new(file))
+    };
+    if reader
+        .fill_buf()
+        .map_err(|err| format!("Could not read {}: {}", path, err))?
+        .starts_with(UTF8_BOM)
+    {
+        reader.consume(UTF8_BOM.len());
+    }
+    Ok(Box::new(reader))
+}
+
+/// Opens a CSV for reading, decompressing it on the fly if it is gzipped.
+/// Whitespace around fields is trimmed, so a quoted field padded with spaces
+/// still reads as its value.
+fn open_csv(path: &str) -> Result<Reader<Box<dyn Read>>, Box<dyn Error>> {
+    Ok(ReaderBuilder::new()
+        .trim(Trim::All)
+        .from_reader(open_input(path)?))
+}
+
+/// Field `index` of `record`, without quotes left around it. A quote only
+/// opens a quoted field at its very start, so `"value"` padded with spaces
+/// keeps its quotes through parsing and the trim.
+fn field(record: &StringRecord, index: usize) -> Option<&str> {
+    record.get(index).map(|value| {
+        value
+            .strip_prefix('"')
+            .and_then(|value| value.strip_suffix('"'))
+            .map(str::trim)
+            .unwrap_or(value)
+    })
+}
+
+/// Reads recipients from a CSV with a header row, the address in the first
+/// column, an optional amount in whole tokens in the second and an optional
+/// ATA owner in the third. If `amount` is given it is used for every row and
+/// the amount column is ignored. Labels are read from `label_column` if given.
+/// With `decimals_column`, a row that fills it in has its amount converted
+/// with those decimals instead of `decimals`, with a warning when they
+/// differ. A path ending in `.gz` is decompressed as it is read.
+pub fn read_recipients_csv(
+    path: &str,
+    decimals: u8,
+    amount: Option<u64>,
+    label_column: Option<&LabelColumn>,
+    decimals_column: Option<&LabelColumn>,
+) -> Result<Vec<Recipient>, Box<dyn Error>> {
+    let mut reader = open_csv(path)?;
+    let mut recipients: Vec<Recipient> = Vec::new();
+
+    let label_index = column_index(&mut reader, path, label_column)?;
+    let decimals_index = column_index(&mut reader, path, decimals_column)?;
+    let batch_id_index = reader
+        .headers()?
+        .iter()
+        .position(|header| header == BATCH_ID_HEADER);
+    // Rows per decimals value that differs from the token's.
+    let mut mismatched: BTreeMap<u8, usize> = BTreeMap::new();
+
+    for (index, result) in reader.records().enumerate() {
+        let record = result?;
+        // Row 1 is the header.
+        let row = index + 2;
+        let Some(address) = field(&record, 0) else {
+            continue;
+        };
+        let pubkey = Pubkey::from_str(address)
+            .map_err(|_| format!("Invalid address {} on row {}", address, row))?;
+
+        let amount = match amount {
+            Some(amount) => amount,
+            None => {
+                let value = field(&record, 1)
+                    .filter(|value| !value.is_empty())
+                    .ok_or_else(|| format!("Missing amount on row {} and no AMOUNT given", row))?;
+                let row_decimals = decimals_index
+                    .and_then(|index| field(&record, index))
+                    .filter(|value| !value.is_empty())
+                    .map(|value| {
+                        value
+                            .parse::<u8>()
+                            .map_err(|_| format!("Invalid decimals {} on row {}", value, row))
+                    })
+                    .transpose()?
+                    .unwrap_or(decimals);
+                if row_decimals != decimals {
+                    *mismatched.entry(row_decimals).or_default() += 1;
+                }
+                ui_amount_to_base_units(value, row_decimals)
+                    .map_err(|err| format!("{} on row {}", err, row))?
+            }
+        };
+
+        let ata_owner = field(&record, 2)
+            .filter(|value| !value.is_empty())
+            .map(|value| {
+                Pubkey::from_str(value)
+                    .map_err(|_| format!("Invalid ATA owner {} on row {}", value, row))
+            })
+            .transpose()?;
+
+        let label = label_index
+            .and_then(|index| field(&record, index))
+            .filter(|value| !value.is_empty())
+            .map(str::to_string);
+        let batch_id = batch_id_index
+            .and_then(|index| field(&record, index))
+            .filter(|value| !value.is_empty())
+            .map(str::to_string);
+
+        recipients.push(Recipient {
+            pubkey,
+            amount,
+            ata_owner,
+            label,
+            batch_id,
+        });
+    }
+
+    for (row_decimals, rows) in mismatched.iter() {
+        progress!(
+            "{} row(s) of {} give {} decimals but the token has {}; their amounts are converted with {} ⚠️",
+            rows,
+            path,
+            row_decimals,
+            decimals,
+            row_decimals
+        );
+    }
+
+    Ok(recipients)
+}
+
+/// The index of `column` in the CSV's header, if one was asked for.
+fn column_index(
+    reader: &mut Reader<Box<dyn Read>>,
+    path: &str,
+    column: Option<&LabelColumn>,
+) -> Result<Option<usize>, Box<dyn Error>> {
+    Ok(match column {
+        None => None,
+        Some(LabelColumn::Index(index)) => Some(*index),
+        Some(LabelColumn::Name(name)) => Some(
+            reader
+                .headers()?
+                .iter()
+                .position(|header| header == name)
+                .ok_or_else(|| format!("{} has no {} column", path, name))?,
+        ),
+    })
+}
+
+/// Whether any row of the CSV at `path` fills in the amount column.
+pub fn has_csv_amounts(path: &str) -> Result<bool, Box<dyn Error>> {
+    for result in open_csv(path)?.records() {
+        if field(&result?, 1).is_some_and(|value| !value.is_empty()) {
+            return Ok(true);
+        }
+    }
+    Ok(false)
+}
+
+/// Expands each pattern that contains glob metacharacters into the files it
+/// matches, in sorted order, and keeps plain paths as they are. A pattern that
+/// matches nothing is an error rather than silently contributing no
+/// recipients.
+pub fn expand_recipient_paths(patterns: &[&str]) -> Result<Vec<String>, Box<dyn Error>> {
+    let mut paths: Vec<String> = Vec::new();
+    for pattern in patterns {
+        if !pattern.contains(['*', '?', '[']) {
+            paths.push(pattern.to_string());
+            continue;
+        }
+        let mut matches: Vec<String> = glob::glob(pattern)
+            .map_err(|err| format!("Invalid recipients pattern {}: {}", pattern, err))?
+            .map(|entry| entry.map(|path| path.display().to_string()))
+            .collect::<Result<_, _>>()?;
+        if matches.is_empty() {
+            return Err(format!("No files match {}", pattern).into());
+        }
+        matches.sort();
+        paths.extend(matches);
+    }
+
+    let mut seen = HashSet::new();
+    paths.retain(|path| seen.insert(path.clone()));
+    Ok(paths)
+}
+
+/// Reads several recipient CSVs as one list. Every file must have the same
+/// header as the first. A recipient listed more than once is kept at its first
+/// occurrence, and a warning is printed if the duplicates disagree on the
+/// amount.
+pub fn read_recipients_csvs(
+    paths: &[String],
+    decimals: u8,
+    amount: Option<u64>,
+    label_column: Option<&LabelColumn>,
+    decimals_column: Option<&LabelColumn>,
+) -> Result<Vec<Recipient>, Box<dyn Error>> {
+    let Some(first) = paths.first() else {
+        return Ok(Vec::new());
+    };
+    let header = open_csv(first)?.headers()?.clone();
+
+    let mut recipients: Vec<Recipient> = Vec::new();
+    let mut positions: HashMap<Pubkey, usize> = HashMap::new();
+    let mut duplicates = 0;
+    for path in paths {
+        if path != first {
+            let other = open_csv(path)?.headers()?.clone();
+            if other != header {
+                return Err(format!(
+                    "{} has header {:?}, but {} has {:?}; all recipient files must share the same columns",
+                    path,
+                    other.iter().collect::<Vec<_>>(),
+                    first,
+                    header.iter().collect::<Vec<_>>()
+                )
+                .into());
+            }
+        }
+
+        for recipient in read_recipients_csv(path, decimals, amount, label_column, decimals_column)?
+        {
+            match positions.get(&recipient.pubkey) {
+                Some(&position) => {
+                    duplicates += 1;
+                    if recipients[position].amount != recipient.amount {
+                        progress!(
+                            "{} is listed more than once with different amounts, keeping the first ⚠️",
+                            recipient.pubkey
+                        );
+                    }
+                }
+                None => {
+                    positions.insert(recipient.pubkey, recipients.len());
+                    recipients.push(recipient);
+                }
+            }
+        }
+    }
+
+    if paths.len() > 1 {
+        progress!(
+            "Read {} recipient(s) from {} file(s)",
+            recipients.len(),
+            paths.len()
+        );
+    }
+    if duplicates > 0 {
+        progress!("Dropped {} duplicate recipient(s)", duplicates);
+    }
+
+    Ok(recipients)
+}
+
+#[cfg(test)]
+mod tests {
+    use {
+        super::*,
+        std::io::Write,
+        tempfile::{Builder, NamedTempFile},
+    };
+
+    /// A recipients CSV holding `contents`, removed when dropped.
+    fn csv_file(contents: &[u8]) -> NamedTempFile {
+        let mut file = Builder::new().suffix(".csv").tempfile().unwrap();
+        file.write_all(contents).unwrap();
+        file
+    }
+
+    fn read(file: &NamedTempFile, decimals: u8) -> Vec<Recipient> {
+        read_recipients_csv(file.path().to_str().unwrap(), decimals, None, None, None).unwrap()
+    }
+
+    #[test]
+    fn keeps_large_base_unit_amounts_exact() {
+        let address = Pubkey::new_unique();
+        let file = csv_file(format!("address,amount\n{},18446744073000000\n", address).as_bytes());
+        let recipients = read(&file, 0);
+        assert_eq!(recipients.len(), 1);
+        assert_eq!(recipients[0].pubkey, address);
+        assert_eq!(recipients[0].amount, 18_446_744_073_000_000);
+    }
+
+    #[test]
+    fn reads_a_bom_prefixed_fully_quoted_export() {
+        let (first, second) = (Pubkey::new_unique(), Pubkey::new_unique());
+        let mut contents = UTF8_BOM.to_vec();
+        contents.extend_from_slice(
+            format!(
+                "\"address\",\"amount\"\r\n\"{}\",\"1.5\"\r\n\"{}\",\"2\"\r\n",
+                first, second
+            )
+            .as_bytes(),
+        );
+        let recipients = read(&csv_file(&contents), 2);
+        assert_eq!(
+            recipients
+                .iter()
+                .map(|recipient| (recipient.pubkey, recipient.amount))
+                .collect::<Vec<_>>(),
+            [(first, 150), (second, 200)]
+        );
+    }
+
+    #[test]
+    fn reads_a_quoted_field_padded_with_spaces() {
+        let address = Pubkey::new_unique();
+        let file = csv_file(format!("address,amount\n  \"{}\"  ,  \"3\"  \n", address).as_bytes());
+        let recipients = read(&file, 0);
+        assert_eq!(recipients.len(), 1);
+        assert_eq!(recipients[0].pubkey, address);
+        assert_eq!(recipients[0].amount, 3);
+    }
+}
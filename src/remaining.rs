@@ -0,0 +1,268 @@
+use {
+    crate::{
+        amount::base_units_to_ui_amount,
+        recipients::{
+            is_gzip, read_recipients_csv, LabelColumn, Recipient, BATCH_ID_HEADER, LABEL_HEADER,
+        },
+    },
+    csv::{StringRecord, Writer},
+    flate2::{write::GzEncoder, Compression},
+    serde::{Deserialize, Serialize},
+    solana_sdk::pubkey::Pubkey,
+    std::{
+        collections::{BTreeMap, HashSet},
+        error::Error,
+        fs::{self, File, OpenOptions},
+        path::Path,
+        process,
+    },
+};
+
+pub const REMAINING_RECIPIENTS_PATH: &str = "remaining_recipients.csv";
+pub const PERMANENTLY_FAILED_PATH: &str = "permanently_failed.csv";
+pub const RECIPIENT_ATTEMPTS_PATH: &str = "recipient_attempts.json";
+pub const FAILURES_PATH: &str = "failures.csv";
+
+/// Columns of the remaining and permanently failed CSVs. `airdrop` reads the
+/// first three; the rest are for cross-referencing.
+pub const RECIPIENT_HEADER: [&str; 5] = [
+    "address",
+    "amount",
+    "ata_owner",
+    LABEL_HEADER,
+    BATCH_ID_HEADER,
+];
+
+/// Failed-attempt counts per recipient, persisted across runs so a recipient
+/// that can never succeed eventually stops being retried.
+#[derive(Default, Serialize, Deserialize)]
+#[serde(transparent)]
+pub struct RecipientAttempts {
+    attempts: BTreeMap<String, u32>,
+}
+
+impl RecipientAttempts {
+    pub fn load(path: &str) -> Result<Self, Box<dyn Error>> {
+        if !Path::new(path).exists() {
+            return Ok(Self::default());
+        }
+        let file = File::open(path)?;
+        serde_json::from_reader(file).map_err(|err| format!("Invalid {}: {}", path, err).into())
+    }
+
+    pub fn save(&self, path: &str) -> Result<(), Box<dyn Error>> {
+        // Don't leave a state file behind for runs that never failed.
+        if self.attempts.is_empty() && !Path::new(path).exists() {
+            return Ok(());
+        }
+        serde_json::to_writer_pretty(File::create(path)?, self)?;
+        Ok(())
+    }
+
+    /// Records a failed attempt and returns the new total for the recipient.
+    pub fn record_failure(&mut self, recipient: &Pubkey) -> u32 {
+        let count = self.attempts.entry(recipient.to_string()).or_default();
+        *count += 1;
+        *count
+    }
+
+    pub fn clear(&mut self, recipient: &Pubkey) {
+        self.attempts.remove(&recipient.to_string());
+    }
+}
+
+/// Writes recipients in the same format `airdrop` reads, so the file can be
+/// passed straight back in to resume. A path ending in `.gz` is written
+/// gzipped.
+///
+/// The file is always either the old complete version or the new one: the
+/// rows go to a temporary file in the same directory, which is flushed to
+/// disk and then renamed over `path`. If anything fails on the way, the
+/// temporary file is removed and `path` is left as it was.
+pub fn write_remaining_csv(
+    path: &str,
+    recipients: &[Recipient],
+    decimals: u8,
+) -> Result<(), Box<dyn Error>> {
+    let rows: Vec<StringRecord> = recipients
+        .iter()
+        .map(|recipient| recipient_record(recipient, decimals))
+        .collect();
+    write_csv_atomically(path, &StringRecord::from(RECIPIENT_HEADER.to_vec()), &rows)
+}
+
+/// Writes `header` and `rows` to the CSV at `path` the way
+/// [`write_remaining_csv`] does: gzipped for `.gz`, and never half-written.
+pub fn write_csv_atomically(
+    path: &str,
+    header: &StringRecord,
+    rows: &[StringRecord],
+) -> Result<(), Box<dyn Error>> {
+    let target = Path::new(path);
+    let file_name = target
+        .file_name()
+        .ok_or_else(|| format!("{} is not a file path", path))?;
+    // The process id keeps two runs sharing a directory from writing the
+    // same temporary file.
+    let temp_path = target.with_file_name(format!(
+        ".{}.{}.tmp",
+        file_name.to_string_lossy(),
+        process::id()
+    ));
+
+    let result = write_csv_file(&temp_path, is_gzip(path), header, rows)
+        .and_then(|()| Ok(fs::rename(&temp_path, target)?));
+    if let Err(err) = result {
+        let _ = fs::remove_file(&temp_path);
+        return Err(format!("Could not write {}: {}", path, err).into());
+    }
+    sync_parent_dir(target);
+    Ok(())
+}
+
+/// Writes the CSV to `path` and waits for it to reach the disk, so the rename
+/// that follows can never expose a file whose contents were lost.
+fn write_csv_file(
+    path: &Path,
+    gzip: bool,
+    header: &StringRecord,
+    rows: &[StringRecord],
+) -> Result<(), Box<dyn Error>> {
+    let file = File::create(path)?;
+    let file = if gzip {
+        let mut writer = Writer::from_writer(GzEncoder::new(file, Compression::default()));
+        write_rows(&mut writer, header, rows)?;
+        writer
+            .into_inner()
+            .map_err(|err| err.into_error())?
+            .finish()?
+    } else {
+        let mut writer = Writer::from_writer(file);
+        write_rows(&mut writer, header, rows)?;
+        writer.into_inner().map_err(|err| err.into_error())?
+    };
+    file.sync_all()?;
+    Ok(())
+}
+
+/// Makes a rename within `path`'s directory durable. Best effort: not every
+/// platform can open a directory to sync it, and the rename itself has
+/// already succeeded.
+fn sync_parent_dir(path: &Path) {
+    let dir = match path.parent() {
+        Some(dir) if !dir.as_os_str().is_empty() => dir,
+        _ => Path::new("."),
+    };
+    if let Ok(dir) = File::open(dir) {
+        let _ = dir.sync_all();
+    }
+}
+
+fn write_rows<W: std::io::Write>(
+    writer: &mut Writer<W>,
+    header: &StringRecord,
+    rows: &[StringRecord],
+) -> Result<(), Box<dyn Error>> {
+    writer.write_record(header)?;
+    for row in rows.iter() {
+        writer.write_record(row)?;
+    }
+    Ok(())
+}
+
+/// Merges `recipients` into the remaining CSV at `path` instead of replacing
+/// it. Existing rows for anyone in `superseded` are dropped, since this run has
+/// either paid them or is re-adding them, so a row is never duplicated and
+/// the latest amount wins. Returns the number of rows now in the file.
+pub fn merge_remaining_csv(
+    path: &str,
+    recipients: &[Recipient],
+    superseded: &HashSet<Pubkey>,
+    decimals: u8,
+) -> Result<usize, Box<dyn Error>> {
+    let mut merged: Vec<Recipient> = if Path::new(path).exists() {
+        read_recipients_csv(
+            path,
+            decimals,
+            None,
+            Some(&LabelColumn::Name(LABEL_HEADER.to_string())),
+            None,
+        )?
+    } else {
+        Vec::new()
+    };
+
+    let added: HashSet<Pubkey> = recipients
+        .iter()
+        .map(|recipient| recipient.pubkey)
+        .collect();
+    merged.retain(|recipient| {
+        !superseded.contains(&recipient.pubkey) && !added.contains(&recipient.pubkey)
+    });
+    merged.extend_from_slice(recipients);
+
+    write_remaining_csv(path, &merged, decimals)?;
+    Ok(merged.len())
+}
+
+pub fn append_permanently_failed(
+    path: &str,
+    recipients: &[Recipient],
+    decimals: u8,
+) -> Result<(), Box<dyn Error>> {
+    let is_new = !Path::new(path).exists();
+    let file = OpenOptions::new().create(true).append(true).open(path)?;
+    let mut writer = Writer::from_writer(file);
+    if is_new {
+        writer.write_record(RECIPIENT_HEADER)?;
+    }
+    for recipient in recipients.iter() {
+        writer.write_record(&recipient_record(recipient, decimals))?;
+    }
+    writer.flush()?;
+    Ok(())
+}
+
+/// Writes one row per failed recipient, with the signature of its
+/// transaction and the error it failed with, replacing the file.
+pub fn write_failures_csv<'a>(
+    path: &str,
+    failures: impl Iterator<Item = (&'a Recipient, String, &'a str)>,
+    decimals: u8,
+) -> Result<(), Box<dyn Error>> {
+    let mut writer = Writer::from_path(path)?;
+    writer.write_record([
+        "address",
+        "amount",
+        LABEL_HEADER,
+        BATCH_ID_HEADER,
+        "signature",
+        "error",
+    ])?;
+    for (recipient, signature, error) in failures {
+        writer.write_record([
+            recipient.pubkey.to_string(),
+            base_units_to_ui_amount(recipient.amount, decimals),
+            recipient.label.clone().unwrap_or_default(),
+            recipient.batch_id.clone().unwrap_or_default(),
+            signature,
+            error.to_string(),
+        ])?;
+    }
+    writer.flush()?;
+    Ok(())
+}
+
+/// A recipient as a row under `RECIPIENT_HEADER`.
+fn recipient_record(recipient: &Recipient, decimals: u8) -> StringRecord {
+    StringRecord::from(vec![
+        recipient.pubkey.to_string(),
+        base_units_to_ui_amount(recipient.amount, decimals),
+        recipient
+            .ata_owner
+            .map(|owner| owner.to_string())
+            .unwrap_or_default(),
+        recipient.label.clone().unwrap_or_default(),
+        recipient.batch_id.clone().unwrap_or_default(),
+    ])
+}
@@ -0,0 +1,131 @@
+use {
+    crate::{
+        recipients::open_input,
+        remaining::{write_csv_atomically, RECIPIENT_HEADER},
+        RemoveArgs, RequeueArgs,
+    },
+    csv::{ReaderBuilder, StringRecord},
+    solana_sdk::pubkey::Pubkey,
+    std::{error::Error, path::Path, str::FromStr},
+};
+
+/// A recipients CSV read as it is, so it can be written back with its header
+/// and every untouched row exactly as they were.
+struct RecipientsFile {
+    header: StringRecord,
+    rows: Vec<StringRecord>,
+}
+
+impl RecipientsFile {
+    fn read(path: &str) -> Result<Self, Box<dyn Error>> {
+        let mut reader = ReaderBuilder::new()
+            .flexible(true)
+            .from_reader(open_input(path)?);
+        let header = reader.headers()?.clone();
+        let rows = reader
+            .records()
+            .collect::<Result<_, _>>()
+            .map_err(|err| format!("Could not read {}: {}", path, err))?;
+        Ok(Self { header, rows })
+    }
+
+    /// Removes and returns the rows for `address`.
+    fn take(&mut self, address: &Pubkey) -> Vec<StringRecord> {
+        let (taken, kept) = std::mem::take(&mut self.rows)
+            .into_iter()
+            .partition(|row| row_address(row) == Some(*address));
+        self.rows = kept;
+        taken
+    }
+
+    /// Adds `row`, whose columns are named by `header`, rearranged into this
+    /// file's columns. Columns this file doesn't have are dropped, and those
+    /// `row` doesn't have are left empty.
+    fn add(&mut self, header: &StringRecord, row: &StringRecord) {
+        let fields: Vec<&str> = self
+            .header
+            .iter()
+            .map(|name| {
+                header
+                    .iter()
+                    .position(|column| column == name)
+                    .and_then(|index| row.get(index))
+                    .unwrap_or_default()
+            })
+            .collect();
+        self.rows.push(StringRecord::from(fields));
+    }
+
+    fn write(&self, path: &str) -> Result<(), Box<dyn Error>> {
+        write_csv_atomically(path, &self.header, &self.rows)
+    }
+}
+
+/// The address in the first column of `row`, if it holds one.
+fn row_address(row: &StringRecord) -> Option<Pubkey> {
+    row.get(0)
+        .and_then(|address| Pubkey::from_str(address.trim().trim_matches('"')).ok())
+}
+
+/// Drops a recipient from a remaining CSV, so `retry-failed` stops trying to
+/// pay them.
+pub fn remove_recipient(args: &RemoveArgs) -> Result<(), Box<dyn Error>> {
+    let mut file = RecipientsFile::read(&args.from)?;
+    let removed = file.take(&args.address);
+    if removed.is_empty() {
+        return Err(format!("{} is not in {}", args.address, args.from).into());
+    }
+    file.write(&args.from)?;
+    println!(
+        "Removed {} row(s) for {} from {} ({} row(s) left)",
+        removed.len(),
+        args.address,
+        args.from,
+        file.rows.len()
+    );
+    Ok(())
+}
+
+/// Moves a recipient from a failures CSV back into a remaining CSV for
+/// another attempt. An existing row for them in the target is replaced, so
+/// they are never listed twice. The target is written first: if that fails,
+/// the recipient is still in the source.
+pub fn requeue_recipient(args: &RequeueArgs) -> Result<(), Box<dyn Error>> {
+    if Path::new(&args.from) == Path::new(&args.to) {
+        return Err(format!("--from and --to are both {}", args.from).into());
+    }
+    let mut source = RecipientsFile::read(&args.from)?;
+    let moved = source.take(&args.address);
+    if moved.is_empty() {
+        return Err(format!("{} is not in {}", args.address, args.from).into());
+    }
+
+    let mut target = if Path::new(&args.to).exists() {
+        RecipientsFile::read(&args.to)?
+    } else {
+        RecipientsFile {
+            header: StringRecord::from(RECIPIENT_HEADER.to_vec()),
+            rows: Vec::new(),
+        }
+    };
+    let replaced = target.take(&args.address).len();
+    for row in moved.iter() {
+        target.add(&source.header, row);
+    }
+    target.write(&args.to)?;
+    source.write(&args.from)?;
+
+    println!(
+        "Moved {} row(s) for {} from {} to {}{}",
+        moved.len(),
+        args.address,
+        args.from,
+        args.to,
+        if replaced > 0 {
+            format!(", replacing {} row(s) already there", replaced)
+        } else {
+            String::new()
+        }
+    );
+    Ok(())
+}
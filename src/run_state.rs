@@ -0,0 +1,75 @@
+use {
+    csv::{Reader, Writer},
+    solana_sdk::{pubkey::Pubkey, signature::Signature},
+    std::{collections::HashSet, error::Error, fs::OpenOptions, path::Path, str::FromStr},
+};
+
+/// The recipients a `--run-id` run has confirmed, kept in an append-only CSV
+/// so a run killed mid-batch loses nothing that already landed, and so a
+/// restart with the same run id never pays anyone twice.
+pub struct RunState {
+    path: String,
+    confirmed: HashSet<Pubkey>,
+}
+
+impl RunState {
+    /// The default state file for `run_id`.
+    pub fn default_path(run_id: &str) -> String {
+        format!("run_{}.csv", run_id)
+    }
+
+    pub fn load(path: &str) -> Result<Self, Box<dyn Error>> {
+        let mut confirmed = HashSet::new();
+        if Path::new(path).exists() {
+            for (index, result) in Reader::from_path(path)?.records().enumerate() {
+                let record = result?;
+                let address = record.get(0).unwrap_or_default();
+                let pubkey = Pubkey::from_str(address).map_err(|_| {
+                    format!(
+                        "Invalid address {} on row {} of {}",
+                        address,
+                        index + 2,
+                        path
+                    )
+                })?;
+                confirmed.insert(pubkey);
+            }
+        }
+        Ok(Self {
+            path: path.to_string(),
+            confirmed,
+        })
+    }
+
+    pub fn confirmed_count(&self) -> usize {
+        self.confirmed.len()
+    }
+
+    pub fn is_confirmed(&self, recipient: &Pubkey) -> bool {
+        self.confirmed.contains(recipient)
+    }
+
+    /// Appends the recipients of a confirmed transaction, flushing straight
+    /// away so the record survives the process being killed.
+    pub fn record<'a>(
+        &mut self,
+        signature: &Signature,
+        recipients: impl IntoIterator<Item = &'a Pubkey>,
+    ) -> Result<(), Box<dyn Error>> {
+        let is_new = !Path::new(&self.path).exists();
+        let file = OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(&self.path)?;
+        let mut writer = Writer::from_writer(file);
+        if is_new {
+            writer.write_record(["address", "signature"])?;
+        }
+        for recipient in recipients {
+            writer.write_record([recipient.to_string(), signature.to_string()])?;
+            self.confirmed.insert(*recipient);
+        }
+        writer.flush()?;
+        Ok(())
+    }
+}
@@ -0,0 +1,66 @@
+use {
+    crate::jito::JitoSender,
+    solana_client::{
+        client_error::ClientError, nonblocking::rpc_client::RpcClient,
+        rpc_config::RpcSendTransactionConfig,
+    },
+    solana_sdk::{hash::Hash, signature::Signature, transaction::Transaction},
+};
+
+/// What building and sending batches needs from the network. Retry and
+/// backoff logic is written against this rather than `RpcClient`, so it can be
+/// pointed at a custom sender, e.g. a relay or a stub.
+pub trait TransactionSender {
+    async fn send(
+        &self,
+        transaction: &Transaction,
+        config: RpcSendTransactionConfig,
+    ) -> Result<Signature, ClientError>;
+
+    async fn get_latest_blockhash(&self) -> Result<Hash, ClientError>;
+}
+
+impl TransactionSender for RpcClient {
+    async fn send(
+        &self,
+        transaction: &Transaction,
+        config: RpcSendTransactionConfig,
+    ) -> Result<Signature, ClientError> {
+        self.send_transaction_with_config(transaction, config).await
+    }
+
+    async fn get_latest_blockhash(&self) -> Result<Hash, ClientError> {
+        RpcClient::get_latest_blockhash(self).await
+    }
+}
+
+/// Where broadcasts go: the RPC by default, or Jito's block engine as bundles
+/// with `--jito-url`.
+pub enum Broadcaster<'a> {
+    Rpc(&'a RpcClient),
+    Jito(JitoSender<'a>),
+}
+
+impl TransactionSender for Broadcaster<'_> {
+    async fn send(
+        &self,
+        transaction: &Transaction,
+        config: RpcSendTransactionConfig,
+    ) -> Result<Signature, ClientError> {
+        match self {
+            Broadcaster::Rpc(rpc_client) => {
+                TransactionSender::send(*rpc_client, transaction, config).await
+            }
+            Broadcaster::Jito(jito) => jito.send(transaction, config).await,
+        }
+    }
+
+    async fn get_latest_blockhash(&self) -> Result<Hash, ClientError> {
+        match self {
+            Broadcaster::Rpc(rpc_client) => {
+                TransactionSender::get_latest_blockhash(*rpc_client).await
+            }
+            Broadcaster::Jito(jito) => jito.get_latest_blockhash().await,
+        }
+    }
+}
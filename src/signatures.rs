@@ -0,0 +1,259 @@
+use {
+    crate::{
+        amount::{base_units_to_ui_amount, ui_amount_to_base_units},
+        recipients::Recipient,
+        remaining::write_csv_atomically,
+        MAX_SIGNATURE_STATUSES_PER_REQUEST,
+    },
+    csv::{ReaderBuilder, StringRecord, Writer},
+    solana_client::nonblocking::rpc_client::RpcClient,
+    solana_sdk::{commitment_config::CommitmentConfig, pubkey::Pubkey, signature::Signature},
+    std::{collections::HashMap, error::Error, fs::OpenOptions, path::Path, str::FromStr},
+};
+
+pub const SIGNATURE_LOG_PATH: &str = "signatures.csv";
+
+/// Columns of the signature log. A row whose transaction was never accepted
+/// by the cluster has an empty `signature` and the reason in `error`; every
+/// other row has an empty `error`.
+const SIGNATURE_LOG_HEADER: [&str; 6] = [
+    "signature",
+    "address",
+    "amount",
+    "label",
+    "batch_id",
+    "error",
+];
+
+/// Appends one row per recipient paid by a confirmed transaction, so each
+/// on-chain signature can be traced back to its batch, who it paid and their
+/// label.
+pub fn append_signature_log<'a>(
+    path: &str,
+    signature: &Signature,
+    batch_id: &str,
+    transfers: impl IntoIterator<Item = (&'a Recipient, u64)>,
+    decimals: u8,
+) -> Result<(), Box<dyn Error>> {
+    append_rows(
+        path,
+        &signature.to_string(),
+        batch_id,
+        transfers,
+        "",
+        decimals,
+    )
+}
+
+/// Appends one row per recipient of a transaction that failed to send, with
+/// no signature and the error the send failed with, so the log also accounts
+/// for the batches that never reached the cluster.
+pub fn append_send_failure_log<'a>(
+    path: &str,
+    batch_id: &str,
+    transfers: impl IntoIterator<Item = (&'a Recipient, u64)>,
+    error: &str,
+    decimals: u8,
+) -> Result<(), Box<dyn Error>> {
+    append_rows(path, "", batch_id, transfers, error, decimals)
+}
+
+fn append_rows<'a>(
+    path: &str,
+    signature: &str,
+    batch_id: &str,
+    transfers: impl IntoIterator<Item = (&'a Recipient, u64)>,
+    error: &str,
+    decimals: u8,
+) -> Result<(), Box<dyn Error>> {
+    let is_new = !Path::new(path).exists();
+    if !is_new {
+        add_error_column(path)?;
+    }
+    let file = OpenOptions::new().create(true).append(true).open(path)?;
+    let mut writer = Writer::from_writer(file);
+    if is_new {
+        writer.write_record(SIGNATURE_LOG_HEADER)?;
+    }
+    for (recipient, amount) in transfers {
+        writer.write_record([
+            signature,
+            &recipient.pubkey.to_string(),
+            &base_units_to_ui_amount(amount, decimals),
+            recipient.label.as_deref().unwrap_or_default(),
+            batch_id,
+            error,
+        ])?;
+    }
+    writer.flush()?;
+    Ok(())
+}
+
+/// Rewrites a log from before the `error` column existed with an empty one,
+/// so the rows appended to it line up with its header.
+fn add_error_column(path: &str) -> Result<(), Box<dyn Error>> {
+    let mut reader = ReaderBuilder::new().flexible(true).from_path(path)?;
+    let header = reader.headers()?.clone();
+    if header.len() != SIGNATURE_LOG_HEADER.len() - 1 {
+        return Ok(());
+    }
+    let rows = reader
+        .records()
+        .map(|row| {
+            row.map(|mut row| {
+                row.push_field("");
+                row
+            })
+        })
+        .collect::<Result<Vec<StringRecord>, _>>()
+        .map_err(|err| format!("Could not read {}: {}", path, err))?;
+    write_csv_atomically(
+        path,
+        &StringRecord::from(SIGNATURE_LOG_HEADER.to_vec()),
+        &rows,
+    )
+}
+
+/// The recipients a transaction paid and how much, in base units.
+type LoggedTransfers = Vec<(Pubkey, u64)>;
+
+/// The transfers in a signature log, grouped by the transaction that made
+/// them. Rows without a signature were never sent and are left out.
+fn read_signature_log(
+    path: &str,
+    decimals: u8,
+) -> Result<HashMap<Signature, LoggedTransfers>, Box<dyn Error>> {
+    let mut reader = ReaderBuilder::new()
+        .flexible(true)
+        .from_path(path)
+        .map_err(|err| format!("Could not read {}: {}", path, err))?;
+    let header = reader.headers()?.clone();
+    let column = |name: &str| {
+        header
+            .iter()
+            .position(|column| column == name)
+            .ok_or_else(|| format!("{} has no {} column; is it a signatures CSV?", path, name))
+    };
+    let (signature_column, address_column, amount_column) =
+        (column("signature")?, column("address")?, column("amount")?);
+
+    let mut transfers: HashMap<Signature, LoggedTransfers> = HashMap::new();
+    for (index, row) in reader.records().enumerate() {
+        let row = row?;
+        // Row 1 is the header.
+        let invalid = |what: &str| format!("{} row {}: invalid {}", path, index + 2, what);
+        let signature = row.get(signature_column).unwrap_or_default();
+        if signature.is_empty() {
+            continue;
+        }
+        let signature = Signature::from_str(signature).map_err(|_| invalid("signature"))?;
+        let address = row
+            .get(address_column)
+            .and_then(|address| Pubkey::from_str(address).ok())
+            .ok_or_else(|| invalid("address"))?;
+        let amount = ui_amount_to_base_units(row.get(amount_column).unwrap_or_default(), decimals)
+            .map_err(|err| format!("{}: {}", invalid("amount"), err))?;
+        transfers
+            .entry(signature)
+            .or_default()
+            .push((address, amount));
+    }
+    Ok(transfers)
+}
+
+/// Checks every transaction in the signature log at `path` and returns how
+/// much its confirmed transactions paid each recipient, in base units.
+/// Transactions that failed, or whose status the cluster no longer knows,
+/// pay nobody.
+pub async fn confirmed_payments(
+    rpc_client: &RpcClient,
+    path: &str,
+    decimals: u8,
+) -> Result<HashMap<Pubkey, u64>, Box<dyn Error>> {
+    let transfers = read_signature_log(path, decimals)?;
+    let signatures: Vec<Signature> = transfers.keys().copied().collect();
+    progress!(
+        "Checking {} transaction(s) from {} 🔍",
+        signatures.len(),
+        path
+    );
+
+    let mut paid: HashMap<Pubkey, u64> = HashMap::new();
+    let (mut confirmed, mut failed, mut unknown) = (0, 0, 0);
+    for chunk in signatures.chunks(MAX_SIGNATURE_STATUSES_PER_REQUEST) {
+        let statuses = rpc_client
+            .get_signature_statuses_with_history(chunk)
+            .await?
+            .value;
+        for (signature, status) in chunk.iter().zip(statuses) {
+            match status {
+                Some(status)
+                    if status.err.is_none()
+                        && status.satisfies_commitment(CommitmentConfig::confirmed()) =>
+                {
+                    confirmed += 1;
+                    for (address, amount) in transfers[signature].iter() {
+                        let total = paid.entry(*address).or_default();
+                        *total = total.saturating_add(*amount);
+                    }
+                }
+                Some(status) if status.err.is_some() => failed += 1,
+                _ => unknown += 1,
+            }
+        }
+    }
+    progress!(
+        "{} of them confirmed, {} failed and {} unknown or not yet confirmed",
+        confirmed,
+        failed,
+        unknown
+    );
+    Ok(paid)
+}
+
+/// Checks every transaction in the signature log at `path`, typically the
+/// `signatures.csv` of the run being resumed, and drops the recipients its
+/// confirmed transactions already paid in full. A recipient paid only part of
+/// what they are owed is sent the rest. Transactions that failed, or whose
+/// status the cluster no longer knows, pay nobody, so their recipients are
+/// sent to again.
+pub async fn skip_prior_payments(
+    rpc_client: &RpcClient,
+    path: &str,
+    recipients: Vec<Recipient>,
+    decimals: u8,
+) -> Result<Vec<Recipient>, Box<dyn Error>> {
+    let paid = confirmed_payments(rpc_client, path, decimals).await?;
+    let mut skipped = 0;
+    let mut topped_up = 0;
+    let mut remaining: Vec<Recipient> = Vec::with_capacity(recipients.len());
+    for mut recipient in recipients {
+        let already_paid = paid.get(&recipient.pubkey).copied().unwrap_or_default();
+        if already_paid >= recipient.amount {
+            skipped += 1;
+            continue;
+        }
+        if already_paid > 0 {
+            progress!(
+                "{} was already paid {} of the {} owed, sending the rest",
+                recipient.pubkey,
+                base_units_to_ui_amount(already_paid, decimals),
+                base_units_to_ui_amount(recipient.amount, decimals)
+            );
+            recipient.amount -= already_paid;
+            topped_up += 1;
+        }
+        remaining.push(recipient);
+    }
+    progress!(
+        "Skipped {} recipient(s) already paid by a confirmed transaction in {}{}",
+        skipped,
+        path,
+        if topped_up > 0 {
+            format!(", and {} partly paid one(s) are sent the rest", topped_up)
+        } else {
+            String::new()
+        }
+    );
+    Ok(remaining)
+}
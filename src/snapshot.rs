@@ -0,0 +1,120 @@
+use {
+    crate::SnapshotArgs,
+    csv::Writer,
+    solana_account_decoder::UiAccountEncoding,
+    solana_client::{
+        nonblocking::rpc_client::RpcClient,
+        rpc_config::{RpcAccountInfoConfig, RpcProgramAccountsConfig},
+        rpc_filter::{Memcmp, RpcFilterType},
+    },
+    solana_sdk::{account::Account, program_pack::Pack, pubkey::Pubkey},
+    spl_token_2022::{
+        extension::StateWithExtensions,
+        state::{Account as TokenAccount, Mint},
+    },
+    std::{collections::BTreeMap, error::Error, str::FromStr},
+};
+
+/// Offset of the owner field within a token account.
+const TOKEN_ACCOUNT_OWNER_OFFSET: usize = 32;
+
+/// Writes every owner holding at least `--min-balance` of the mint to a
+/// recipients CSV that `airdrop` can consume directly.
+pub async fn take_snapshot(
+    args: SnapshotArgs,
+    rpc_client: &RpcClient,
+) -> Result<(), Box<dyn Error>> {
+    let mint = Pubkey::from_str(&args.mint_address)?;
+    let mint_account = rpc_client.get_account(&mint).await?;
+    spl_token_2022::check_spl_token_program_account(&mint_account.owner)
+        .map_err(|_| format!("{} is not owned by a token program", mint))?;
+    StateWithExtensions::<Mint>::unpack(&mint_account.data)
+        .map_err(|_| format!("{} is not a mint account", mint))?;
+    let token_program = mint_account.owner;
+
+    // A single getProgramAccounts call for a large mint can exceed response
+    // limits, so optionally split it into one page per owner prefix byte.
+    let pages: Vec<Option<u8>> = if args.paginate {
+        (0..=u8::MAX).map(Some).collect()
+    } else {
+        vec![None]
+    };
+
+    let mut balances: BTreeMap<Pubkey, u64> = BTreeMap::new();
+    for (page_index, owner_prefix) in pages.iter().enumerate() {
+        let accounts =
+            fetch_token_accounts(rpc_client, &token_program, &mint, *owner_prefix).await?;
+        if args.paginate {
+            progress!(
+                "Fetched page {}/{} ({} accounts)",
+                page_index + 1,
+                pages.len(),
+                accounts.len()
+            );
+        }
+
+        for (_address, account) in accounts {
+            let Ok(state) = StateWithExtensions::<TokenAccount>::unpack(&account.data) else {
+                continue;
+            };
+            let balance = balances.entry(state.base.owner).or_default();
+            *balance = balance.saturating_add(state.base.amount);
+        }
+    }
+
+    let holders: Vec<&Pubkey> = balances
+        .iter()
+        .filter(|(_owner, balance)| **balance > 0 && **balance >= args.min_balance)
+        .map(|(owner, _balance)| owner)
+        .collect();
+
+    let mut writer = Writer::from_path(&args.output_csv_path)?;
+    writer.write_record(["address"])?;
+    for holder in holders.iter() {
+        writer.write_record([holder.to_string()])?;
+    }
+    writer.flush()?;
+
+    println!(
+        "Wrote {} holder(s) of {} to {}",
+        holders.len(),
+        mint,
+        args.output_csv_path
+    );
+
+    Ok(())
+}
+
+async fn fetch_token_accounts(
+    rpc_client: &RpcClient,
+    token_program: &Pubkey,
+    mint: &Pubkey,
+    owner_prefix: Option<u8>,
+) -> Result<Vec<(Pubkey, Account)>, Box<dyn Error>> {
+    let mut filters = vec![RpcFilterType::Memcmp(Memcmp::new_raw_bytes(
+        0,
+        mint.to_bytes().to_vec(),
+    ))];
+    if *token_program == spl_token::id() {
+        filters.push(RpcFilterType::DataSize(TokenAccount::LEN as u64));
+    }
+    if let Some(prefix) = owner_prefix {
+        filters.push(RpcFilterType::Memcmp(Memcmp::new_raw_bytes(
+            TOKEN_ACCOUNT_OWNER_OFFSET,
+            vec![prefix],
+        )));
+    }
+
+    let config = RpcProgramAccountsConfig {
+        filters: Some(filters),
+        account_config: RpcAccountInfoConfig {
+            encoding: Some(UiAccountEncoding::Base64),
+            ..Default::default()
+        },
+        ..Default::default()
+    };
+
+    Ok(rpc_client
+        .get_program_accounts_with_config(token_program, config)
+        .await?)
+}
@@ -0,0 +1,39 @@
+use {
+    solana_sdk::pubkey::Pubkey,
+    std::{fmt, str::FromStr},
+};
+
+/// A token account given with `--source-token-account` to send from. Written
+/// as `ACCOUNT`, signed for by `--keypair`, or `ACCOUNT:KEYPAIR` with the
+/// owner's keypair file or `env:VAR_NAME`.
+#[derive(Clone, Debug, PartialEq)]
+pub struct SourceTokenAccount {
+    pub address: Pubkey,
+    pub authority: Option<String>,
+}
+
+impl FromStr for SourceTokenAccount {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        // Addresses are base58, so the first colon always ends the address,
+        // while the keypair may itself be `env:VAR_NAME`.
+        let (address, authority) = match s.split_once(':') {
+            Some((_address, "")) => return Err(format!("missing keypair after : in {}", s)),
+            Some((address, authority)) => (address, Some(authority.to_string())),
+            None => (s, None),
+        };
+        let address = Pubkey::from_str(address)
+            .map_err(|_| format!("invalid token account address {}", address))?;
+        Ok(SourceTokenAccount { address, authority })
+    }
+}
+
+impl fmt::Display for SourceTokenAccount {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match &self.authority {
+            Some(authority) => write!(f, "{}:{}", self.address, authority),
+            None => write!(f, "{}", self.address),
+        }
+    }
+}
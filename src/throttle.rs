@@ -0,0 +1,92 @@
+use {
+    rand::Rng,
+    std::{fmt, str::FromStr, time::Duration},
+    tokio::{sync::Mutex, time::Instant},
+};
+
+/// A random extra delay to put between broadcasts, drawn uniformly from
+/// `min..=max`. Written `MIN-MAX` in milliseconds.
+#[derive(Clone, Copy, Debug)]
+pub struct SendJitter {
+    min: Duration,
+    max: Duration,
+}
+
+impl SendJitter {
+    fn sample(&self) -> Duration {
+        rand::thread_rng().gen_range(self.min..=self.max)
+    }
+}
+
+impl FromStr for SendJitter {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (min, max) = s
+            .split_once('-')
+            .ok_or_else(|| format!("expected MIN-MAX in milliseconds, found {}", s))?;
+        let parse = |value: &str| {
+            value
+                .trim()
+                .parse::<u64>()
+                .map(Duration::from_millis)
+                .map_err(|_| format!("invalid number of milliseconds {}", value))
+        };
+        let (min, max) = (parse(min)?, parse(max)?);
+        if min > max {
+            return Err(format!(
+                "the minimum {:?} is above the maximum {:?}",
+                min, max
+            ));
+        }
+        Ok(Self { min, max })
+    }
+}
+
+impl fmt::Display for SendJitter {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}-{}", self.min.as_millis(), self.max.as_millis())
+    }
+}
+
+/// Spaces broadcasts at least `1 / max_tps` seconds apart across the whole
+/// run, however many sends are in flight, like a leaky bucket draining at a
+/// fixed rate. With a jitter, each gap also gets a random extra delay, so
+/// sends don't go out at a regular beat. Waiters queue on the lock, so they
+/// go out in turn.
+#[derive(Debug)]
+pub struct SendLimiter {
+    interval: Duration,
+    jitter: Option<SendJitter>,
+    next_send: Mutex<Option<Instant>>,
+}
+
+impl SendLimiter {
+    pub fn new(max_tps: Option<u32>, jitter: Option<SendJitter>) -> Self {
+        Self {
+            interval: max_tps
+                .map(|max_tps| Duration::from_secs(1) / max_tps.max(1))
+                .unwrap_or_default(),
+            jitter,
+            next_send: Mutex::new(None),
+        }
+    }
+
+    /// Waits until the next broadcast slot is free and claims it.
+    pub async fn wait(&self) {
+        let mut next_send = self.next_send.lock().await;
+        let now = Instant::now();
+        let slot = match *next_send {
+            Some(slot) if slot > now => {
+                tokio::time::sleep_until(slot).await;
+                slot
+            }
+            _ => now,
+        };
+        let jitter = self
+            .jitter
+            .map(|jitter| jitter.sample())
+            .unwrap_or_default();
+        *next_send = Some(slot + self.interval + jitter);
+    }
+}
@@ -0,0 +1,43 @@
+use std::time::Duration;
+
+/// Time spent in each phase of building and landing one batch. Phases that
+/// didn't happen for a batch, e.g. confirmation in fire-and-forget mode, are
+/// left empty.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct BatchTimings {
+    pub blockhash: Option<Duration>,
+    pub signing: Option<Duration>,
+    pub send: Option<Duration>,
+    pub confirm: Option<Duration>,
+}
+
+/// Prints min/max/avg/p95 for each phase across all batches.
+pub fn print_timing_summary(timings: &[BatchTimings]) {
+    println!("Timings across {} batch(es):", timings.len());
+    print_phase("blockhash", timings.iter().filter_map(|t| t.blockhash));
+    print_phase("signing", timings.iter().filter_map(|t| t.signing));
+    print_phase("send", timings.iter().filter_map(|t| t.send));
+    print_phase("confirm", timings.iter().filter_map(|t| t.confirm));
+}
+
+fn print_phase(name: &str, samples: impl Iterator<Item = Duration>) {
+    let mut samples: Vec<Duration> = samples.collect();
+    if samples.is_empty() {
+        return;
+    }
+    samples.sort_unstable();
+
+    let total: Duration = samples.iter().sum();
+    let avg = total / samples.len() as u32;
+    // Nearest-rank percentile.
+    let p95_rank = (samples.len() * 95).div_ceil(100).max(1);
+
+    println!(
+        "  {:<9} min {:>9.3?}  max {:>9.3?}  avg {:>9.3?}  p95 {:>9.3?}",
+        name,
+        samples[0],
+        samples[samples.len() - 1],
+        avg,
+        samples[p95_rank - 1]
+    );
+}
@@ -0,0 +1,85 @@
+use {
+    serde_json::Value,
+    std::{sync::Mutex, time::Duration},
+    tokio::task::JoinSet,
+};
+
+/// How long each POST may take.
+const WEBHOOK_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// Attempts after the first before a notification is given up on.
+const WEBHOOK_RETRIES: u32 = 3;
+
+/// Delay before the first retry, doubled for each one after it.
+const WEBHOOK_RETRY_BACKOFF: Duration = Duration::from_secs(1);
+
+/// An endpoint told about every batch as it confirms. Notifications are
+/// best effort: they are sent in the background so they never hold up the
+/// airdrop, and one that still fails after its retries is only logged.
+#[derive(Debug)]
+pub struct Webhook {
+    url: String,
+    client: reqwest::Client,
+    pending: Mutex<JoinSet<()>>,
+}
+
+impl Webhook {
+    pub fn new(url: &str) -> Self {
+        Self {
+            url: url.to_string(),
+            client: reqwest::Client::new(),
+            pending: Mutex::new(JoinSet::new()),
+        }
+    }
+
+    /// POSTs `body` as JSON in the background, retrying failed attempts
+    /// with exponential backoff.
+    pub fn notify(&self, body: Value) {
+        let url = self.url.clone();
+        let client = self.client.clone();
+        self.pending.lock().unwrap().spawn(async move {
+            let mut attempt = 0;
+            loop {
+                let result = client
+                    .post(&url)
+                    .timeout(WEBHOOK_TIMEOUT)
+                    .json(&body)
+                    .send()
+                    .await
+                    .and_then(|response| response.error_for_status());
+                match result {
+                    Ok(_) => return,
+                    Err(err) if attempt < WEBHOOK_RETRIES => {
+                        let backoff = WEBHOOK_RETRY_BACKOFF * 2u32.saturating_pow(attempt);
+                        attempt += 1;
+                        progress!(
+                            "Webhook {} failed, retrying in {:?} ({}/{}): {}",
+                            url,
+                            backoff,
+                            attempt,
+                            WEBHOOK_RETRIES,
+                            err
+                        );
+                        tokio::time::sleep(backoff).await;
+                    }
+                    Err(err) => {
+                        progress!(
+                            "Webhook {} failed, giving up on batch {} ⚠️: {}",
+                            url,
+                            body["batch_id"],
+                            err
+                        );
+                        return;
+                    }
+                }
+            }
+        });
+    }
+
+    /// Waits for every notification still being sent, so the last batches
+    /// are reported before the process exits.
+    pub async fn finish(&self) {
+        let mut pending = std::mem::take(&mut *self.pending.lock().unwrap());
+        while pending.join_next().await.is_some() {}
+    }
+}